@@ -0,0 +1,349 @@
+// ウィンドウを開かずに.scrfファイルを処理するコマンドライン
+
+use story_composer::export::epub::{self, EpubOptions};
+use story_composer::export::pdf::{self, PdfOptions, LAYOUTS};
+use story_composer::export::site::{self, Site};
+use story_composer::export::{self, docx, odt, ExportFormat, LineEnding, TextEncoding, TextOptions};
+use story_composer::format;
+use story_composer::{Document, PlotKind, PlotStatus, SaveData, FORMAT_VERSION, MAX_PLOTS};
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const COMMANDS: &[&str] = &["compose", "export", "stats", "validate", "help", "--help", "-h"];
+
+const USAGE: &str = "\
+使い方: StoryComposer2 <コマンド> [オプション]
+
+コマンド:
+  compose <file.scrf> [-o <出力>]                       プロットを繋げた文書を出力する
+  export <file.scrf> --format txt|md|html [-o <出力>]   出力テキストを指定の形式で書き出す
+         [--encoding utf8|utf8bom|sjis] [--newline crlf|lf]
+                                                        txtの文字コードと改行（既定はutf8・crlf）
+  export <file.scrf> --format docx|odt -o <出力>        Word・OpenDocument形式で書き出す
+  export <file.scrf> --format epub -o <出力> [--vertical] [--cover <画像>]
+                                                        EPUBとして書き出す
+  export <file.scrf> --format pdf -o <出力> --font <フォント> [--font-index <番号>]
+         [--layout <字数>x<行数>] [--grid]              縦書きの原稿としてPDFに書き出す
+                                                        （既定は40x34、20x20はマス目付き）
+  export <file.scrf> --format narou|kakuyomu|pixiv -o <フォルダ> [--encoding ...] [--newline ...]
+                                                        投稿サイトの記法に変換し、1話ずつ書き出す
+  stats <file.scrf>                                     プロット数や文字数を表示する
+  validate <file.scrf>...                               ファイルを読み込めるか検査する
+
+-o を省略すると標準出力に書き出します。
+コマンドを指定しない場合はウィンドウを開きます。";
+
+pub fn is_command(args: &[String]) -> bool {
+    args.first().is_some_and(|arg| COMMANDS.contains(&arg.as_str()))
+}
+
+// 終了コードを返す
+pub fn run(args: &[String]) -> i32 {
+    attach_console();
+
+    let result = match args[0].as_str() {
+        "compose" => compose(&args[1..]),
+        "export" => export(&args[1..]),
+        "stats" => stats(&args[1..]),
+        "validate" => return validate(&args[1..]),
+        _ => {
+            println!("{}", USAGE);
+            return 0;
+        }
+    };
+
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("エラー: {}", e);
+            2
+        }
+    }
+}
+
+// windows_subsystem = "windows" のため、呼び出し元のコンソールに出力を繋ぐ
+fn attach_console() {
+    #[cfg(target_os = "windows")]
+    {
+        extern "system" {
+            fn AttachConsole(dwProcessId: u32) -> i32;
+        }
+        const ATTACH_PARENT_PROCESS: u32 = 0xFFFF_FFFF;
+        unsafe {
+            AttachConsole(ATTACH_PARENT_PROCESS);
+        }
+    }
+}
+
+struct Options {
+    inputs: Vec<PathBuf>,
+    output: Option<PathBuf>,
+    format: Option<String>,
+    vertical: bool,
+    cover: Option<PathBuf>,
+    font: Option<PathBuf>,
+    font_index: u32,
+    layout: Option<String>,
+    grid: bool,
+    text: TextOptions,
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        inputs: Vec::new(),
+        output: None,
+        format: None,
+        vertical: false,
+        cover: None,
+        font: None,
+        font_index: 0,
+        layout: None,
+        grid: false,
+        text: TextOptions::default(),
+    };
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-o" | "--output" => {
+                let value = iter.next().ok_or("-o には出力先を指定してください")?;
+                options.output = Some(PathBuf::from(value));
+            }
+            "-f" | "--format" => {
+                let value = iter.next().ok_or("--format には形式を指定してください")?;
+                options.format = Some(value.clone());
+            }
+            "--vertical" => options.vertical = true,
+            "--cover" => {
+                let value = iter.next().ok_or("--cover には画像ファイルを指定してください")?;
+                options.cover = Some(PathBuf::from(value));
+            }
+            "--font" => {
+                let value = iter.next().ok_or("--font にはフォントファイルを指定してください")?;
+                options.font = Some(PathBuf::from(value));
+            }
+            "--font-index" => {
+                let value = iter.next().ok_or("--font-index には番号を指定してください")?;
+                options.font_index = value.parse().map_err(|_| format!("フォントの番号が不正です: {}", value))?;
+            }
+            "--layout" => {
+                let value = iter.next().ok_or("--layout には字数x行数を指定してください")?;
+                options.layout = Some(value.clone());
+            }
+            "--grid" => options.grid = true,
+            "--encoding" => {
+                let value = iter.next().ok_or("--encoding には文字コードを指定してください")?;
+                options.text.encoding = TextEncoding::from_name(value)
+                    .ok_or_else(|| format!("不明な文字コードです: {}（utf8, utf8bom, sjis のいずれか）", value))?;
+            }
+            "--newline" => {
+                let value = iter.next().ok_or("--newline には改行コードを指定してください")?;
+                options.text.line_ending = LineEnding::from_name(value)
+                    .ok_or_else(|| format!("不明な改行コードです: {}（crlf, lf のいずれか）", value))?;
+            }
+            _ if arg.starts_with('-') => return Err(format!("不明なオプションです: {}", arg)),
+            _ => options.inputs.push(PathBuf::from(arg)),
+        }
+    }
+    Ok(options)
+}
+
+fn single_input(options: &Options) -> Result<&Path, String> {
+    match options.inputs.as_slice() {
+        [input] => Ok(input),
+        [] => Err("入力ファイルを指定してください".to_owned()),
+        _ => Err("入力ファイルは1つだけ指定してください".to_owned()),
+    }
+}
+
+fn write_output(output: Option<&Path>, content: impl AsRef<[u8]>) -> Result<(), String> {
+    match output {
+        Some(path) => std::fs::write(path, content).map_err(|e| format!("{}: {}", path.display(), e)),
+        None => {
+            let mut stdout = std::io::stdout().lock();
+            stdout.write_all(content.as_ref()).and_then(|()| stdout.flush()).map_err(|e| e.to_string())
+        }
+    }
+}
+
+fn load(path: &Path) -> Result<Document, String> {
+    Document::load(path).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn compose(args: &[String]) -> Result<(), String> {
+    let options = parse_options(args)?;
+    let data = load(single_input(&options)?)?;
+    let mut text = data.compose_record().text();
+    text.push('\n');
+    write_output(options.output.as_deref(), &text)
+}
+
+// 文書生成の後にプロットを変更したファイルや、文書生成していないファイルでも今のプロットの内容で書き出す
+fn export(args: &[String]) -> Result<(), String> {
+    let options = parse_options(args)?;
+    let path = single_input(&options)?;
+    let prepared = export::prepare(&load(path)?).map_err(|e| format!("{}: {}", path.display(), e))?;
+    if let Some(warning) = prepared.warning() {
+        eprintln!("警告: {}: {}", path.display(), warning);
+    }
+    let data = prepared.doc;
+    match options.format.as_deref() {
+        Some("epub") => return export_epub(&data, &options),
+        Some("pdf") => return export_pdf(&data, &options),
+        Some("docx") => return write_binary(options.output.as_deref(), "Word", &docx::build(&data)?),
+        Some("odt") => return write_binary(options.output.as_deref(), "ODT", &odt::build(&data)?),
+        Some(name) if Site::from_name(name).is_some() => return export_site(&data, &options),
+        _ => {}
+    }
+    let format = match options.format.as_deref() {
+        Some(name) => ExportFormat::from_name(name)
+            .ok_or_else(|| format!("不明な形式です: {}（txt, md, html, docx, odt, epub, pdf, narou, kakuyomu, pixiv のいずれか）", name))?,
+        None => ExportFormat::Text,
+    };
+    if format == ExportFormat::Text {
+        return write_output(options.output.as_deref(), export::encode_text(&data.composed_text, &options.text)?);
+    }
+    let content = export::render(format, &data);
+    write_output(options.output.as_deref(), &content)
+}
+
+// バイナリ形式は標準出力には書き出さない
+fn write_binary(output: Option<&Path>, format_name: &str, content: &[u8]) -> Result<(), String> {
+    let output = output.ok_or_else(|| format!("{}の書き出しには -o で出力先を指定してください", format_name))?;
+    std::fs::write(output, content).map_err(|e| format!("{}: {}", output.display(), e))
+}
+
+fn export_epub(data: &Document, options: &Options) -> Result<(), String> {
+    let epub_options = EpubOptions {
+        vertical: options.vertical,
+        cover_image: options.cover.clone(),
+    };
+    write_binary(options.output.as_deref(), "EPUB", &epub::build(data, &epub_options)?)
+}
+
+fn export_pdf(data: &Document, options: &Options) -> Result<(), String> {
+    let font = options.font.clone().ok_or("PDFの書き出しには --font でフォントファイルを指定してください")?;
+    let mut pdf_options = PdfOptions::new(&LAYOUTS[1], font);
+    pdf_options.font_index = options.font_index;
+    if let Some(layout) = &options.layout {
+        let (chars, lines) = layout
+            .split_once(['x', 'X', '×'])
+            .and_then(|(chars, lines)| Some((chars.parse().ok()?, lines.parse().ok()?)))
+            .ok_or_else(|| format!("--layout は 40x34 のように指定してください: {}", layout))?;
+        pdf_options.chars_per_line = chars;
+        pdf_options.lines_per_page = lines;
+        pdf_options.grid = LAYOUTS
+            .iter()
+            .any(|l| l.grid && l.chars_per_line == chars && l.lines_per_page == lines);
+    }
+    pdf_options.grid |= options.grid;
+    write_binary(options.output.as_deref(), "PDF", &pdf::build(data, &pdf_options)?)
+}
+
+fn export_site(data: &Document, options: &Options) -> Result<(), String> {
+    let site = options.format.as_deref().and_then(Site::from_name).ok_or("投稿サイトの指定が不正です")?;
+    let dir = options
+        .output
+        .as_deref()
+        .ok_or_else(|| format!("{}向けの書き出しには -o で出力先のフォルダを指定してください", site.display_name()))?;
+    let count = site::write_episodes(data, site, &options.text, dir)?;
+    println!("{}向けに{}話を書き出しました: {}", site.display_name(), count, dir.display());
+    Ok(())
+}
+
+// 空白と改行を除いた文字数
+fn count_chars(text: &str) -> usize {
+    text.chars().filter(|c| !c.is_whitespace()).count()
+}
+
+fn stats(args: &[String]) -> Result<(), String> {
+    let options = parse_options(args)?;
+    let path = single_input(&options)?;
+    let data = load(path)?;
+
+    let count_kind = |kind: PlotKind| data.plots.iter().filter(|p| p.kind == kind).count();
+    let scenes = || data.plots.iter().filter(|p| p.kind == PlotKind::Scene);
+    let count_status = |status: PlotStatus| scenes().filter(|p| p.status == status).count();
+    let plot_chars: usize = scenes().map(|p| count_chars(&p.text)).sum();
+    let composed_chars = count_chars(&data.composed_text);
+
+    println!("ファイル: {}", path.display());
+    println!(
+        "プロット数: {}（幕 {} / 章 {} / シーン {}）",
+        data.plots.len(),
+        count_kind(PlotKind::Act),
+        count_kind(PlotKind::Chapter),
+        count_kind(PlotKind::Scene)
+    );
+    println!(
+        "進捗: {} {} / {} {} / {} {}",
+        PlotStatus::Idea.display_name(),
+        count_status(PlotStatus::Idea),
+        PlotStatus::Draft.display_name(),
+        count_status(PlotStatus::Draft),
+        PlotStatus::Done.display_name(),
+        count_status(PlotStatus::Done)
+    );
+    println!("プロットの文字数: {}", plot_chars);
+    println!("出力テキストの文字数: {}", composed_chars);
+    println!("出力テキストの行数: {}", data.composed_text.lines().count());
+    println!("原稿用紙換算（400字詰め）: {:.1}枚", composed_chars as f64 / 400.0);
+    Ok(())
+}
+
+// 問題のあったファイルがあれば終了コード1を返す
+fn validate(args: &[String]) -> i32 {
+    let options = match parse_options(args) {
+        Ok(options) if !options.inputs.is_empty() => options,
+        Ok(_) => {
+            eprintln!("エラー: 入力ファイルを指定してください");
+            return 2;
+        }
+        Err(e) => {
+            eprintln!("エラー: {}", e);
+            return 2;
+        }
+    };
+
+    let mut failed = false;
+    for path in &options.inputs {
+        let problems = match validate_file(path) {
+            Ok(problems) => problems,
+            Err(e) => vec![e],
+        };
+        if problems.is_empty() {
+            println!("OK: {}", path.display());
+        } else {
+            failed = true;
+            for problem in problems {
+                println!("NG: {}: {}", path.display(), problem);
+            }
+        }
+    }
+    i32::from(failed)
+}
+
+fn validate_file(path: &Path) -> Result<Vec<String>, String> {
+    let content = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let raw: serde_json::Value = serde_json::from_str(&content).map_err(|e| e.to_string())?;
+    let data = SaveData::from_json(&content)?;
+
+    let mut problems = Vec::new();
+    let version = format::format_version(&raw)?;
+    if version < FORMAT_VERSION {
+        println!("情報: {}: 形式 v{} のファイルです（読み込み時に v{} へ変換されます）", path.display(), version, FORMAT_VERSION);
+    }
+    if data.plots.is_empty() {
+        problems.push("プロットがありません".to_owned());
+    }
+    if data.plots.len() > MAX_PLOTS {
+        problems.push(format!("プロット数が上限（{}）を超えています: {}", MAX_PLOTS, data.plots.len()));
+    }
+    let mut ids = HashSet::new();
+    for plot in &data.plots {
+        if !ids.insert(plot.id) {
+            problems.push(format!("プロットIDが重複しています: {}", plot.id));
+        }
+    }
+    Ok(problems)
+}
@@ -0,0 +1,278 @@
+// 出力テキストの編集をプロットに戻す（文書生成の逆）
+
+use crate::document::{ComposeRecord, ComposedSection, Document, HEADING_SEPARATOR};
+use std::ops::Range;
+
+// シーンのプロットに戻す変更
+#[derive(Clone)]
+pub struct PlotChange {
+    pub id: usize,
+    pub old: String,
+    pub new: String,
+}
+
+#[derive(Clone, PartialEq)]
+pub enum DiffLine {
+    Same(String),
+    Removed(String),
+    Added(String),
+}
+
+// 行単位の差分（最長共通部分列）
+pub fn diff_lines(old: &str, new: &str) -> Vec<DiffLine> {
+    let old: Vec<&str> = old.split('\n').collect();
+    let new: Vec<&str> = new.split('\n').collect();
+    // 共通する先頭と末尾を除いてから表を作る
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (a, b) = (&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]);
+
+    let mut lengths = vec![vec![0u32; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lengths[i][j] = if a[i] == b[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff: Vec<DiffLine> = old[..prefix].iter().map(|l| DiffLine::Same((*l).to_owned())).collect();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            diff.push(DiffLine::Same(a[i].to_owned()));
+            i += 1;
+            j += 1;
+        } else if i < a.len() && (j == b.len() || lengths[i + 1][j] >= lengths[i][j + 1]) {
+            diff.push(DiffLine::Removed(a[i].to_owned()));
+            i += 1;
+        } else {
+            diff.push(DiffLine::Added(b[j].to_owned()));
+            j += 1;
+        }
+    }
+    diff.extend(old[old.len() - suffix..].iter().map(|l| DiffLine::Same((*l).to_owned())));
+    diff
+}
+
+pub struct Decomposed {
+    pub changes: Vec<PlotChange>,
+    // 対応付けられなかった箇所の説明
+    pub problems: Vec<String>,
+}
+
+pub fn same_text(a: &str, b: &str) -> bool {
+    a.trim_end_matches('\n') == b.trim_end_matches('\n')
+}
+
+// 次の区間の始まりの目印。書式の {{text}} より前の部分か、見出しならその区間の全体
+fn anchor(section: &ComposedSection) -> Option<(&str, bool)> {
+    match &section.frame {
+        Some((lead, _)) if !lead.trim().is_empty() => Some((lead, false)),
+        Some(_) if !section.kind.is_heading() => None,
+        _ => (!section.generated.trim().is_empty()).then_some((section.generated.as_str(), true)),
+    }
+}
+
+fn section_label(section: &ComposedSection) -> String {
+    section.generated.trim().chars().take(20).collect()
+}
+
+// rest の中で、joiner と次の区間の目印が続く位置（区間の全体を目印にする場合は後ろが空行か末尾のもの）
+fn find_next(rest: &str, joiner: &str, next: &ComposedSection) -> Option<usize> {
+    let (anchor, whole) = anchor(next)?;
+    let marker = format!("{}{}", joiner, anchor);
+    rest.match_indices(&marker).map(|(i, _)| i).find(|&i| {
+        let after = &rest[i + marker.len()..];
+        !whole || after.is_empty() || after.starts_with(HEADING_SEPARATOR)
+    })
+}
+
+// 前回の文書生成の記録をもとに、出力テキストの中で生成元のプロットごとの区間の範囲（バイト単位）を求める。
+// 区間の境目は、次の区間の目印（見出しや書式の前置き）、手直しの無い区間、区切り線の順に探す。
+// 改行は LF に揃えてあること。
+pub fn locate_ranges(record: &ComposeRecord, text: &str) -> Result<Vec<(usize, Range<usize>)>, String> {
+    let mut start = 0;
+    let mut end = text.trim_end_matches('\n').len();
+    if !record.header.is_empty() {
+        let header = format!("{}{}", record.header, HEADING_SEPARATOR);
+        if !text[..end].starts_with(&header) {
+            return Err("ヘッダーが見つかりません".to_owned());
+        }
+        start = header.len();
+    }
+    if !record.footer.is_empty() {
+        let footer = format!("{}{}", HEADING_SEPARATOR, record.footer.trim_end_matches('\n'));
+        if !text[start..end].ends_with(&footer) {
+            return Err("フッターが見つかりません".to_owned());
+        }
+        end -= footer.len();
+    }
+
+    let sections = &record.sections;
+    let mut located = Vec::with_capacity(sections.len());
+    for (i, section) in sections.iter().enumerate() {
+        let Some(next) = sections.get(i + 1) else {
+            located.push((section.id, start..end));
+            break;
+        };
+        let rest = &text[start..end];
+        let joiner = record.joiner(i);
+        // 本文に区切り線と同じ行があっても途中で切らないよう、手直しの無い区間かどうかを区切り線より先に確かめる
+        let len = find_next(rest, joiner, next)
+            .or_else(|| {
+                rest.starts_with(&format!("{}{}", section.generated, joiner))
+                    .then_some(section.generated.len())
+            })
+            .or_else(|| (!joiner.trim().is_empty()).then(|| rest.find(joiner)).flatten())
+            .ok_or_else(|| format!("「{}」の区間の始まりが見つかりません", section_label(next)))?;
+        located.push((section.id, start..start + len));
+        start += len + joiner.len();
+    }
+    Ok(located)
+}
+
+// 今の出力テキストを生成元のプロットごとの区間に分ける
+pub fn locate_sections(record: &ComposeRecord, composed_text: &str) -> Result<Vec<(usize, String)>, String> {
+    let text = composed_text.replace("\r\n", "\n");
+    let ranges = locate_ranges(record, &text)?;
+    Ok(ranges.into_iter().map(|(id, range)| (id, text[range].to_owned())).collect())
+}
+
+// 出力テキストを前回の文書生成の記録（無ければ今のプロット）で区間に分け、順にシーンのプロットへ対応付ける。
+// 見出しのプロットは目印として使うだけで変更しない。生成した時から変わっていない区間は戻さない。
+pub fn decompose(doc: &Document) -> Result<Decomposed, String> {
+    let record = if doc.composed.sections.is_empty() {
+        doc.compose_record()
+    } else {
+        doc.composed.clone()
+    };
+    let located = locate_sections(&record, &doc.composed_text)?;
+
+    let mut changes = Vec::new();
+    let mut problems = Vec::new();
+    for (section, (id, current)) in record.sections.iter().zip(located) {
+        let Some(plot) = doc.plot_index(id).map(|index| &doc.plots[index]) else {
+            continue;
+        };
+        if plot.kind.is_heading() || section.kind.is_heading() || same_text(&current, &section.generated) {
+            continue;
+        }
+        let Some(new) = section.body(&current) else {
+            problems.push(format!("「{}」: 書式の前後の部分が見つかりません", section_label(section)));
+            continue;
+        };
+        if !same_text(&plot.text, new) {
+            changes.push(PlotChange {
+                id,
+                old: plot.text.clone(),
+                new: new.to_owned(),
+            });
+        }
+    }
+    Ok(Decomposed { changes, problems })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::{PlotFragment, PlotKind};
+    use crate::template::ComposeTemplate;
+
+    fn composed_doc(template: ComposeTemplate) -> Document {
+        let plots = [(PlotKind::Chapter, "第一章"), (PlotKind::Scene, "本文A\n二行目"), (PlotKind::Scene, "本文B")]
+            .iter()
+            .enumerate()
+            .map(|(id, &(kind, text))| PlotFragment {
+                text: text.to_owned(),
+                title: format!("題{}", id),
+                ..PlotFragment::new(id, kind)
+            })
+            .collect();
+        let mut doc = Document::from_parts(plots, String::new());
+        doc.meta.title = "作品".to_owned();
+        doc.template = template;
+        doc.compose();
+        doc
+    }
+
+    #[test]
+    fn diff_lines_marks_changed_lines() {
+        let diff = diff_lines("a\nb\nc", "a\nx\nc\nd");
+        let expected = [
+            DiffLine::Same("a".to_owned()),
+            DiffLine::Removed("b".to_owned()),
+            DiffLine::Added("x".to_owned()),
+            DiffLine::Same("c".to_owned()),
+            DiffLine::Added("d".to_owned()),
+        ];
+        assert!(diff == expected);
+        assert!(diff_lines("同じ", "同じ") == [DiffLine::Same("同じ".to_owned())]);
+    }
+
+    #[test]
+    fn locate_ranges_with_every_builtin_template() {
+        for (name, template) in ComposeTemplate::builtins() {
+            // 区切りも前置きも無い書式では、手直しした区間の後ろの境目は見つけられない
+            let marked = name != "区切りなし";
+            let mut doc = composed_doc(template);
+            doc.composed_text = doc.composed_text.replace("本文B", "本文B（手直し）\n\n追加した段落");
+            if marked {
+                doc.composed_text = doc.composed_text.replace("本文A", "本文A（手直し）");
+            }
+            let ranges = locate_ranges(&doc.composed, &doc.composed_text).unwrap_or_else(|e| panic!("{}: {}", name, e));
+            let ids: Vec<usize> = ranges.iter().map(|(id, _)| *id).collect();
+            assert_eq!(ids, [0, 1, 2], "{}", name);
+            let body = |i: usize| doc.composed.sections[i].body(&doc.composed_text[ranges[i].1.clone()]);
+            let scene_a = if marked { "本文A（手直し）\n二行目" } else { "本文A\n二行目" };
+            assert_eq!(body(1), Some(scene_a), "{}", name);
+            assert_eq!(body(2), Some("本文B（手直し）\n\n追加した段落"), "{}", name);
+        }
+    }
+
+    #[test]
+    fn locate_ranges_fails_when_markers_are_removed() {
+        let mut doc = composed_doc(ComposeTemplate::builtins()[2].1.clone());
+        assert!(!doc.composed.header.is_empty());
+        doc.composed_text = doc.composed_text.replacen("作品", "別の題", 1);
+        assert!(locate_ranges(&doc.composed, &doc.composed_text).is_err());
+
+        let mut doc = composed_doc(ComposeTemplate::default());
+        doc.composed_text = doc.composed_text.replace("---", "");
+        doc.composed_text = doc.composed_text.replace("本文A", "変更");
+        assert!(locate_ranges(&doc.composed, &doc.composed_text).is_err());
+    }
+
+    #[test]
+    fn locate_ranges_keeps_separator_inside_scene() {
+        let mut doc = composed_doc(ComposeTemplate::default());
+        doc.plots[1].text = "前半\n\n---\n\n後半".to_owned();
+        doc.compose();
+        doc.composed_text = doc.composed_text.replace("本文B", "本文B改");
+        let ranges = locate_ranges(&doc.composed, &doc.composed_text).unwrap();
+        assert_eq!(&doc.composed_text[ranges[1].1.clone()], "前半\n\n---\n\n後半");
+        assert_eq!(&doc.composed_text[ranges[2].1.clone()], "本文B改");
+
+        let decomposed = decompose(&doc).unwrap();
+        assert_eq!(decomposed.changes.len(), 1);
+        assert_eq!((decomposed.changes[0].id, decomposed.changes[0].new.as_str()), (2, "本文B改"));
+    }
+
+    #[test]
+    fn decompose_returns_edited_scenes_only() {
+        let mut doc = composed_doc(ComposeTemplate::builtins()[1].1.clone());
+        doc.composed_text = doc.composed_text.replace("本文B", "本文B改").replace('\n', "\r\n");
+        let decomposed = decompose(&doc).unwrap();
+        assert!(decomposed.problems.is_empty());
+        assert_eq!(decomposed.changes.len(), 1);
+        let change = &decomposed.changes[0];
+        assert_eq!((change.id, change.old.as_str(), change.new.as_str()), (2, "本文B", "本文B改"));
+    }
+}
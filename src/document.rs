@@ -0,0 +1,875 @@
+// プロットと出力テキストからなる文書
+
+use crate::template::{compose_record, ComposeTemplate};
+use serde::{Deserialize, Serialize};
+
+pub const MAX_PLOTS: usize = 1024;
+pub const HEADING_SEPARATOR: &str = "\n\n";
+
+// 構成の階層（幕 → 章 → シーン）
+#[derive(Clone, Copy, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub enum PlotKind {
+    Act,
+    Chapter,
+    #[default]
+    Scene,
+}
+
+impl PlotKind {
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            PlotKind::Act => "幕",
+            PlotKind::Chapter => "章",
+            PlotKind::Scene => "シーン",
+        }
+    }
+
+    // 小さいほど上位の階層
+    pub fn rank(&self) -> u8 {
+        match self {
+            PlotKind::Act => 0,
+            PlotKind::Chapter => 1,
+            PlotKind::Scene => 2,
+        }
+    }
+
+    pub fn is_heading(&self) -> bool {
+        *self != PlotKind::Scene
+    }
+
+    pub fn all() -> &'static [PlotKind] {
+        &[PlotKind::Scene, PlotKind::Chapter, PlotKind::Act]
+    }
+}
+
+// 執筆の進捗（並び順は進み具合の順）
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Default, Serialize, Deserialize)]
+pub enum PlotStatus {
+    #[default]
+    Idea,
+    Draft,
+    Done,
+}
+
+impl PlotStatus {
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            PlotStatus::Idea => "アイデア",
+            PlotStatus::Draft => "下書き",
+            PlotStatus::Done => "完成",
+        }
+    }
+
+    pub fn all() -> &'static [PlotStatus] {
+        &[PlotStatus::Idea, PlotStatus::Draft, PlotStatus::Done]
+    }
+
+    pub fn next(&self) -> PlotStatus {
+        match self {
+            PlotStatus::Idea => PlotStatus::Draft,
+            PlotStatus::Draft => PlotStatus::Done,
+            PlotStatus::Done => PlotStatus::Idea,
+        }
+    }
+}
+
+// プロットの色ラベル
+#[derive(Clone, Copy, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub enum PlotColor {
+    #[default]
+    None,
+    Red,
+    Orange,
+    Yellow,
+    Green,
+    Blue,
+    Purple,
+}
+
+impl PlotColor {
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            PlotColor::None => "なし",
+            PlotColor::Red => "赤",
+            PlotColor::Orange => "橙",
+            PlotColor::Yellow => "黄",
+            PlotColor::Green => "緑",
+            PlotColor::Blue => "青",
+            PlotColor::Purple => "紫",
+        }
+    }
+
+    pub fn rgb(&self) -> Option<[u8; 3]> {
+        match self {
+            PlotColor::None => None,
+            PlotColor::Red => Some([210, 70, 70]),
+            PlotColor::Orange => Some([230, 140, 50]),
+            PlotColor::Yellow => Some([220, 200, 60]),
+            PlotColor::Green => Some([70, 170, 90]),
+            PlotColor::Blue => Some([70, 130, 210]),
+            PlotColor::Purple => Some([150, 90, 200]),
+        }
+    }
+
+    pub fn all() -> &'static [PlotColor] {
+        &[
+            PlotColor::None,
+            PlotColor::Red,
+            PlotColor::Orange,
+            PlotColor::Yellow,
+            PlotColor::Green,
+            PlotColor::Blue,
+            PlotColor::Purple,
+        ]
+    }
+}
+
+// 幕・章は見出し行として同じ一覧に並べ、後続のシーンをその子として扱う。
+// 旧形式の.scrfにはkindが無いので、すべてシーンとして読み込まれる。
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PlotFragment {
+    pub id: usize,
+    pub text: String,
+    #[serde(default)]
+    pub kind: PlotKind,
+    #[serde(default)]
+    pub collapsed: bool,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub synopsis: String,
+    #[serde(default)]
+    pub status: PlotStatus,
+    #[serde(default)]
+    pub color: PlotColor,
+    // 作者用メモ（文書生成には含めない）
+    #[serde(default)]
+    pub notes: String,
+    // 構想・別案・資料など、文書生成に含めないプロット（見出しの場合は配下のシーンも含めない）
+    #[serde(default)]
+    pub excluded: bool,
+    // 登場人物・サブプロット・場所などの自由なタグ
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl PlotFragment {
+    pub fn new(id: usize, kind: PlotKind) -> Self {
+        Self {
+            id,
+            kind,
+            ..Default::default()
+        }
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+}
+
+// タグの前後の空白と先頭の # を除く（空になる場合は None）
+pub fn normalize_tag(tag: &str) -> Option<String> {
+    let tag = tag.trim().trim_start_matches('#').trim();
+    (!tag.is_empty()).then(|| tag.to_owned())
+}
+
+// プロット一覧の絞り込み。選んだタグをすべて持つプロットと、それを含む見出しを表示する
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PlotFilter {
+    #[serde(default)]
+    pub tags: Vec<String>,
+    // 絞り込んだプロットだけを文書生成に含める
+    #[serde(default)]
+    pub compose_only: bool,
+}
+
+impl PlotFilter {
+    pub fn is_active(&self) -> bool {
+        !self.tags.is_empty()
+    }
+
+    fn matches(&self, plot: &PlotFragment) -> bool {
+        self.tags.iter().all(|tag| plot.has_tag(tag))
+    }
+}
+
+// 文書の情報（書き出し時のタイトル・著者名など）
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct DocumentMeta {
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub author: String,
+    // 空の場合は書き出した日付を使う
+    #[serde(default)]
+    pub date: String,
+}
+
+impl DocumentMeta {
+    pub fn date_or_today(&self) -> String {
+        if self.date.trim().is_empty() {
+            chrono::Local::now().format("%Y-%m-%d").to_string()
+        } else {
+            self.date.trim().to_owned()
+        }
+    }
+}
+
+// 文書生成の時点でプロットから作った出力テキストの区間（再生成時に手直しを見分けるため）
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ComposedSection {
+    pub id: usize,
+    pub kind: PlotKind,
+    // 書式を当てはめて作った区間の内容
+    pub generated: String,
+    // 書式のうち {{text}} の前後に置かれた部分（書式に {{text}} が無い場合は None）
+    #[serde(default)]
+    pub frame: Option<(String, String)>,
+}
+
+impl ComposedSection {
+    // 区間から書式の前後の部分を除いて本文を取り出す
+    pub fn body<'a>(&self, section: &'a str) -> Option<&'a str> {
+        let (lead, trail) = self.frame.as_ref()?;
+        let section = section.trim_end_matches('\n');
+        section.strip_prefix(lead.as_str())?.strip_suffix(trail.trim_end_matches('\n'))
+    }
+}
+
+// 前回の文書生成の記録。ヘッダー・フッターと区切りは生成した時点の書式のもの
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ComposeRecord {
+    pub header: String,
+    pub footer: String,
+    // シーン同士の間に入れた文字列
+    pub separator: String,
+    pub sections: Vec<ComposedSection>,
+}
+
+impl ComposeRecord {
+    // i 番目と次の区間の間の文字列。シーン同士の間だけ区切りを入れ、見出しの前後は空行のみ
+    pub fn joiner(&self, i: usize) -> &str {
+        if self.sections[i].kind.is_heading() || self.sections[i + 1].kind.is_heading() {
+            HEADING_SEPARATOR
+        } else {
+            &self.separator
+        }
+    }
+
+    // 区間ごとの内容を順に繋げ、ヘッダーとフッターを付ける
+    pub fn join<'a>(&self, sections: impl IntoIterator<Item = &'a str>) -> String {
+        let mut text = String::new();
+        if !self.header.is_empty() {
+            text.push_str(&self.header);
+            text.push_str(HEADING_SEPARATOR);
+        }
+        for (i, section) in sections.into_iter().enumerate() {
+            if i > 0 {
+                text.push_str(self.joiner(i - 1));
+            }
+            text.push_str(section);
+        }
+        if !self.footer.is_empty() {
+            text.push_str(HEADING_SEPARATOR);
+            text.push_str(&self.footer);
+        }
+        text
+    }
+
+    pub fn text(&self) -> String {
+        self.join(self.sections.iter().map(|s| s.generated.as_str()))
+    }
+}
+
+// 1つのプロットから作る出力テキストの区間
+pub fn section_text(kind: PlotKind, text: &str) -> &str {
+    if kind.is_heading() {
+        text.trim()
+    } else {
+        text
+    }
+}
+
+// 標準の書式でプロットを順に繋げて出力テキストを作る
+pub fn compose_plots(plots: &[PlotFragment]) -> String {
+    compose_record(plots, &ComposeTemplate::default(), &DocumentMeta::default()).text()
+}
+
+// 編集操作は変更があった場合にtrueを返す（呼び出し側で元に戻す履歴を積むため）
+#[derive(Clone)]
+pub struct Document {
+    pub plots: Vec<PlotFragment>,
+    pub composed_text: String,
+    pub meta: DocumentMeta,
+    pub template: ComposeTemplate,
+    pub composed: ComposeRecord,
+    pub filter: PlotFilter,
+    // このタグを持つプロットは文書生成に含めない
+    pub excluded_tags: Vec<String>,
+    next_id: usize,
+}
+
+impl Default for Document {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Document {
+    pub fn new() -> Self {
+        Self {
+            plots: vec![PlotFragment::new(0, PlotKind::Scene)],
+            composed_text: String::new(),
+            meta: DocumentMeta::default(),
+            template: ComposeTemplate::default(),
+            composed: ComposeRecord::default(),
+            filter: PlotFilter::default(),
+            excluded_tags: Vec::new(),
+            next_id: 1,
+        }
+    }
+
+    pub fn from_parts(plots: Vec<PlotFragment>, composed_text: String) -> Self {
+        let next_id = plots.iter().map(|p| p.id).max().map_or(0, |id| id + 1);
+        let mut doc = Self {
+            plots,
+            composed_text,
+            meta: DocumentMeta::default(),
+            template: ComposeTemplate::default(),
+            composed: ComposeRecord::default(),
+            filter: PlotFilter::default(),
+            excluded_tags: Vec::new(),
+            next_id,
+        };
+        if doc.plots.is_empty() {
+            let id = doc.allocate_id();
+            doc.plots.push(PlotFragment::new(id, PlotKind::Scene));
+        }
+        doc
+    }
+
+    pub fn allocate_id(&mut self) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    pub fn plot_index(&self, id: usize) -> Option<usize> {
+        self.plots.iter().position(|p| p.id == id)
+    }
+
+    // 使われているタグ（名前順）
+    pub fn all_tags(&self) -> Vec<String> {
+        let mut tags: Vec<String> = self.plots.iter().flat_map(|p| p.tags.iter().cloned()).collect();
+        tags.sort();
+        tags.dedup();
+        tags
+    }
+
+    // 除外したプロット、または除外するタグを持つプロット
+    pub fn is_excluded(&self, index: usize) -> bool {
+        let plot = &self.plots[index];
+        plot.excluded || self.excluded_tags.iter().any(|tag| plot.has_tag(tag))
+    }
+
+    // プロットごとに絞り込みの表示対象かどうか。一致した見出しの配下と、一致したプロットを含む見出しも対象にする
+    pub fn filter_matches(&self) -> Vec<bool> {
+        if !self.filter.is_active() {
+            return vec![true; self.plots.len()];
+        }
+        let direct: Vec<bool> = self.plots.iter().map(|p| self.filter.matches(p)).collect();
+        let mut matches = direct.clone();
+        for i in 0..self.plots.len() {
+            if self.plots[i].kind.is_heading() {
+                let end = self.block_end(i);
+                if direct[i] {
+                    matches[i..end].fill(true);
+                } else if direct[i + 1..end].contains(&true) {
+                    matches[i] = true;
+                }
+            }
+        }
+        matches
+    }
+
+    // 文書生成に含めるプロット（除外した見出しの配下も除く。絞り込みを文書生成に使う場合は絞り込んだものだけ）
+    pub fn included_plots(&self) -> Vec<&PlotFragment> {
+        let filtered = self.filter.compose_only.then(|| self.filter_matches());
+        let mut plots = Vec::with_capacity(self.plots.len());
+        let mut skip_until = 0;
+        for (i, plot) in self.plots.iter().enumerate() {
+            if i < skip_until {
+                continue;
+            }
+            if self.is_excluded(i) {
+                skip_until = self.block_end(i);
+                continue;
+            }
+            if filtered.as_ref().is_some_and(|matches| !matches[i]) {
+                continue;
+            }
+            plots.push(plot);
+        }
+        plots
+    }
+
+    // 重複するタグは加えない
+    pub fn add_tag(&mut self, index: usize, tag: &str) -> bool {
+        match normalize_tag(tag) {
+            Some(tag) if !self.plots[index].has_tag(&tag) => {
+                self.plots[index].tags.push(tag);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn remove_tag(&mut self, index: usize, tag: &str) -> bool {
+        let before = self.plots[index].tags.len();
+        self.plots[index].tags.retain(|t| t != tag);
+        self.plots[index].tags.len() != before
+    }
+
+    // 今のプロットに文書の書式を当てはめる
+    pub fn compose_record(&self) -> ComposeRecord {
+        compose_record(self.included_plots(), &self.template, &self.meta)
+    }
+
+    pub fn compose(&mut self) {
+        let record = self.compose_record();
+        self.set_composed(record.text(), record);
+    }
+
+    // 出力テキストを置き換え、生成元の記録を残す
+    pub fn set_composed(&mut self, text: String, record: ComposeRecord) {
+        self.composed_text = text;
+        self.composed = record;
+    }
+
+    // 出力テキストの内容をプロットに書き戻した後、そのプロットを生成元の記録と揃える
+    pub fn mark_composed(&mut self, id: usize) {
+        let Some(index) = self.plot_index(id) else {
+            return;
+        };
+        let plot = &self.plots[index];
+        if let Some(section) = self.composed.sections.iter_mut().find(|s| s.id == id) {
+            if let Some((lead, trail)) = &section.frame {
+                section.generated = format!("{}{}{}", lead, section_text(plot.kind, &plot.text), trail);
+            }
+        }
+    }
+
+    // 見出しの場合は配下のシーンを含めたブロックの終端（次の同格以上の見出し）を返す
+    pub fn block_end(&self, index: usize) -> usize {
+        let rank = self.plots[index].kind.rank();
+        if !self.plots[index].kind.is_heading() {
+            return index + 1;
+        }
+        self.plots[index + 1..]
+            .iter()
+            .position(|p| p.kind.rank() <= rank)
+            .map_or(self.plots.len(), |pos| index + 1 + pos)
+    }
+
+    pub fn add_plot_after(&mut self, index: usize, kind: PlotKind) -> bool {
+        if self.plots.len() >= MAX_PLOTS {
+            return false;
+        }
+        // 折りたたまれた見出しの中には挿入しない
+        let insert_at = if self.plots[index].collapsed {
+            self.block_end(index)
+        } else {
+            index + 1
+        };
+        let new_plot = PlotFragment::new(self.allocate_id(), kind);
+        self.plots.insert(insert_at, new_plot);
+        true
+    }
+
+    // 取り込んだプロットにIDを振って末尾に加える。空の新規文書の場合は置き換える。
+    pub fn append_plots(&mut self, plots: Vec<PlotFragment>) -> bool {
+        let is_blank = self.plots.len() == 1 && self.plots[0].text.is_empty() && self.plots[0].title.is_empty();
+        let kept = if is_blank { 0 } else { self.plots.len() };
+        if plots.is_empty() || kept + plots.len() > MAX_PLOTS {
+            return false;
+        }
+        if is_blank {
+            self.plots.clear();
+        }
+        for plot in plots {
+            let id = self.allocate_id();
+            self.plots.push(PlotFragment { id, ..plot });
+        }
+        true
+    }
+
+    pub fn remove_plot(&mut self, index: usize) -> bool {
+        if self.plots.len() <= 1 {
+            return false;
+        }
+        self.plots.remove(index);
+        true
+    }
+
+    // 選んだプロットをまとめて削除する（すべてを削除することはできない）
+    pub fn remove_plots(&mut self, indices: &[usize]) -> bool {
+        if indices.is_empty() || indices.len() >= self.plots.len() {
+            return false;
+        }
+        for &index in indices.iter().rev() {
+            self.plots.remove(index);
+        }
+        true
+    }
+
+    // 選んだプロットの複製をそれぞれの直後に挿入し、複製のIDを返す
+    pub fn duplicate_plots(&mut self, indices: &[usize]) -> Option<Vec<usize>> {
+        if indices.is_empty() || self.plots.len() + indices.len() > MAX_PLOTS {
+            return None;
+        }
+        let mut ids = Vec::with_capacity(indices.len());
+        for &index in indices.iter().rev() {
+            let id = self.allocate_id();
+            let copy = PlotFragment {
+                id,
+                ..self.plots[index].clone()
+            };
+            self.plots.insert(index + 1, copy);
+            ids.push(id);
+        }
+        ids.reverse();
+        Some(ids)
+    }
+
+    // 選んだシーンを先頭のシーンにまとめる。本文・メモは空行を挟んで繋げ、タグは合わせる。
+    // タイトル・あらすじ・色は最初に設定されているもの、進捗は最も進んでいないものにし、
+    // 除外はすべてのシーンが除外されている場合だけ残す（含めていた本文が出力から消えないように）。
+    // 除外するタグを持つシーンと文書生成に含めるシーンは、結合するとすべて除外されるため結合しない。
+    pub fn merge_plots(&mut self, indices: &[usize]) -> Result<bool, String> {
+        if indices.len() < 2 {
+            return Ok(false);
+        }
+        if indices.iter().any(|&i| self.plots[i].kind.is_heading()) {
+            return Err("幕・章は結合できません".to_owned());
+        }
+        if indices.iter().any(|&i| !self.is_excluded(i)) {
+            let tagged = self
+                .excluded_tags
+                .iter()
+                .find(|tag| indices.iter().any(|&i| self.plots[i].has_tag(tag)));
+            if let Some(tag) = tagged {
+                return Err(format!("除外するタグ「{}」を持つシーンと、文書生成に含めるシーンは結合できません", tag));
+            }
+        }
+        let join = |parts: Vec<&str>| {
+            parts
+                .into_iter()
+                .map(|p| p.trim_end_matches('\n'))
+                .filter(|p| !p.trim().is_empty())
+                .collect::<Vec<_>>()
+                .join("\n\n")
+        };
+        let plots: Vec<&PlotFragment> = indices.iter().map(|&i| &self.plots[i]).collect();
+        let text = join(plots.iter().map(|p| p.text.as_str()).collect());
+        let notes = join(plots.iter().map(|p| p.notes.as_str()).collect());
+        let title = plots.iter().map(|p| p.title.as_str()).find(|t| !t.trim().is_empty()).unwrap_or("").to_owned();
+        let synopsis = plots.iter().map(|p| p.synopsis.as_str()).find(|s| !s.trim().is_empty()).unwrap_or("").to_owned();
+        let color = plots.iter().map(|p| p.color).find(|&c| c != PlotColor::None).unwrap_or_default();
+        let status = plots.iter().map(|p| p.status).min().unwrap_or_default();
+        let excluded = plots.iter().all(|p| p.excluded);
+        let mut tags: Vec<String> = Vec::new();
+        for tag in plots.iter().flat_map(|p| &p.tags) {
+            if !tags.contains(tag) {
+                tags.push(tag.clone());
+            }
+        }
+
+        let first = &mut self.plots[indices[0]];
+        first.text = text;
+        first.notes = notes;
+        first.title = title;
+        first.synopsis = synopsis;
+        first.tags = tags;
+        first.color = color;
+        first.status = status;
+        first.excluded = excluded;
+        for &index in indices[1..].iter().rev() {
+            self.plots.remove(index);
+        }
+        Ok(true)
+    }
+
+    // シーンの本文を char_index 文字目で2つに分ける。後半は直後の新しいシーンになり、進捗・色・タグ・除外を引き継ぐ
+    // （タイトル・あらすじ・メモは前半に残す）。どちらかが空になる位置では分けない。
+    pub fn split_plot(&mut self, index: usize, char_index: usize) -> bool {
+        let plot = &self.plots[index];
+        if plot.kind.is_heading() || self.plots.len() >= MAX_PLOTS {
+            return false;
+        }
+        let at = plot.text.char_indices().nth(char_index).map_or(plot.text.len(), |(i, _)| i);
+        let (head, tail) = plot.text.split_at(at);
+        let (head, tail) = (head.trim_end().to_owned(), tail.trim_start_matches(['\n', '\r']).to_owned());
+        if head.trim().is_empty() || tail.trim().is_empty() {
+            return false;
+        }
+        let new_plot = PlotFragment {
+            text: tail,
+            status: plot.status,
+            color: plot.color,
+            tags: plot.tags.clone(),
+            excluded: plot.excluded,
+            ..PlotFragment::new(self.allocate_id(), PlotKind::Scene)
+        };
+        self.plots[index].text = head;
+        self.plots.insert(index + 1, new_plot);
+        true
+    }
+
+    pub fn set_plot_kind(&mut self, index: usize, kind: PlotKind) -> bool {
+        if self.plots[index].kind == kind {
+            return false;
+        }
+        self.plots[index].kind = kind;
+        self.plots[index].collapsed = false;
+        true
+    }
+
+    // 選んだプロット（昇順の位置）を元の並び順のまま取り出し、先頭が position の位置になるように挿入する
+    pub fn move_plots_to(&mut self, indices: &[usize], position: usize) -> bool {
+        if indices.is_empty() {
+            return false;
+        }
+        let before: Vec<usize> = self.plots.iter().map(|p| p.id).collect();
+        let mut moved = Vec::with_capacity(indices.len());
+        for &index in indices.iter().rev() {
+            moved.push(self.plots.remove(index));
+        }
+        moved.reverse();
+        let position = position.min(self.plots.len());
+        self.plots.splice(position..position, moved);
+        self.plots.iter().map(|p| p.id).ne(before)
+    }
+
+    // 選んだプロットを、元の並びで target の位置にあるプロットの直前（末尾の場合は plots.len()）へまとめて移動する
+    pub fn move_plots(&mut self, indices: &[usize], target: usize) -> bool {
+        let position = target - indices.iter().filter(|&&i| i < target).count();
+        self.move_plots_to(indices, position)
+    }
+
+    // 見出しの場合は配下のシーンを含めたブロックの位置
+    pub fn block_indices(&self, index: usize) -> Vec<usize> {
+        (index..self.block_end(index)).collect()
+    }
+
+    // 見出しは配下のシーンごと、直前の同格以上のまとまりを飛び越えて移動する。
+    // 章は属する幕の見出しを越えない（幕の外へは移動できない）
+    pub fn move_plot_up(&mut self, index: usize) -> bool {
+        if index == 0 {
+            return false;
+        }
+        let kind = self.plots[index].kind;
+        let end = self.block_end(index);
+        let prev_start = self.plots[..index]
+            .iter()
+            .rposition(|p| p.kind.rank() <= kind.rank())
+            .unwrap_or(0);
+        if kind.is_heading() && self.plots[prev_start].kind.rank() < kind.rank() {
+            return false;
+        }
+        self.plots[prev_start..end].rotate_left(index - prev_start);
+        true
+    }
+
+    pub fn move_plot_down(&mut self, index: usize) -> bool {
+        let end = self.block_end(index);
+        if end >= self.plots.len() {
+            return false;
+        }
+        let kind = self.plots[index].kind;
+        if kind.is_heading() && self.plots[end].kind.rank() < kind.rank() {
+            return false;
+        }
+        let next_end = self.plots[end + 1..]
+            .iter()
+            .position(|p| p.kind.rank() <= kind.rank())
+            .map_or(self.plots.len(), |pos| end + 1 + pos);
+        self.plots[index..next_end].rotate_left(end - index);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn doc(plots: &[(PlotKind, &str)]) -> Document {
+        let plots = plots
+            .iter()
+            .enumerate()
+            .map(|(id, &(kind, text))| PlotFragment {
+                text: text.to_owned(),
+                ..PlotFragment::new(id, kind)
+            })
+            .collect();
+        Document::from_parts(plots, String::new())
+    }
+
+    fn ids(doc: &Document) -> Vec<usize> {
+        doc.plots.iter().map(|p| p.id).collect()
+    }
+
+    fn scenes(count: usize) -> Document {
+        doc(&vec![(PlotKind::Scene, "本文"); count])
+    }
+
+    #[test]
+    fn move_plots_to_keeps_order_of_moved_plots() {
+        let mut d = scenes(5);
+        assert!(d.move_plots_to(&[1, 3], 0));
+        assert_eq!(ids(&d), [1, 3, 0, 2, 4]);
+
+        // 位置は取り出した後の並びで数え、末尾を超える指定は末尾にする
+        let mut d = scenes(5);
+        assert!(d.move_plots_to(&[0, 1], 99));
+        assert_eq!(ids(&d), [2, 3, 4, 0, 1]);
+
+        // 元と同じ並びになる移動は変更なし
+        let mut d = scenes(5);
+        assert!(!d.move_plots_to(&[2, 3], 2));
+        assert!(!d.move_plots_to(&[], 0));
+        assert_eq!(ids(&d), [0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn move_plots_inserts_before_original_target() {
+        let mut d = scenes(5);
+        assert!(d.move_plots(&[0, 1], 4));
+        assert_eq!(ids(&d), [2, 3, 0, 1, 4]);
+
+        let mut d = scenes(5);
+        assert!(d.move_plots(&[3], 5));
+        assert_eq!(ids(&d), [0, 1, 2, 4, 3]);
+    }
+
+    #[test]
+    fn move_chapter_stays_within_act() {
+        let mut d = doc(&[
+            (PlotKind::Act, "第一幕"),
+            (PlotKind::Chapter, "第一章"),
+            (PlotKind::Scene, "本文A"),
+            (PlotKind::Chapter, "第二章"),
+            (PlotKind::Scene, "本文B"),
+            (PlotKind::Act, "第二幕"),
+            (PlotKind::Chapter, "第三章"),
+        ]);
+        // 章は配下のシーンごと同じ幕の中で入れ替わる
+        assert!(d.move_plot_down(1));
+        assert_eq!(ids(&d), [0, 3, 4, 1, 2, 5, 6]);
+        assert!(d.move_plot_up(3));
+        assert_eq!(ids(&d), [0, 1, 2, 3, 4, 5, 6]);
+
+        // 幕の最初・最後の章は幕の見出しを越えない
+        assert!(!d.move_plot_up(1));
+        assert!(!d.move_plot_down(3));
+        assert!(!d.move_plot_up(6));
+        assert_eq!(ids(&d), [0, 1, 2, 3, 4, 5, 6]);
+
+        // シーンは章の見出しを越えて前後の章へ移る
+        assert!(d.move_plot_down(2));
+        assert_eq!(ids(&d), [0, 1, 3, 2, 4, 5, 6]);
+
+        // 幕は配下の章ごと移動する
+        assert!(d.move_plot_up(5));
+        assert_eq!(ids(&d), [5, 6, 0, 1, 3, 2, 4]);
+    }
+
+    #[test]
+    fn split_plot_at_character() {
+        let mut d = doc(&[(PlotKind::Scene, "前半の文。\n\n後半の文。")]);
+        d.plots[0].title = "題".to_owned();
+        d.plots[0].status = PlotStatus::Draft;
+        d.plots[0].tags = vec!["主人公".to_owned()];
+        d.plots[0].excluded = true;
+        assert!(d.split_plot(0, 5));
+        assert_eq!(d.plots.len(), 2);
+        assert_eq!(d.plots[0].text, "前半の文。");
+        assert_eq!(d.plots[0].title, "題");
+        let tail = &d.plots[1];
+        assert_eq!(tail.text, "後半の文。");
+        assert!(tail.title.is_empty());
+        assert_eq!(tail.status, PlotStatus::Draft);
+        assert_eq!(tail.tags, ["主人公"]);
+        assert!(tail.excluded);
+        assert_ne!(tail.id, d.plots[0].id);
+    }
+
+    #[test]
+    fn split_plot_rejects_empty_half_and_headings() {
+        let mut d = doc(&[(PlotKind::Chapter, "第一章"), (PlotKind::Scene, "本文")]);
+        assert!(!d.split_plot(0, 1));
+        assert!(!d.split_plot(1, 0));
+        assert!(!d.split_plot(1, 2));
+        assert_eq!(d.plots.len(), 2);
+    }
+
+    #[test]
+    fn merge_plots_joins_scenes() {
+        let mut d = doc(&[(PlotKind::Scene, "一\n"), (PlotKind::Scene, ""), (PlotKind::Scene, "三")]);
+        d.plots[1].title = "二の題".to_owned();
+        d.plots[0].tags = vec!["a".to_owned()];
+        d.plots[2].tags = vec!["a".to_owned(), "b".to_owned()];
+        d.plots[0].status = PlotStatus::Done;
+        d.plots[1].status = PlotStatus::Done;
+        d.plots[2].status = PlotStatus::Draft;
+        d.plots[2].color = PlotColor::Red;
+        assert_eq!(d.merge_plots(&[0, 1, 2]), Ok(true));
+        assert_eq!(ids(&d), [0]);
+        let merged = &d.plots[0];
+        assert_eq!(merged.text, "一\n\n三");
+        assert_eq!(merged.title, "二の題");
+        assert_eq!(merged.tags, ["a", "b"]);
+        assert_eq!(merged.status, PlotStatus::Draft);
+        assert_eq!(merged.color, PlotColor::Red);
+    }
+
+    #[test]
+    fn merge_plots_keeps_exclusion_only_if_all_excluded() {
+        let mut d = scenes(3);
+        d.plots[0].excluded = true;
+        assert_eq!(d.merge_plots(&[0, 1]), Ok(true));
+        assert!(!d.plots[0].excluded);
+
+        d.plots[0].excluded = true;
+        d.plots[1].excluded = true;
+        assert_eq!(d.merge_plots(&[0, 1]), Ok(true));
+        assert!(d.plots[0].excluded);
+    }
+
+    #[test]
+    fn merge_plots_rejects_headings_and_excluded_tags() {
+        let mut d = doc(&[(PlotKind::Chapter, "第一章"), (PlotKind::Scene, "一"), (PlotKind::Scene, "二")]);
+        assert!(d.merge_plots(&[0, 1]).is_err());
+        assert_eq!(d.merge_plots(&[1]), Ok(false));
+
+        d.plots[2].tags = vec!["没".to_owned()];
+        d.excluded_tags = vec!["没".to_owned()];
+        assert!(d.merge_plots(&[1, 2]).is_err());
+        assert_eq!(d.plots.len(), 3);
+    }
+
+    #[test]
+    fn included_plots_skip_excluded_blocks_and_tags() {
+        let mut d = doc(&[
+            (PlotKind::Chapter, "第一章"),
+            (PlotKind::Scene, "一"),
+            (PlotKind::Chapter, "第二章"),
+            (PlotKind::Scene, "二"),
+            (PlotKind::Scene, "三"),
+        ]);
+        d.plots[0].excluded = true;
+        d.plots[4].tags = vec!["没".to_owned()];
+        d.excluded_tags = vec!["没".to_owned()];
+        let texts: Vec<&str> = d.included_plots().iter().map(|p| p.text.as_str()).collect();
+        assert_eq!(texts, ["第二章", "二"]);
+    }
+}
@@ -0,0 +1,696 @@
+// 出力テキストを各形式に変換する（GUIとコマンドラインで共用）
+
+use crate::decompose;
+use crate::document::{ComposeRecord, Document, DocumentMeta, PlotKind};
+use crate::recompose;
+use crate::markup::{self, Inline};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Write;
+use std::ops::Range;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+pub mod docx;
+pub mod epub;
+pub mod odt;
+pub mod pdf;
+pub mod site;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Text,
+    Markdown,
+    Html,
+}
+
+impl ExportFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "txt" | "text" => Some(ExportFormat::Text),
+            "md" | "markdown" => Some(ExportFormat::Markdown),
+            "html" | "htm" => Some(ExportFormat::Html),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Text => "txt",
+            ExportFormat::Markdown => "md",
+            ExportFormat::Html => "html",
+        }
+    }
+
+    pub fn filter_name(&self) -> &'static str {
+        match self {
+            ExportFormat::Text => "テキストファイル",
+            ExportFormat::Markdown => "Markdown",
+            ExportFormat::Html => "HTML",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum TextEncoding {
+    #[default]
+    Utf8,
+    Utf8Bom,
+    // Windowsの日本語の文字コード（CP932）
+    ShiftJis,
+}
+
+impl TextEncoding {
+    pub fn all() -> &'static [TextEncoding] {
+        &[TextEncoding::Utf8, TextEncoding::Utf8Bom, TextEncoding::ShiftJis]
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "utf8" | "utf-8" => Some(TextEncoding::Utf8),
+            "utf8bom" | "utf-8-bom" => Some(TextEncoding::Utf8Bom),
+            "sjis" | "shift_jis" | "cp932" => Some(TextEncoding::ShiftJis),
+            _ => None,
+        }
+    }
+
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            TextEncoding::Utf8 => "UTF-8",
+            TextEncoding::Utf8Bom => "UTF-8（BOM付き）",
+            TextEncoding::ShiftJis => "Shift_JIS（CP932）",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum LineEnding {
+    #[default]
+    Crlf,
+    Lf,
+}
+
+impl LineEnding {
+    pub fn all() -> &'static [LineEnding] {
+        &[LineEnding::Crlf, LineEnding::Lf]
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "crlf" => Some(LineEnding::Crlf),
+            "lf" => Some(LineEnding::Lf),
+            _ => None,
+        }
+    }
+
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            LineEnding::Crlf => "CRLF（Windows）",
+            LineEnding::Lf => "LF（Mac・Linux）",
+        }
+    }
+}
+
+// テキストファイルとして書き出すときの文字コードと改行コード
+#[derive(Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct TextOptions {
+    pub encoding: TextEncoding,
+    pub line_ending: LineEnding,
+}
+
+// EPUB・DOCX・ODTのようにファイルをzipにまとめる形式。追加した順に書き込む。
+pub(crate) struct Package {
+    zip: ZipWriter<std::io::Cursor<Vec<u8>>>,
+}
+
+impl Package {
+    pub(crate) fn new() -> Self {
+        Self {
+            zip: ZipWriter::new(std::io::Cursor::new(Vec::new())),
+        }
+    }
+
+    fn add_with(&mut self, name: &str, method: CompressionMethod, data: &[u8]) -> Result<(), String> {
+        let options = SimpleFileOptions::default().compression_method(method);
+        self.zip.start_file(name, options).map_err(|e| e.to_string())?;
+        self.zip.write_all(data).map_err(|e| e.to_string())
+    }
+
+    pub(crate) fn add(&mut self, name: &str, data: &[u8]) -> Result<(), String> {
+        self.add_with(name, CompressionMethod::Deflated, data)
+    }
+
+    // EPUB・ODTの mimetype は無圧縮で先頭に置く決まり
+    pub(crate) fn add_stored(&mut self, name: &str, data: &[u8]) -> Result<(), String> {
+        self.add_with(name, CompressionMethod::Stored, data)
+    }
+
+    pub(crate) fn finish(self) -> Result<Vec<u8>, String> {
+        let cursor = self.zip.finish().map_err(|e| e.to_string())?;
+        Ok(cursor.into_inner())
+    }
+}
+
+// 出力テキストを空行で区切った段落単位に分けたもの
+#[derive(Clone)]
+pub enum Block {
+    Heading(PlotKind, String),
+    Separator,
+    Paragraph(Vec<String>),
+}
+
+// 書き出しに使う文書と、作り直しの際に手直しを使い切れなかった区間
+pub struct Prepared {
+    pub doc: Document,
+    // プロットと出力テキストの両方が変更された区間（書き出しには出力テキストの手直しを使う）
+    pub conflicts: Vec<recompose::Conflict>,
+    // 削除・除外したプロットの区間にあった手直し（書き出しには含まれない）
+    pub removed_edits: Vec<String>,
+}
+
+impl Prepared {
+    // 書き出す前に知らせる内容（文書生成のダイアログと同じ説明）
+    pub fn warning(&self) -> Option<String> {
+        let mut lines = Vec::new();
+        if !self.conflicts.is_empty() {
+            lines.push(format!(
+                "プロットと出力テキストの両方が変更された箇所が{}件あります。書き出しには出力テキストの手直しを使います。",
+                self.conflicts.len()
+            ));
+        }
+        if !self.removed_edits.is_empty() {
+            lines.push(format!(
+                "削除・除外したプロットの区間にあった手直し（{}件）は書き出しに含まれません。",
+                self.removed_edits.len()
+            ));
+        }
+        (!lines.is_empty()).then(|| lines.join("\n"))
+    }
+}
+
+// 書き出しに使う文書。前回の文書生成の後に除外・追加・変更したプロットがあれば、その区間だけ出力テキストを作り直す
+// （手直しした区間は残す）。出力テキストが空なら今のプロットから作る。
+// 変更があるのに出力テキストの区間を対応付けられない場合は、古い内容を書き出さないようエラーを返す。
+pub fn prepare(doc: &Document) -> Result<Prepared, String> {
+    let mut doc = doc.clone();
+    let unchanged = |doc| Prepared {
+        doc,
+        conflicts: Vec::new(),
+        removed_edits: Vec::new(),
+    };
+    if doc.composed_text.trim().is_empty() {
+        doc.compose();
+        return Ok(unchanged(doc));
+    }
+    // 記録の無い古いファイルは、出力テキストがいつのプロットから作られたか分からないためそのまま使う
+    if doc.composed.sections.is_empty() {
+        return Ok(unchanged(doc));
+    }
+    match recompose::recompose(&doc) {
+        Ok(result) => {
+            let record = result.record().clone();
+            doc.set_composed(result.text(), record);
+            Ok(Prepared {
+                doc,
+                conflicts: result.conflicts,
+                removed_edits: result.removed_edits,
+            })
+        }
+        Err(_) if is_current(&doc.composed, &doc.compose_record()) => Ok(unchanged(doc)),
+        Err(e) => Err(format!(
+            "前回の文書生成の後にプロットが変更されていますが、出力テキストに反映できません（{}）。「文書生成」をやり直してください",
+            e
+        )),
+    }
+}
+
+// 前回の文書生成の記録が今のプロットから作ったものと同じか
+fn is_current(previous: &ComposeRecord, current: &ComposeRecord) -> bool {
+    previous.text() == current.text()
+        && previous.sections.iter().map(|s| s.id).eq(current.sections.iter().map(|s| s.id))
+}
+
+// 出力テキストの中の幕・章の見出し。range は改行を LF に揃えた出力テキストでの範囲（バイト単位）
+pub(crate) struct HeadingSpan {
+    pub(crate) range: Range<usize>,
+    pub(crate) kind: PlotKind,
+    pub(crate) title: String,
+}
+
+// 前回の文書生成の記録で出力テキストの区間を対応付けられれば、記録にある区間の種類から見出しを決める。
+// 記録が無いか対応付けられない場合は、幕・章のプロットと同じ文字列だけの段落を見出しとみなす。
+pub(crate) fn find_headings(doc: &Document, text: &str) -> Vec<HeadingSpan> {
+    if !doc.composed.sections.is_empty() {
+        if let Ok(ranges) = decompose::locate_ranges(&doc.composed, text) {
+            return doc
+                .composed
+                .sections
+                .iter()
+                .zip(ranges)
+                .filter(|(section, _)| section.kind.is_heading())
+                .filter_map(|(section, (_, range))| {
+                    // 書式の前後の部分（「■」など）を除き、複数行の見出しは1行にまとめる
+                    let current = &text[range.clone()];
+                    let body = section.body(current).unwrap_or(current);
+                    let title = body.lines().map(str::trim).filter(|l| !l.is_empty()).collect::<Vec<_>>().join("　");
+                    (!title.is_empty()).then_some(HeadingSpan {
+                        range,
+                        kind: section.kind,
+                        title,
+                    })
+                })
+                .collect();
+        }
+    }
+
+    let headings: HashMap<&str, PlotKind> = doc
+        .included_plots()
+        .into_iter()
+        .filter(|p| p.kind.is_heading() && !p.text.trim().is_empty())
+        .map(|p| (p.text.trim(), p.kind))
+        .collect();
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    for line in text.split('\n') {
+        lines.push((offset, line));
+        offset += line.len() + 1;
+    }
+    let blank = |i: Option<usize>| i.and_then(|i| lines.get(i)).is_none_or(|(_, l)| l.trim().is_empty());
+    lines
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| blank(i.checked_sub(1)) && blank(Some(i + 1)))
+        .filter_map(|(_, &(start, line))| {
+            let kind = *headings.get(line.trim())?;
+            Some(HeadingSpan {
+                range: start..start + line.len(),
+                kind,
+                title: line.trim().to_owned(),
+            })
+        })
+        .collect()
+}
+
+// "---" か書式の区切り線だけの行か
+pub(crate) fn is_separator(doc: &Document, line: &str) -> bool {
+    let separator = if doc.composed.sections.is_empty() {
+        doc.template.separator.trim()
+    } else {
+        doc.composed.separator.trim()
+    };
+    let line = line.trim();
+    line == "---" || (!separator.is_empty() && line == separator)
+}
+
+// 空行で区切った段落を加える。区切り線だけの段落は区切り線とする
+fn push_paragraphs(blocks: &mut Vec<Block>, text: &str, doc: &Document) {
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.split('\n').chain(std::iter::once("")) {
+        if !line.trim().is_empty() {
+            lines.push(line.to_owned());
+            continue;
+        }
+        if lines.is_empty() {
+            continue;
+        }
+        let paragraph = std::mem::take(&mut lines);
+        if paragraph.len() == 1 && is_separator(doc, &paragraph[0]) {
+            blocks.push(Block::Separator);
+        } else {
+            blocks.push(Block::Paragraph(paragraph));
+        }
+    }
+}
+
+// 幕・章の見出し（find_headings）と、その間の段落に分ける
+pub fn parse_blocks(doc: &Document) -> Vec<Block> {
+    let text = doc.composed_text.replace("\r\n", "\n");
+    let mut blocks = Vec::new();
+    let mut start = 0;
+    for heading in find_headings(doc, &text) {
+        push_paragraphs(&mut blocks, &text[start..heading.range.start], doc);
+        blocks.push(Block::Heading(heading.kind, heading.title));
+        start = heading.range.end;
+    }
+    push_paragraphs(&mut blocks, &text[start..], doc);
+    blocks
+}
+
+// シーンのタイトルを見出しとして差し込む。
+// 出力テキストの段落のまとまりが文書生成に含める本文のあるシーンと同じ数だけある場合に限り、順番に対応付ける。
+pub fn insert_scene_titles(blocks: Vec<Block>, doc: &Document) -> Vec<Block> {
+    let titles: Vec<&str> = doc
+        .included_plots()
+        .into_iter()
+        .filter(|p| !p.kind.is_heading() && !p.text.trim().is_empty())
+        .map(|p| p.title.trim())
+        .collect();
+    let starts_scene = |i: usize| {
+        matches!(blocks[i], Block::Paragraph(_)) && (i == 0 || !matches!(blocks[i - 1], Block::Paragraph(_)))
+    };
+    let scene_starts: Vec<bool> = (0..blocks.len()).map(starts_scene).collect();
+    let scene_count = scene_starts.iter().filter(|&&s| s).count();
+    if scene_count != titles.len() || titles.iter().all(|t| t.is_empty()) {
+        return blocks;
+    }
+
+    let mut result = Vec::with_capacity(blocks.len() + titles.len());
+    let mut titles = titles.into_iter();
+    for (block, starts) in blocks.into_iter().zip(scene_starts) {
+        if starts {
+            if let Some(title) = titles.next().filter(|t| !t.is_empty()) {
+                result.push(Block::Heading(PlotKind::Scene, title.to_owned()));
+            }
+        }
+        result.push(block);
+    }
+    result
+}
+
+// 幕・章は新しいページから始める（幕の直後の章など、見出しが続く場合は改ページしない）
+pub(crate) fn starts_new_page(blocks: &[Block], index: usize) -> bool {
+    let is_chapter = |block: &Block| matches!(block, Block::Heading(kind, _) if kind.is_heading());
+    index > 0 && is_chapter(&blocks[index]) && !is_chapter(&blocks[index - 1])
+}
+
+pub fn render(format: ExportFormat, doc: &Document) -> String {
+    let blocks = || insert_scene_titles(parse_blocks(doc), doc);
+    match format {
+        ExportFormat::Text => render_text(&doc.composed_text),
+        ExportFormat::Markdown => render_markdown(&blocks(), &doc.meta),
+        ExportFormat::Html => render_html(&blocks(), &doc.meta),
+    }
+}
+
+// 改行コードはLFに揃えておき、ファイルに書き出すときに encode_text で変換する
+fn render_text(text: &str) -> String {
+    text.replace("\r\n", "\n")
+}
+
+// Macなどで入力されるJIS寄りの文字を、CP932で同じ位置に割り当てられている文字に置き換える
+fn to_cp932_variant(c: char) -> char {
+    match c {
+        '〜' => '～',
+        '‖' => '∥',
+        '−' => '－',
+        '—' => '―',
+        '¢' => '￠',
+        '£' => '￡',
+        '¬' => '￢',
+        _ => c,
+    }
+}
+
+// 改行コードを変換し、指定の文字コードのバイト列にする
+pub fn encode_text(text: &str, options: &TextOptions) -> Result<Vec<u8>, String> {
+    let text = render_text(text);
+    let text = match options.line_ending {
+        LineEnding::Crlf => text.replace('\n', "\r\n"),
+        LineEnding::Lf => text,
+    };
+    match options.encoding {
+        TextEncoding::Utf8 => Ok(text.into_bytes()),
+        TextEncoding::Utf8Bom => {
+            let mut bytes = "\u{FEFF}".as_bytes().to_vec();
+            bytes.extend_from_slice(text.as_bytes());
+            Ok(bytes)
+        }
+        TextEncoding::ShiftJis => {
+            let text: String = text.chars().map(to_cp932_variant).collect();
+            let (bytes, _, had_errors) = encoding_rs::SHIFT_JIS.encode(&text);
+            if !had_errors {
+                return Ok(bytes.into_owned());
+            }
+            // 表せない文字は数値文字参照に置き換えられてしまうため、書き出さずに知らせる
+            let mut unmappable: Vec<char> = Vec::new();
+            for c in text.chars() {
+                let mut buf = [0; 4];
+                if encoding_rs::SHIFT_JIS.encode(c.encode_utf8(&mut buf)).2 && !unmappable.contains(&c) {
+                    unmappable.push(c);
+                }
+            }
+            let shown: Vec<String> = unmappable.iter().take(10).map(|c| format!("「{}」", c)).collect();
+            let more = if unmappable.len() > 10 { " ほか" } else { "" };
+            Err(format!("Shift_JISで表せない文字があります: {}{}", shown.join(""), more))
+        }
+    }
+}
+
+pub(crate) fn heading_level(kind: PlotKind) -> usize {
+    match kind {
+        PlotKind::Act => 1,
+        PlotKind::Chapter => 2,
+        PlotKind::Scene => 3,
+    }
+}
+
+// YAMLのダブルクォート文字列（改行などの制御文字もエスケープする）
+fn yaml_string(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len() + 2);
+    escaped.push('"');
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04X}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+// 行頭の記号がMarkdownの書式として解釈されないようにする
+fn escape_markdown_line(line: &str) -> String {
+    let trimmed = line.trim_start_matches(' ');
+    let indent = &line[..line.len() - trimmed.len()];
+    let digits = trimmed.chars().take_while(|c| c.is_ascii_digit()).count();
+    if digits > 0 && matches!(trimmed[digits..].chars().next(), Some('.' | ')')) {
+        return format!("{}{}\\{}", indent, &trimmed[..digits], &trimmed[digits..]);
+    }
+    match trimmed.chars().next() {
+        Some('#' | '>' | '-' | '+' | '*' | '=' | '|' | '`' | '~') => format!("{}\\{}", indent, trimmed),
+        _ => line.to_owned(),
+    }
+}
+
+fn render_markdown(blocks: &[Block], meta: &DocumentMeta) -> String {
+    let mut markdown = String::from("---\n");
+    markdown.push_str(&format!("title: {}\n", yaml_string(&meta.title)));
+    markdown.push_str(&format!("author: {}\n", yaml_string(&meta.author)));
+    markdown.push_str(&format!("date: {}\n", yaml_string(&meta.date_or_today())));
+    markdown.push_str("---\n\n");
+
+    let parts: Vec<String> = blocks
+        .iter()
+        .map(|block| match block {
+            Block::Heading(kind, text) => format!("{} {}", "#".repeat(heading_level(*kind)), text),
+            Block::Separator => "* * *".to_owned(),
+            // 改行をそのまま残すため行末に空白2つの強制改行を入れる
+            Block::Paragraph(lines) => lines
+                .iter()
+                .map(|l| escape_markdown_line(l))
+                .collect::<Vec<_>>()
+                .join("  \n"),
+        })
+        .collect();
+    markdown.push_str(&parts.join("\n\n"));
+    markdown.push('\n');
+    markdown
+}
+
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// ルビと傍点をHTMLの要素にする（EPUBのXHTMLと共用）
+pub(crate) fn render_inline_html(line: &str) -> String {
+    markup::parse_inline(line)
+        .into_iter()
+        .map(|inline| match inline {
+            Inline::Text(text) => escape_html(&text),
+            Inline::Ruby { base, ruby } => format!("<ruby>{}<rt>{}</rt></ruby>", escape_html(&base), escape_html(&ruby)),
+            Inline::Emphasis(text) => format!("<em class=\"sesame\">{}</em>", escape_html(&text)),
+        })
+        .collect()
+}
+
+pub(crate) const EMPHASIS_CSS: &str =
+    "em.sesame {\n  font-style: normal;\n  text-emphasis: filled sesame;\n  -webkit-text-emphasis: filled sesame;\n}\n";
+
+fn render_html(blocks: &[Block], meta: &DocumentMeta) -> String {
+    let mut html = String::from("<!DOCTYPE html>\n<html lang=\"ja\">\n<head>\n<meta charset=\"utf-8\">\n");
+    html.push_str(&format!("<title>{}</title>\n", escape_html(&meta.title)));
+    if !meta.author.is_empty() {
+        html.push_str(&format!("<meta name=\"author\" content=\"{}\">\n", escape_html(&meta.author)));
+    }
+    html.push_str(&format!("<style>\n{}</style>\n", EMPHASIS_CSS));
+    html.push_str("</head>\n<body>\n");
+    for block in blocks {
+        match block {
+            Block::Heading(kind, text) => {
+                let level = heading_level(*kind);
+                html.push_str(&format!("<h{}>{}</h{}>\n", level, render_inline_html(text), level));
+            }
+            Block::Separator => html.push_str("<hr>\n"),
+            Block::Paragraph(lines) => {
+                let lines: Vec<String> = lines.iter().map(|l| render_inline_html(l)).collect();
+                html.push_str(&format!("<p>{}</p>\n", lines.join("<br>\n")));
+            }
+        }
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::PlotFragment;
+    use crate::template::ComposeTemplate;
+
+    fn doc(plots: &[(PlotKind, &str)]) -> Document {
+        let plots = plots
+            .iter()
+            .enumerate()
+            .map(|(id, &(kind, text))| PlotFragment {
+                text: text.to_owned(),
+                ..PlotFragment::new(id, kind)
+            })
+            .collect();
+        Document::from_parts(plots, String::new())
+    }
+
+    fn options(encoding: TextEncoding, line_ending: LineEnding) -> TextOptions {
+        TextOptions { encoding, line_ending }
+    }
+
+    #[test]
+    fn encode_text_line_endings_and_bom() {
+        let crlf = encode_text("一\r\n二\n", &options(TextEncoding::Utf8, LineEnding::Crlf)).unwrap();
+        assert_eq!(crlf, "一\r\n二\r\n".as_bytes());
+        let lf = encode_text("一\r\n二", &options(TextEncoding::Utf8Bom, LineEnding::Lf)).unwrap();
+        assert_eq!(lf, "\u{FEFF}一\n二".as_bytes());
+    }
+
+    #[test]
+    fn encode_shift_jis() {
+        let sjis = options(TextEncoding::ShiftJis, LineEnding::Lf);
+        // JIS寄りの波ダッシュはCP932の全角チルダとして書き出す
+        let bytes = encode_text("日本語〜①", &sjis).unwrap();
+        assert_eq!(encoding_rs::SHIFT_JIS.decode(&bytes).0, "日本語～①");
+
+        let error = encode_text("絵文字😀と🍣と😀、한글", &sjis).unwrap_err();
+        assert!(error.contains("「😀」「🍣」「한」「글」"), "{}", error);
+        assert_eq!(error.matches("😀").count(), 1);
+
+        let many: String = ('가'..='갛').collect();
+        assert!(encode_text(&many, &sjis).unwrap_err().ends_with(" ほか"));
+    }
+
+    #[test]
+    fn yaml_string_escapes_control_characters() {
+        assert_eq!(yaml_string("題"), "\"題\"");
+        assert_eq!(yaml_string("a\"b\\c"), "\"a\\\"b\\\\c\"");
+        assert_eq!(yaml_string("一行目\r\n二行目\tタブ\u{7}"), "\"一行目\\r\\n二行目\\tタブ\\u0007\"");
+    }
+
+    #[test]
+    fn parse_blocks_uses_compose_record() {
+        let mut d = doc(&[
+            (PlotKind::Chapter, "第一章"),
+            (PlotKind::Scene, "本文A\n\n---\n\n続き"),
+            (PlotKind::Chapter, "第二章\n副題"),
+            (PlotKind::Scene, "本文B"),
+        ]);
+        d.template = ComposeTemplate::builtins()[2].1.clone();
+        d.compose();
+        let blocks: Vec<String> = parse_blocks(&d)
+            .into_iter()
+            .map(|block| match block {
+                Block::Heading(kind, text) => format!("{:?}:{}", kind, text),
+                Block::Separator => "---".to_owned(),
+                Block::Paragraph(lines) => lines.join("/"),
+            })
+            .collect();
+        assert_eq!(blocks, ["Chapter:第一章", "○/本文A", "---", "続き", "Chapter:第二章　副題", "○/本文B"]);
+    }
+
+    #[test]
+    fn parse_blocks_without_record_matches_heading_text() {
+        let mut d = doc(&[(PlotKind::Act, "第一部"), (PlotKind::Scene, "第一部\n本文")]);
+        d.composed_text = "第一部\n\n第一部\n本文\n\n---\n\n第一部".to_owned();
+        let kinds: Vec<&str> = parse_blocks(&d)
+            .iter()
+            .map(|block| match block {
+                Block::Heading(..) => "見出し",
+                Block::Separator => "区切り",
+                Block::Paragraph(_) => "段落",
+            })
+            .collect();
+        assert_eq!(kinds, ["見出し", "段落", "区切り", "見出し"]);
+    }
+
+    #[test]
+    fn prepare_reflects_plot_changes_after_compose() {
+        let mut d = doc(&[(PlotKind::Scene, "一"), (PlotKind::Scene, "二"), (PlotKind::Scene, "三")]);
+        d.compose();
+        d.composed_text = d.composed_text.replace("一", "一（手直し）");
+        d.plots[1].excluded = true;
+        assert_eq!(prepare(&d).unwrap().doc.composed_text, "一（手直し）\n\n---\n\n三");
+
+        // 対応付けられない出力テキストは、プロットに変更が無ければそのまま使う
+        d.plots[1].excluded = false;
+        d.compose();
+        d.composed_text = "書き直した全文".to_owned();
+        assert_eq!(prepare(&d).unwrap().doc.composed_text, "書き直した全文");
+        d.plots[2].excluded = true;
+        assert!(prepare(&d).is_err());
+
+        // 文書生成していない文書は今のプロットから作る
+        let empty = doc(&[(PlotKind::Scene, "一"), (PlotKind::Scene, "二")]);
+        assert_eq!(prepare(&empty).unwrap().doc.composed_text, "一\n\n---\n\n二");
+    }
+
+    #[test]
+    fn prepare_returns_conflicts_and_removed_edits() {
+        let mut d = doc(&[(PlotKind::Scene, "一"), (PlotKind::Scene, "二"), (PlotKind::Scene, "三")]);
+        d.compose();
+        assert!(prepare(&d).unwrap().warning().is_none());
+
+        d.composed_text = d.composed_text.replace("一", "一（手直し）").replace("三", "三（手直し）");
+        d.plots[0].text = "一（変更）".to_owned();
+        d.plots[2].excluded = true;
+        let prepared = prepare(&d).unwrap();
+        assert_eq!(prepared.doc.composed_text, "一（手直し）\n\n---\n\n二");
+        assert_eq!(prepared.conflicts.len(), 1);
+        assert_eq!(prepared.conflicts[0].generated, "一（変更）");
+        assert_eq!(prepared.removed_edits, ["三（手直し）"]);
+        let warning = prepared.warning().unwrap();
+        assert!(warning.contains("1件あります") && warning.contains("手直し（1件）"));
+    }
+
+    #[test]
+    fn markdown_front_matter_and_escapes() {
+        let mut d = doc(&[(PlotKind::Chapter, "第一章"), (PlotKind::Scene, "# 記号\n1. 番号")]);
+        d.meta.title = "題\n副題".to_owned();
+        d.meta.date = "2024-04-01".to_owned();
+        d.compose();
+        let markdown = render(ExportFormat::Markdown, &d);
+        assert!(markdown.starts_with("---\ntitle: \"題\\n副題\"\nauthor: \"\"\ndate: \"2024-04-01\"\n---\n\n"));
+        assert!(markdown.contains("## 第一章\n\n\\# 記号  \n1\\. 番号\n"));
+    }
+}
@@ -0,0 +1,241 @@
+// Word（.docx）形式での書き出し
+
+use super::{escape_html, heading_level, insert_scene_titles, parse_blocks, starts_new_page, Block, Package};
+use crate::document::{Document, DocumentMeta};
+use crate::markup::{self, Inline};
+
+const CONTENT_TYPES: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+<Types xmlns=\"http://schemas.openxmlformats.org/package/2006/content-types\">\n\
+<Default Extension=\"rels\" ContentType=\"application/vnd.openxmlformats-package.relationships+xml\"/>\n\
+<Default Extension=\"xml\" ContentType=\"application/xml\"/>\n\
+<Override PartName=\"/word/document.xml\" ContentType=\"application/vnd.openxmlformats-officedocument.wordprocessingml.document.main+xml\"/>\n\
+<Override PartName=\"/word/styles.xml\" ContentType=\"application/vnd.openxmlformats-officedocument.wordprocessingml.styles+xml\"/>\n\
+<Override PartName=\"/docProps/core.xml\" ContentType=\"application/vnd.openxmlformats-package.core-properties+xml\"/>\n\
+</Types>\n";
+
+const ROOT_RELS: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+<Relationships xmlns=\"http://schemas.openxmlformats.org/package/2006/relationships\">\n\
+<Relationship Id=\"rId1\" Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument\" Target=\"word/document.xml\"/>\n\
+<Relationship Id=\"rId2\" Type=\"http://schemas.openxmlformats.org/package/2006/relationships/metadata/core-properties\" Target=\"docProps/core.xml\"/>\n\
+</Relationships>\n";
+
+const DOCUMENT_RELS: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+<Relationships xmlns=\"http://schemas.openxmlformats.org/package/2006/relationships\">\n\
+<Relationship Id=\"rId1\" Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/styles\" Target=\"styles.xml\"/>\n\
+</Relationships>\n";
+
+// 本文は10.5pt（サイズは半ポイント単位）
+const BODY_SIZE: u32 = 21;
+const RUBY_SIZE: u32 = 10;
+
+fn heading_style(level: usize, size: u32) -> String {
+    format!(
+        "<w:style w:type=\"paragraph\" w:styleId=\"Heading{0}\">\
+         <w:name w:val=\"heading {0}\"/><w:basedOn w:val=\"Normal\"/><w:next w:val=\"Normal\"/><w:qFormat/>\
+         <w:pPr><w:keepNext/><w:spacing w:before=\"360\" w:after=\"120\"/><w:outlineLvl w:val=\"{1}\"/></w:pPr>\
+         <w:rPr><w:b/><w:sz w:val=\"{2}\"/><w:szCs w:val=\"{2}\"/></w:rPr></w:style>\n",
+        level,
+        level - 1,
+        size
+    )
+}
+
+fn render_styles() -> String {
+    let mut styles = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+         <w:styles xmlns:w=\"http://schemas.openxmlformats.org/wordprocessingml/2006/main\">\n",
+    );
+    styles.push_str(&format!(
+        "<w:docDefaults><w:rPrDefault><w:rPr>\
+         <w:rFonts w:ascii=\"游明朝\" w:eastAsia=\"游明朝\" w:hAnsi=\"游明朝\"/>\
+         <w:sz w:val=\"{0}\"/><w:szCs w:val=\"{0}\"/><w:lang w:val=\"ja-JP\" w:eastAsia=\"ja-JP\"/>\
+         </w:rPr></w:rPrDefault></w:docDefaults>\n",
+        BODY_SIZE
+    ));
+    styles.push_str(
+        "<w:style w:type=\"paragraph\" w:default=\"1\" w:styleId=\"Normal\"><w:name w:val=\"Normal\"/><w:qFormat/></w:style>\n",
+    );
+    styles.push_str(&heading_style(1, 32));
+    styles.push_str(&heading_style(2, 28));
+    styles.push_str(&heading_style(3, 24));
+    styles.push_str(
+        "<w:style w:type=\"paragraph\" w:customStyle=\"1\" w:styleId=\"SceneBreak\">\
+         <w:name w:val=\"Scene Break\"/><w:basedOn w:val=\"Normal\"/><w:next w:val=\"Normal\"/>\
+         <w:pPr><w:jc w:val=\"center\"/><w:spacing w:before=\"240\" w:after=\"240\"/></w:pPr></w:style>\n",
+    );
+    styles.push_str("</w:styles>\n");
+    styles
+}
+
+fn text_run(text: &str) -> String {
+    format!("<w:r><w:t xml:space=\"preserve\">{}</w:t></w:r>", escape_html(text))
+}
+
+fn emphasis_run(text: &str) -> String {
+    format!("<w:r><w:rPr><w:em w:val=\"dot\"/></w:rPr><w:t xml:space=\"preserve\">{}</w:t></w:r>", escape_html(text))
+}
+
+fn render_runs(line: &str) -> String {
+    markup::parse_inline(line)
+        .into_iter()
+        .map(|inline| match inline {
+            Inline::Text(text) => text_run(&text),
+            Inline::Emphasis(text) => emphasis_run(&text),
+            Inline::Ruby { base, ruby } => format!(
+                "<w:r><w:ruby><w:rubyPr><w:rubyAlign w:val=\"distributeSpace\"/>\
+                 <w:hps w:val=\"{}\"/><w:hpsRaise w:val=\"{}\"/><w:hpsBaseText w:val=\"{}\"/><w:lid w:val=\"ja-JP\"/></w:rubyPr>\
+                 <w:rt><w:r><w:rPr><w:sz w:val=\"{}\"/></w:rPr><w:t>{}</w:t></w:r></w:rt>\
+                 <w:rubyBase>{}</w:rubyBase></w:ruby></w:r>",
+                RUBY_SIZE,
+                BODY_SIZE - 2,
+                BODY_SIZE,
+                RUBY_SIZE,
+                escape_html(&ruby),
+                text_run(&base)
+            ),
+        })
+        .collect()
+}
+
+fn paragraph(properties: &str, runs: &str) -> String {
+    if properties.is_empty() {
+        format!("<w:p>{}</w:p>\n", runs)
+    } else {
+        format!("<w:p><w:pPr>{}</w:pPr>{}</w:p>\n", properties, runs)
+    }
+}
+
+fn render_document(blocks: &[Block]) -> String {
+    let mut body = String::new();
+    for (i, block) in blocks.iter().enumerate() {
+        match block {
+            Block::Heading(kind, text) => {
+                let mut properties = format!("<w:pStyle w:val=\"Heading{}\"/>", heading_level(*kind));
+                if starts_new_page(blocks, i) {
+                    properties.push_str("<w:pageBreakBefore/>");
+                }
+                body.push_str(&paragraph(&properties, &render_runs(text)));
+            }
+            Block::Separator => {
+                body.push_str(&paragraph("<w:pStyle w:val=\"SceneBreak\"/>", &text_run("＊　＊　＊")));
+            }
+            Block::Paragraph(lines) => {
+                // 1行を1段落とし、空行で区切られた段落の間には空の段落を入れる
+                for line in lines {
+                    body.push_str(&paragraph("", &render_runs(line)));
+                }
+                if matches!(blocks.get(i + 1), Some(Block::Paragraph(_))) {
+                    body.push_str(&paragraph("", ""));
+                }
+            }
+        }
+    }
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+         <w:document xmlns:w=\"http://schemas.openxmlformats.org/wordprocessingml/2006/main\">\n\
+         <w:body>\n{}<w:sectPr><w:pgSz w:w=\"11906\" w:h=\"16838\"/>\
+         <w:pgMar w:top=\"1701\" w:right=\"1701\" w:bottom=\"1701\" w:left=\"1701\" w:header=\"851\" w:footer=\"992\" w:gutter=\"0\"/>\
+         </w:sectPr>\n</w:body>\n</w:document>\n",
+        body
+    )
+}
+
+fn render_core(meta: &DocumentMeta) -> String {
+    let mut core = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+         <cp:coreProperties xmlns:cp=\"http://schemas.openxmlformats.org/package/2006/metadata/core-properties\" \
+         xmlns:dc=\"http://purl.org/dc/elements/1.1/\" xmlns:dcterms=\"http://purl.org/dc/terms/\" \
+         xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\">\n",
+    );
+    if !meta.title.is_empty() {
+        core.push_str(&format!("<dc:title>{}</dc:title>\n", escape_html(&meta.title)));
+    }
+    if !meta.author.is_empty() {
+        core.push_str(&format!("<dc:creator>{}</dc:creator>\n", escape_html(&meta.author)));
+    }
+    core.push_str("<dc:language>ja-JP</dc:language>\n");
+    core.push_str(&format!(
+        "<dcterms:created xsi:type=\"dcterms:W3CDTF\">{}</dcterms:created>\n",
+        chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ")
+    ));
+    core.push_str("</cp:coreProperties>\n");
+    core
+}
+
+pub fn build(doc: &Document) -> Result<Vec<u8>, String> {
+    let blocks = insert_scene_titles(parse_blocks(doc), doc);
+
+    let mut package = Package::new();
+    package.add("[Content_Types].xml", CONTENT_TYPES.as_bytes())?;
+    package.add("_rels/.rels", ROOT_RELS.as_bytes())?;
+    package.add("word/_rels/document.xml.rels", DOCUMENT_RELS.as_bytes())?;
+    package.add("word/document.xml", render_document(&blocks).as_bytes())?;
+    package.add("word/styles.xml", render_styles().as_bytes())?;
+    package.add("docProps/core.xml", render_core(&doc.meta).as_bytes())?;
+    package.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::{PlotFragment, PlotKind};
+    use std::io::Read;
+    use zip::{CompressionMethod, ZipArchive};
+
+    fn doc() -> Document {
+        let plots = [(PlotKind::Chapter, "第一章"), (PlotKind::Scene, "a<b & c>d\n｜漢字《かんじ》と強調［＃「強調」に傍点］")]
+            .iter()
+            .enumerate()
+            .map(|(id, &(kind, text))| PlotFragment {
+                text: text.to_owned(),
+                ..PlotFragment::new(id, kind)
+            })
+            .collect();
+        let mut doc = Document::from_parts(plots, String::new());
+        doc.meta.title = "作品<1>".to_owned();
+        doc.compose();
+        doc
+    }
+
+    // 書き出したファイルの中身を、格納順に名前・圧縮方式・内容の組にする
+    fn entries(data: Vec<u8>) -> Vec<(String, CompressionMethod, String)> {
+        let mut archive = ZipArchive::new(std::io::Cursor::new(data)).unwrap();
+        (0..archive.len())
+            .map(|i| {
+                let mut file = archive.by_index(i).unwrap();
+                let mut text = String::new();
+                file.read_to_string(&mut text).unwrap();
+                (file.name().to_owned(), file.compression(), text)
+            })
+            .collect()
+    }
+
+    fn entry<'a>(entries: &'a [(String, CompressionMethod, String)], name: &str) -> &'a str {
+        entries.iter().find(|(n, _, _)| n == name).map(|(_, _, text)| text.as_str()).unwrap()
+    }
+
+    #[test]
+    fn build_writes_package_entries() {
+        let entries = entries(build(&doc()).unwrap());
+        let names: Vec<&str> = entries.iter().map(|(name, _, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "[Content_Types].xml",
+                "_rels/.rels",
+                "word/_rels/document.xml.rels",
+                "word/document.xml",
+                "word/styles.xml",
+                "docProps/core.xml"
+            ]
+        );
+        assert!(entry(&entries, "docProps/core.xml").contains("<dc:title>作品&lt;1&gt;</dc:title>"));
+
+        let document = entry(&entries, "word/document.xml");
+        assert!(document.contains("<w:pStyle w:val=\"Heading2\"/>"));
+        assert!(document.contains(">a&lt;b &amp; c&gt;d</w:t>"));
+        assert!(!document.contains("a<b"));
+        assert!(document.contains("<w:t>かんじ</w:t></w:r></w:rt><w:rubyBase><w:r><w:t xml:space=\"preserve\">漢字</w:t>"));
+        assert!(document.contains("<w:em w:val=\"dot\"/></w:rPr><w:t xml:space=\"preserve\">強調</w:t>"));
+    }
+}
@@ -0,0 +1,452 @@
+// EPUB 3 形式での書き出し（縦書き・横書き、目次、ルビ、表紙画像）
+
+use super::{escape_html, insert_scene_titles, parse_blocks, render_inline_html, Block, Package, EMPHASIS_CSS};
+use crate::document::{Document, DocumentMeta, PlotKind};
+use crate::markup;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Default)]
+pub struct EpubOptions {
+    pub vertical: bool,
+    pub cover_image: Option<PathBuf>,
+}
+
+// 幕・章の見出しごとに分けた本文ファイル
+struct Section {
+    title: String,
+    blocks: Vec<Block>,
+}
+
+// 目次の項目（本文ファイルの番号と見出しのid）
+struct TocEntry {
+    kind: PlotKind,
+    title: String,
+    section: usize,
+    anchor: String,
+}
+
+fn split_sections(blocks: Vec<Block>) -> Vec<Section> {
+    let mut sections: Vec<Section> = Vec::new();
+    for block in blocks {
+        let starts_section = matches!(&block, Block::Heading(kind, _) if *kind != PlotKind::Scene);
+        let current_empty = sections.last().is_none_or(|s| s.blocks.is_empty());
+        if sections.is_empty() || (starts_section && !current_empty) {
+            sections.push(Section {
+                title: String::new(),
+                blocks: Vec::new(),
+            });
+        }
+        let section = sections.last_mut().unwrap();
+        if let Block::Heading(_, text) = &block {
+            if section.title.is_empty() {
+                section.title = markup::plain_text(text);
+            }
+        }
+        section.blocks.push(block);
+    }
+    sections
+}
+
+fn section_file(index: usize) -> String {
+    format!("text/section{:03}.xhtml", index + 1)
+}
+
+fn xhtml_page(title: &str, css_path: &str, body: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <!DOCTYPE html>\n\
+         <html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\" xml:lang=\"ja\" lang=\"ja\">\n\
+         <head>\n<meta charset=\"UTF-8\"/>\n<title>{}</title>\n\
+         <link rel=\"stylesheet\" type=\"text/css\" href=\"{}\"/>\n</head>\n\
+         <body>\n{}</body>\n</html>\n",
+        escape_html(title),
+        css_path,
+        body
+    )
+}
+
+fn render_section(section: &Section, index: usize, toc: &mut Vec<TocEntry>) -> String {
+    let mut body = String::from("<section>\n");
+    for (i, block) in section.blocks.iter().enumerate() {
+        match block {
+            Block::Heading(kind, text) => {
+                let anchor = format!("h{}", toc.len() + 1);
+                let level = super::heading_level(*kind);
+                body.push_str(&format!("<h{} id=\"{}\">{}</h{}>\n", level, anchor, render_inline_html(text), level));
+                toc.push(TocEntry {
+                    kind: *kind,
+                    title: markup::plain_text(text),
+                    section: index,
+                    anchor,
+                });
+            }
+            Block::Separator => body.push_str("<hr/>\n"),
+            Block::Paragraph(lines) => {
+                // 1行を1段落とし、空行で区切られた段落の間には空行を入れる
+                for line in lines {
+                    body.push_str(&format!("<p>{}</p>\n", render_inline_html(line)));
+                }
+                if matches!(section.blocks.get(i + 1), Some(Block::Paragraph(_))) {
+                    body.push_str("<p><br/></p>\n");
+                }
+            }
+        }
+    }
+    body.push_str("</section>\n");
+    xhtml_page(&section.title, "../style.css", &body)
+}
+
+fn render_nav(meta_title: &str, sections: &[Section], toc: &[TocEntry]) -> String {
+    let mut body = String::from("<nav epub:type=\"toc\" id=\"toc\">\n<h1>目次</h1>\n<ol>\n");
+    if toc.is_empty() {
+        let title = if meta_title.is_empty() { "本文" } else { meta_title };
+        body.push_str(&format!("<li><a href=\"{}\">{}</a></li>\n", section_file(0), escape_html(title)));
+    } else {
+        // 目次の先頭より前に本文がある場合はそこへの項目も作る
+        if toc[0].section > 0 || !matches!(sections[0].blocks.first(), Some(Block::Heading(..))) {
+            body.push_str(&format!("<li><a href=\"{}\">本文</a></li>\n", section_file(0)));
+        }
+        // 開いている項目の階層と、その中に子の一覧を開いたかどうか
+        let mut open: Vec<(usize, bool)> = Vec::new();
+        let close = |body: &mut String, (_, has_children): (usize, bool)| {
+            if has_children {
+                body.push_str("</ol>\n");
+            }
+            body.push_str("</li>\n");
+        };
+        for entry in toc {
+            let level = super::heading_level(entry.kind);
+            while let Some(&last) = open.last().filter(|(l, _)| *l >= level) {
+                open.pop();
+                close(&mut body, last);
+            }
+            if let Some(parent) = open.last_mut().filter(|(_, has_children)| !has_children) {
+                parent.1 = true;
+                body.push_str("<ol>\n");
+            }
+            body.push_str(&format!(
+                "<li><a href=\"{}#{}\">{}</a>\n",
+                section_file(entry.section),
+                entry.anchor,
+                escape_html(&entry.title)
+            ));
+            open.push((level, false));
+        }
+        while let Some(last) = open.pop() {
+            close(&mut body, last);
+        }
+    }
+    body.push_str("</ol>\n</nav>\n");
+    xhtml_page("目次", "style.css", &body)
+}
+
+fn render_css(vertical: bool) -> String {
+    let writing_mode = if vertical { "vertical-rl" } else { "horizontal-tb" };
+    format!(
+        "@charset \"UTF-8\";\n\
+         html {{\n  writing-mode: {0};\n  -webkit-writing-mode: {0};\n  -epub-writing-mode: {0};\n}}\n\
+         body {{\n  font-family: serif;\n  line-height: 1.75;\n}}\n\
+         p {{\n  margin: 0;\n}}\n\
+         h1, h2, h3 {{\n  font-weight: bold;\n  margin: 2em 0 1em;\n}}\n\
+         hr {{\n  border: none;\n  margin: 1em 0;\n}}\n\
+         hr::after {{\n  content: \"＊　＊　＊\";\n}}\n\
+         rt {{\n  font-size: 0.5em;\n}}\n\
+         nav ol {{\n  list-style: none;\n  padding-left: 0;\n}}\n\
+         nav ol ol {{\n  padding-left: 1em;\n}}\n\
+         .cover {{\n  margin: 0;\n  padding: 0;\n  text-align: center;\n}}\n\
+         .cover img {{\n  max-width: 100%;\n  max-height: 100%;\n}}\n\
+         {1}",
+        writing_mode, EMPHASIS_CSS
+    )
+}
+
+fn cover_media_type(path: &Path) -> Result<(&'static str, &'static str), String> {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+    match extension.as_str() {
+        "jpg" | "jpeg" => Ok(("jpg", "image/jpeg")),
+        "png" => Ok(("png", "image/png")),
+        "gif" => Ok(("gif", "image/gif")),
+        _ => Err(format!("表紙画像はJPEG・PNG・GIFのいずれかを指定してください: {}", path.display())),
+    }
+}
+
+// 書籍の識別子。EPUBごとに異なればよいので、文書の情報と現在時刻から作る
+fn book_identifier(meta: &DocumentMeta) -> String {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    meta.title.hash(&mut hasher);
+    meta.author.hash(&mut hasher);
+    chrono::Local::now().timestamp_nanos_opt().hash(&mut hasher);
+    let high = hasher.finish();
+    high.rotate_left(17).hash(&mut hasher);
+    let low = hasher.finish();
+    format!(
+        "urn:uuid:{:08x}-{:04x}-4{:03x}-{:04x}-{:012x}",
+        high >> 32,
+        (high >> 16) & 0xffff,
+        high & 0x0fff,
+        (low >> 48) & 0x3fff | 0x8000,
+        low & 0xffff_ffff_ffff
+    )
+}
+
+// 「2024-04-01」「2024/4/1」「2024-04」「2024」のような日付を W3CDTF の形式にする
+fn w3cdtf_date(date: &str) -> Option<String> {
+    let parts: Vec<&str> = date.trim().split(['-', '/']).collect();
+    if parts.iter().any(|p| p.is_empty() || !p.chars().all(|c| c.is_ascii_digit())) {
+        return None;
+    }
+    let numbers: Vec<u32> = parts.iter().map(|p| p.parse().ok()).collect::<Option<_>>()?;
+    match (numbers.as_slice(), parts[0].len()) {
+        ([year], 4) => Some(format!("{:04}", year)),
+        ([year, month], 4) if (1..=12).contains(month) => Some(format!("{:04}-{:02}", year, month)),
+        ([year, month, day], 4) => {
+            chrono::NaiveDate::from_ymd_opt(*year as i32, *month, *day).map(|d| d.format("%Y-%m-%d").to_string())
+        }
+        _ => None,
+    }
+}
+
+fn render_opf(
+    meta: &DocumentMeta,
+    options: &EpubOptions,
+    section_count: usize,
+    cover: Option<(&str, &str)>,
+) -> String {
+    let title = if meta.title.is_empty() { "無題" } else { meta.title.as_str() };
+    let mut opf = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" unique-identifier=\"book-id\" xml:lang=\"ja\">\n\
+         <metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n",
+    );
+    opf.push_str(&format!("<dc:identifier id=\"book-id\">{}</dc:identifier>\n", book_identifier(meta)));
+    opf.push_str(&format!("<dc:title>{}</dc:title>\n", escape_html(title)));
+    if !meta.author.is_empty() {
+        opf.push_str(&format!("<dc:creator>{}</dc:creator>\n", escape_html(&meta.author)));
+    }
+    opf.push_str("<dc:language>ja</dc:language>\n");
+    // dc:date は W3CDTF の形式に限られるため、「令和六年春」のような日付は書き出さない
+    if let Some(date) = w3cdtf_date(&meta.date_or_today()) {
+        opf.push_str(&format!("<dc:date>{}</dc:date>\n", date));
+    }
+    opf.push_str(&format!(
+        "<meta property=\"dcterms:modified\">{}</meta>\n",
+        chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ")
+    ));
+    if options.vertical {
+        opf.push_str("<meta name=\"primary-writing-mode\" content=\"vertical-rl\"/>\n");
+    }
+    if cover.is_some() {
+        opf.push_str("<meta name=\"cover\" content=\"cover-image\"/>\n");
+    }
+    opf.push_str("</metadata>\n<manifest>\n");
+    opf.push_str("<item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\"/>\n");
+    opf.push_str("<item id=\"style\" href=\"style.css\" media-type=\"text/css\"/>\n");
+    if let Some((extension, media_type)) = cover {
+        opf.push_str(&format!(
+            "<item id=\"cover-image\" href=\"images/cover.{}\" media-type=\"{}\" properties=\"cover-image\"/>\n",
+            extension, media_type
+        ));
+        opf.push_str("<item id=\"cover\" href=\"text/cover.xhtml\" media-type=\"application/xhtml+xml\"/>\n");
+    }
+    for i in 0..section_count {
+        opf.push_str(&format!(
+            "<item id=\"section{:03}\" href=\"{}\" media-type=\"application/xhtml+xml\"/>\n",
+            i + 1,
+            section_file(i)
+        ));
+    }
+    let direction = if options.vertical { "rtl" } else { "ltr" };
+    opf.push_str(&format!("</manifest>\n<spine page-progression-direction=\"{}\">\n", direction));
+    if cover.is_some() {
+        opf.push_str("<itemref idref=\"cover\"/>\n");
+    }
+    for i in 0..section_count {
+        opf.push_str(&format!("<itemref idref=\"section{:03}\"/>\n", i + 1));
+    }
+    opf.push_str("</spine>\n</package>\n");
+    opf
+}
+
+const CONTAINER_XML: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+<container version=\"1.0\" xmlns=\"urn:oasis:names:tc:opendocument:xmlns:container\">\n\
+<rootfiles>\n\
+<rootfile full-path=\"OEBPS/content.opf\" media-type=\"application/oebps-package+xml\"/>\n\
+</rootfiles>\n\
+</container>\n";
+
+pub fn build(doc: &Document, options: &EpubOptions) -> Result<Vec<u8>, String> {
+    let blocks = insert_scene_titles(parse_blocks(doc), doc);
+    let mut sections = split_sections(blocks);
+    if sections.is_empty() {
+        sections.push(Section {
+            title: String::new(),
+            blocks: Vec::new(),
+        });
+    }
+    for section in sections.iter_mut().filter(|s| s.title.is_empty()) {
+        section.title = doc.meta.title.clone();
+    }
+
+    let cover = match &options.cover_image {
+        Some(path) => {
+            let (extension, media_type) = cover_media_type(path)?;
+            let data = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            Some((extension, media_type, data))
+        }
+        None => None,
+    };
+
+    let mut toc = Vec::new();
+    let pages: Vec<String> = sections
+        .iter()
+        .enumerate()
+        .map(|(i, section)| render_section(section, i, &mut toc))
+        .collect();
+
+    let mut package = Package::new();
+    package.add_stored("mimetype", b"application/epub+zip")?;
+    package.add("META-INF/container.xml", CONTAINER_XML.as_bytes())?;
+    let opf = render_opf(&doc.meta, options, pages.len(), cover.as_ref().map(|(e, m, _)| (*e, *m)));
+    package.add("OEBPS/content.opf", opf.as_bytes())?;
+    package.add("OEBPS/nav.xhtml", render_nav(&doc.meta.title, &sections, &toc).as_bytes())?;
+    package.add("OEBPS/style.css", render_css(options.vertical).as_bytes())?;
+    if let Some((extension, _, data)) = &cover {
+        package.add_stored(&format!("OEBPS/images/cover.{}", extension), data)?;
+        let body = format!("<div class=\"cover\"><img src=\"../images/cover.{}\" alt=\"表紙\"/></div>\n", extension);
+        package.add("OEBPS/text/cover.xhtml", xhtml_page("表紙", "../style.css", &body).as_bytes())?;
+    }
+    for (i, page) in pages.iter().enumerate() {
+        package.add(&format!("OEBPS/{}", section_file(i)), page.as_bytes())?;
+    }
+
+    package.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::PlotFragment;
+    use std::io::Read;
+    use zip::{CompressionMethod, ZipArchive};
+
+    fn doc() -> Document {
+        let plots = [
+            (PlotKind::Act, "第一幕"),
+            (PlotKind::Chapter, "第一章"),
+            (PlotKind::Scene, "本文A"),
+            (PlotKind::Chapter, "第二章"),
+            (PlotKind::Scene, "本文B"),
+        ]
+        .iter()
+        .enumerate()
+        .map(|(id, &(kind, text))| PlotFragment {
+            text: text.to_owned(),
+            ..PlotFragment::new(id, kind)
+        })
+        .collect();
+        let mut doc = Document::from_parts(plots, String::new());
+        doc.meta.title = "作品".to_owned();
+        doc.compose();
+        doc
+    }
+
+    // 格納順に名前・圧縮方式・内容の組にする
+    fn entries(data: Vec<u8>) -> Vec<(String, CompressionMethod, Vec<u8>)> {
+        let mut archive = ZipArchive::new(std::io::Cursor::new(data)).unwrap();
+        (0..archive.len())
+            .map(|i| {
+                let mut file = archive.by_index(i).unwrap();
+                let mut data = Vec::new();
+                file.read_to_end(&mut data).unwrap();
+                (file.name().to_owned(), file.compression(), data)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn split_sections_at_acts_and_chapters() {
+        let sections = split_sections(parse_blocks(&doc()));
+        let titles: Vec<&str> = sections.iter().map(|s| s.title.as_str()).collect();
+        assert_eq!(titles, ["第一幕", "第一章", "第二章"]);
+        assert_eq!(sections[0].blocks.len(), 1);
+        assert!(matches!(sections[1].blocks.as_slice(), [Block::Heading(PlotKind::Chapter, _), Block::Paragraph(_)]));
+    }
+
+    #[test]
+    fn build_nests_chapters_under_acts() {
+        let options = EpubOptions {
+            vertical: true,
+            cover_image: None,
+        };
+        let entries = entries(build(&doc(), &options).unwrap());
+        let names: Vec<&str> = entries.iter().map(|(name, _, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "mimetype",
+                "META-INF/container.xml",
+                "OEBPS/content.opf",
+                "OEBPS/nav.xhtml",
+                "OEBPS/style.css",
+                "OEBPS/text/section001.xhtml",
+                "OEBPS/text/section002.xhtml",
+                "OEBPS/text/section003.xhtml"
+            ]
+        );
+        assert_eq!((entries[0].1, entries[0].2.as_slice()), (CompressionMethod::Stored, b"application/epub+zip".as_slice()));
+
+        let nav = String::from_utf8(entries[3].2.clone()).unwrap();
+        let toc = "<ol>\n<li><a href=\"text/section001.xhtml#h1\">第一幕</a>\n\
+                   <ol>\n<li><a href=\"text/section002.xhtml#h2\">第一章</a>\n</li>\n\
+                   <li><a href=\"text/section003.xhtml#h3\">第二章</a>\n</li>\n</ol>\n</li>\n</ol>\n</nav>";
+        assert!(nav.contains(toc), "{}", nav);
+        let section = String::from_utf8(entries[6].2.clone()).unwrap();
+        assert!(section.contains("<h2 id=\"h2\">第一章</h2>\n<p>本文A</p>"));
+    }
+
+    #[test]
+    fn build_adds_cover_image() {
+        let path = std::env::temp_dir().join(format!("story_composer_cover_{}.png", std::process::id()));
+        std::fs::write(&path, b"png").unwrap();
+        let options = EpubOptions {
+            vertical: false,
+            cover_image: Some(path.clone()),
+        };
+        let result = build(&doc(), &options);
+        std::fs::remove_file(&path).unwrap();
+        let entries = entries(result.unwrap());
+        let cover = entries.iter().find(|(name, _, _)| name == "OEBPS/images/cover.png").unwrap();
+        assert_eq!(cover.2, b"png");
+        assert!(entries.iter().any(|(name, _, _)| name == "OEBPS/text/cover.xhtml"));
+        let opf = String::from_utf8(entries[2].2.clone()).unwrap();
+        assert!(opf.contains("images/cover.png"));
+    }
+
+    #[test]
+    fn w3cdtf_dates() {
+        assert_eq!(w3cdtf_date("2024-04-01").as_deref(), Some("2024-04-01"));
+        assert_eq!(w3cdtf_date(" 2024/4/1 ").as_deref(), Some("2024-04-01"));
+        assert_eq!(w3cdtf_date("2024-4").as_deref(), Some("2024-04"));
+        assert_eq!(w3cdtf_date("2024").as_deref(), Some("2024"));
+        for date in ["令和六年春", "2024-02-30", "2024-13", "24-04-01", "2024年4月1日", "2024--01", ""] {
+            assert_eq!(w3cdtf_date(date), None, "{}", date);
+        }
+    }
+
+    #[test]
+    fn opf_omits_free_text_date() {
+        let mut meta = DocumentMeta {
+            title: "題".to_owned(),
+            ..Default::default()
+        };
+        let options = EpubOptions {
+            vertical: false,
+            cover_image: None,
+        };
+        meta.date = "令和六年春".to_owned();
+        assert!(!render_opf(&meta, &options, 1, None).contains("<dc:date>"));
+        meta.date = "2024/4/1".to_owned();
+        assert!(render_opf(&meta, &options, 1, None).contains("<dc:date>2024-04-01</dc:date>"));
+    }
+}
@@ -0,0 +1,225 @@
+// OpenDocument テキスト（.odt）形式での書き出し
+
+use super::{escape_html, heading_level, insert_scene_titles, parse_blocks, starts_new_page, Block, Package};
+use crate::document::{Document, DocumentMeta};
+use crate::markup::{self, Inline};
+
+const MIMETYPE: &[u8] = b"application/vnd.oasis.opendocument.text";
+
+const MANIFEST: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+<manifest:manifest xmlns:manifest=\"urn:oasis:names:tc:opendocument:xmlns:manifest:1.0\" manifest:version=\"1.3\">\n\
+<manifest:file-entry manifest:full-path=\"/\" manifest:media-type=\"application/vnd.oasis.opendocument.text\" manifest:version=\"1.3\"/>\n\
+<manifest:file-entry manifest:full-path=\"content.xml\" manifest:media-type=\"text/xml\"/>\n\
+<manifest:file-entry manifest:full-path=\"styles.xml\" manifest:media-type=\"text/xml\"/>\n\
+<manifest:file-entry manifest:full-path=\"meta.xml\" manifest:media-type=\"text/xml\"/>\n\
+</manifest:manifest>\n";
+
+const NAMESPACES: &str = "xmlns:office=\"urn:oasis:names:tc:opendocument:xmlns:office:1.0\" \
+xmlns:style=\"urn:oasis:names:tc:opendocument:xmlns:style:1.0\" \
+xmlns:text=\"urn:oasis:names:tc:opendocument:xmlns:text:1.0\" \
+xmlns:fo=\"urn:oasis:names:tc:opendocument:xmlns:xsl-fo-compatible:1.0\" \
+xmlns:dc=\"http://purl.org/dc/elements/1.1/\" \
+xmlns:meta=\"urn:oasis:names:tc:opendocument:xmlns:meta:1.0\" \
+office:version=\"1.3\"";
+
+fn heading_style(level: usize, size: &str) -> String {
+    format!(
+        "<style:style style:name=\"Heading_20_{0}\" style:display-name=\"Heading {0}\" style:family=\"paragraph\" \
+         style:parent-style-name=\"Heading\" style:next-style-name=\"Standard\" style:default-outline-level=\"{0}\" style:class=\"text\">\
+         <style:text-properties fo:font-size=\"{1}\" fo:font-weight=\"bold\" style:font-size-asian=\"{1}\" style:font-weight-asian=\"bold\"/>\
+         </style:style>\n",
+        level, size
+    )
+}
+
+fn render_styles() -> String {
+    let mut styles = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<office:document-styles {}>\n<office:styles>\n",
+        NAMESPACES
+    );
+    styles.push_str(
+        "<style:default-style style:family=\"paragraph\">\
+         <style:text-properties fo:font-size=\"10.5pt\" style:font-size-asian=\"10.5pt\" fo:language=\"ja\" fo:country=\"JP\" \
+         style:language-asian=\"ja\" style:country-asian=\"JP\" style:font-name-asian=\"游明朝\"/>\
+         </style:default-style>\n\
+         <style:style style:name=\"Standard\" style:family=\"paragraph\" style:class=\"text\"/>\n\
+         <style:style style:name=\"Heading\" style:family=\"paragraph\" style:parent-style-name=\"Standard\" \
+         style:next-style-name=\"Standard\" style:class=\"text\">\
+         <style:paragraph-properties fo:margin-top=\"0.6cm\" fo:margin-bottom=\"0.2cm\" fo:keep-with-next=\"always\"/>\
+         </style:style>\n",
+    );
+    styles.push_str(&heading_style(1, "16pt"));
+    styles.push_str(&heading_style(2, "14pt"));
+    styles.push_str(&heading_style(3, "12pt"));
+    styles.push_str(
+        "<style:style style:name=\"Scene_20_Break\" style:display-name=\"Scene Break\" style:family=\"paragraph\" \
+         style:parent-style-name=\"Standard\" style:class=\"text\">\
+         <style:paragraph-properties fo:text-align=\"center\" fo:margin-top=\"0.4cm\" fo:margin-bottom=\"0.4cm\"/>\
+         </style:style>\n",
+    );
+    styles.push_str("</office:styles>\n</office:document-styles>\n");
+    styles
+}
+
+fn render_inline(line: &str) -> String {
+    markup::parse_inline(line)
+        .into_iter()
+        .map(|inline| match inline {
+            Inline::Text(text) => escape_html(&text),
+            Inline::Emphasis(text) => format!("<text:span text:style-name=\"Sesame\">{}</text:span>", escape_html(&text)),
+            Inline::Ruby { base, ruby } => format!(
+                "<text:ruby><text:ruby-base>{}</text:ruby-base><text:ruby-text>{}</text:ruby-text></text:ruby>",
+                escape_html(&base),
+                escape_html(&ruby)
+            ),
+        })
+        .collect()
+}
+
+fn render_content(blocks: &[Block]) -> String {
+    let mut body = String::new();
+    for (i, block) in blocks.iter().enumerate() {
+        match block {
+            Block::Heading(kind, text) => {
+                let level = heading_level(*kind);
+                // 改ページは見出しのスタイルを継いだ自動スタイルで指定する
+                let style = if starts_new_page(blocks, i) {
+                    format!("PageBreak{}", level)
+                } else {
+                    format!("Heading_20_{}", level)
+                };
+                body.push_str(&format!(
+                    "<text:h text:style-name=\"{}\" text:outline-level=\"{}\">{}</text:h>\n",
+                    style,
+                    level,
+                    render_inline(text)
+                ));
+            }
+            Block::Separator => {
+                body.push_str("<text:p text:style-name=\"Scene_20_Break\">＊　＊　＊</text:p>\n");
+            }
+            Block::Paragraph(lines) => {
+                // 1行を1段落とし、空行で区切られた段落の間には空の段落を入れる
+                for line in lines {
+                    body.push_str(&format!("<text:p text:style-name=\"Standard\">{}</text:p>\n", render_inline(line)));
+                }
+                if matches!(blocks.get(i + 1), Some(Block::Paragraph(_))) {
+                    body.push_str("<text:p text:style-name=\"Standard\"/>\n");
+                }
+            }
+        }
+    }
+
+    let mut content = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<office:document-content {}>\n<office:automatic-styles>\n",
+        NAMESPACES
+    );
+    for level in 1..=2 {
+        content.push_str(&format!(
+            "<style:style style:name=\"PageBreak{0}\" style:family=\"paragraph\" style:parent-style-name=\"Heading_20_{0}\">\
+             <style:paragraph-properties fo:break-before=\"page\"/></style:style>\n",
+            level
+        ));
+    }
+    content.push_str(
+        "<style:style style:name=\"Sesame\" style:family=\"text\">\
+         <style:text-properties style:text-emphasize=\"dot above\"/></style:style>\n",
+    );
+    content.push_str("</office:automatic-styles>\n<office:body>\n<office:text>\n");
+    content.push_str(&body);
+    content.push_str("</office:text>\n</office:body>\n</office:document-content>\n");
+    content
+}
+
+fn render_meta(meta: &DocumentMeta) -> String {
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<office:document-meta {}>\n<office:meta>\n",
+        NAMESPACES
+    );
+    if !meta.title.is_empty() {
+        xml.push_str(&format!("<dc:title>{}</dc:title>\n", escape_html(&meta.title)));
+    }
+    if !meta.author.is_empty() {
+        xml.push_str(&format!("<meta:initial-creator>{0}</meta:initial-creator>\n<dc:creator>{0}</dc:creator>\n", escape_html(&meta.author)));
+    }
+    xml.push_str("<dc:language>ja-JP</dc:language>\n");
+    xml.push_str(&format!(
+        "<meta:creation-date>{}</meta:creation-date>\n<meta:generator>StoryComposer</meta:generator>\n",
+        chrono::Local::now().format("%Y-%m-%dT%H:%M:%S")
+    ));
+    xml.push_str("</office:meta>\n</office:document-meta>\n");
+    xml
+}
+
+pub fn build(doc: &Document) -> Result<Vec<u8>, String> {
+    let blocks = insert_scene_titles(parse_blocks(doc), doc);
+
+    let mut package = Package::new();
+    package.add_stored("mimetype", MIMETYPE)?;
+    package.add("META-INF/manifest.xml", MANIFEST.as_bytes())?;
+    package.add("content.xml", render_content(&blocks).as_bytes())?;
+    package.add("styles.xml", render_styles().as_bytes())?;
+    package.add("meta.xml", render_meta(&doc.meta).as_bytes())?;
+    package.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::{PlotFragment, PlotKind};
+    use std::io::Read;
+    use zip::{CompressionMethod, ZipArchive};
+
+    fn doc() -> Document {
+        let plots = [(PlotKind::Chapter, "第一章"), (PlotKind::Scene, "a<b & c>d\n｜漢字《かんじ》と強調［＃「強調」に傍点］")]
+            .iter()
+            .enumerate()
+            .map(|(id, &(kind, text))| PlotFragment {
+                text: text.to_owned(),
+                ..PlotFragment::new(id, kind)
+            })
+            .collect();
+        let mut doc = Document::from_parts(plots, String::new());
+        doc.meta.title = "作品<1>".to_owned();
+        doc.compose();
+        doc
+    }
+
+    // 書き出したファイルの中身を、格納順に名前・圧縮方式・内容の組にする
+    fn entries(data: Vec<u8>) -> Vec<(String, CompressionMethod, String)> {
+        let mut archive = ZipArchive::new(std::io::Cursor::new(data)).unwrap();
+        (0..archive.len())
+            .map(|i| {
+                let mut file = archive.by_index(i).unwrap();
+                let mut text = String::new();
+                file.read_to_string(&mut text).unwrap();
+                (file.name().to_owned(), file.compression(), text)
+            })
+            .collect()
+    }
+
+    fn entry<'a>(entries: &'a [(String, CompressionMethod, String)], name: &str) -> &'a str {
+        entries.iter().find(|(n, _, _)| n == name).map(|(_, _, text)| text.as_str()).unwrap()
+    }
+
+    #[test]
+    fn build_stores_mimetype_first() {
+        let entries = entries(build(&doc()).unwrap());
+        let (name, method, text) = &entries[0];
+        assert_eq!((name.as_str(), *method, text.as_bytes()), ("mimetype", CompressionMethod::Stored, MIMETYPE));
+        let names: Vec<&str> = entries.iter().map(|(name, _, _)| name.as_str()).collect();
+        assert_eq!(names, ["mimetype", "META-INF/manifest.xml", "content.xml", "styles.xml", "meta.xml"]);
+        assert!(entry(&entries, "meta.xml").contains("<dc:title>作品&lt;1&gt;</dc:title>"));
+    }
+
+    #[test]
+    fn build_escapes_text_and_renders_ruby() {
+        let entries = entries(build(&doc()).unwrap());
+        let content = entry(&entries, "content.xml");
+        assert!(content.contains("text:outline-level=\"2\">第一章</text:h>"));
+        assert!(content.contains(">a&lt;b &amp; c&gt;d</text:p>"));
+        assert!(!content.contains("a<b"));
+        assert!(content.contains("<text:ruby><text:ruby-base>漢字</text:ruby-base><text:ruby-text>かんじ</text:ruby-text></text:ruby>"));
+        assert!(content.contains("<text:span text:style-name=\"Sesame\">強調</text:span>"));
+    }
+}
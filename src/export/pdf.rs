@@ -0,0 +1,711 @@
+// 原稿用紙・応募原稿の書式で縦書きのPDFを書き出す
+
+use super::{parse_blocks, Block};
+use crate::document::Document;
+use crate::markup::{self, Inline};
+use pdf_writer::types::{CidFontType, FontFlags, SystemInfo, UnicodeCmap};
+use pdf_writer::{Content, Filter, Finish, Name, Pdf, Rect, Ref, Str, TextStr};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::PathBuf;
+use ttf_parser::gsub::{SingleSubstitution, SubstitutionSubtable};
+use ttf_parser::{Face, GlyphId, Tag};
+
+// A4横置き（ポイント）
+const PAGE_WIDTH: f32 = 842.0;
+const PAGE_HEIGHT: f32 = 595.0;
+const MARGIN_X: f32 = 36.0;
+const MARGIN_TOP: f32 = 42.0;
+const MARGIN_BOTTOM: f32 = 48.0;
+const PAGE_NUMBER_SIZE: f32 = 9.0;
+// 見出しの字下げ
+const HEADING_INDENT: usize = 3;
+
+pub struct ManuscriptLayout {
+    pub name: &'static str,
+    pub chars_per_line: usize,
+    pub lines_per_page: usize,
+    pub grid: bool,
+}
+
+pub const LAYOUTS: &[ManuscriptLayout] = &[
+    ManuscriptLayout {
+        name: "原稿用紙（20字×20行）",
+        chars_per_line: 20,
+        lines_per_page: 20,
+        grid: true,
+    },
+    ManuscriptLayout {
+        name: "応募原稿（40字×34行）",
+        chars_per_line: 40,
+        lines_per_page: 34,
+        grid: false,
+    },
+    ManuscriptLayout {
+        name: "応募原稿（42字×34行）",
+        chars_per_line: 42,
+        lines_per_page: 34,
+        grid: false,
+    },
+    ManuscriptLayout {
+        name: "応募原稿（40字×30行）",
+        chars_per_line: 40,
+        lines_per_page: 30,
+        grid: false,
+    },
+];
+
+#[derive(Clone)]
+pub struct PdfOptions {
+    pub chars_per_line: usize,
+    pub lines_per_page: usize,
+    // マス目を描く（原稿用紙の場合は中央に柱を空ける）
+    pub grid: bool,
+    pub page_numbers: bool,
+    pub font_path: PathBuf,
+    // TTCの場合に使うフォントの番号
+    pub font_index: u32,
+}
+
+impl PdfOptions {
+    pub fn new(layout: &ManuscriptLayout, font_path: PathBuf) -> Self {
+        Self {
+            chars_per_line: layout.chars_per_line,
+            lines_per_page: layout.lines_per_page,
+            grid: layout.grid,
+            page_numbers: true,
+            font_path,
+            font_index: 0,
+        }
+    }
+
+    pub fn apply_layout(&mut self, layout: &ManuscriptLayout) {
+        self.chars_per_line = layout.chars_per_line;
+        self.lines_per_page = layout.lines_per_page;
+        self.grid = layout.grid;
+    }
+}
+
+// 行頭に置かない文字
+const NO_LINE_START: &str = "、。，．）」』】〕〉》］｝ぁぃぅぇぉっゃゅょゎゕゖァィゥェォッャュョヮヵヶー・：；！？‼⁇⁈⁉々ゝゞヽヾ…‥";
+// 行末に置かない文字
+const NO_LINE_END: &str = "（「『【〔〈《［｛";
+// 行末のマスの外にぶら下げてよい文字
+const HANGING: &str = "、。，．」』";
+// ぶら下げるマスの上限（「。」」のように続く場合）
+const MAX_HANGING: usize = 2;
+
+// 1マス以上を占める組版の単位（ルビの親文字はまとめて扱う）
+struct Unit {
+    chars: Vec<char>,
+    ruby: Option<String>,
+    emphasis: bool,
+}
+
+impl Unit {
+    fn char(c: char) -> Self {
+        Self {
+            chars: vec![c],
+            ruby: None,
+            emphasis: false,
+        }
+    }
+}
+
+// 原稿の1行（縦書きなので1列）
+#[derive(Default)]
+struct Column {
+    units: Vec<Unit>,
+    cells: usize,
+}
+
+impl Column {
+    fn push(&mut self, unit: Unit) {
+        self.cells += unit.chars.len();
+        self.units.push(unit);
+    }
+
+    fn pop(&mut self) -> Option<Unit> {
+        let unit = self.units.pop()?;
+        self.cells -= unit.chars.len();
+        Some(unit)
+    }
+}
+
+// 原稿では半角の英数字も1マスに1字ずつ書くので全角にそろえる
+fn to_full_width(c: char) -> char {
+    match c {
+        ' ' => '　',
+        '!'..='~' => char::from_u32(c as u32 + 0xFEE0).unwrap_or(c),
+        _ => c,
+    }
+}
+
+fn single_char(unit: &Unit) -> Option<char> {
+    match (unit.chars.as_slice(), &unit.ruby) {
+        ([c], None) if !unit.emphasis => Some(*c),
+        _ => None,
+    }
+}
+
+fn line_units(inlines: Vec<Inline>, indent: usize, chars_per_line: usize) -> Vec<Unit> {
+    let mut units: Vec<Unit> = (0..indent).map(|_| Unit::char('　')).collect();
+    for inline in inlines {
+        match inline {
+            Inline::Text(text) => units.extend(text.chars().map(|c| Unit::char(to_full_width(c)))),
+            Inline::Emphasis(text) => units.extend(text.chars().map(|c| Unit {
+                emphasis: true,
+                ..Unit::char(to_full_width(c))
+            })),
+            Inline::Ruby { base, ruby } => {
+                // 1行に収まらない親文字は分割し、ルビは先頭にだけ付ける
+                let chars: Vec<char> = base.chars().map(to_full_width).collect();
+                let mut ruby = Some(ruby);
+                for chunk in chars.chunks(chars_per_line.max(1)) {
+                    units.push(Unit {
+                        chars: chunk.to_vec(),
+                        ruby: ruby.take(),
+                        emphasis: false,
+                    });
+                }
+            }
+        }
+    }
+    units
+}
+
+// 1行分の文字を禁則処理をしながら列に折り返す
+fn wrap_line(units: Vec<Unit>, chars_per_line: usize, columns: &mut Vec<Column>) {
+    let mut column = Column::default();
+    for unit in units {
+        let c = single_char(&unit);
+        if column.cells + unit.chars.len() > chars_per_line && !column.units.is_empty() {
+            if c.is_some_and(|c| HANGING.contains(c)) && column.cells < chars_per_line + MAX_HANGING {
+                column.push(unit);
+                continue;
+            }
+            let mut carried = Vec::new();
+            if c.is_some_and(|c| NO_LINE_START.contains(c)) {
+                // 追い出し：直前の文字と一緒に次の行へ送る（行頭禁則の文字が続く場合は、その前の文字まで戻る）
+                while column.units.len() > 1 {
+                    let Some(last) = column.pop() else {
+                        break;
+                    };
+                    let can_start = !single_char(&last).is_some_and(|c| NO_LINE_START.contains(c));
+                    carried.push(last);
+                    if can_start {
+                        break;
+                    }
+                }
+            }
+            columns.push(std::mem::take(&mut column));
+            for carried in carried.into_iter().rev() {
+                column.push(carried);
+            }
+        }
+        if c.is_some_and(|c| NO_LINE_END.contains(c)) && column.cells + 1 == chars_per_line {
+            columns.push(std::mem::take(&mut column));
+        }
+        column.push(unit);
+    }
+    columns.push(column);
+}
+
+fn layout_columns(doc: &Document, chars_per_line: usize) -> Vec<Column> {
+    let blocks = parse_blocks(doc);
+    let mut columns = Vec::new();
+    for (i, block) in blocks.into_iter().enumerate() {
+        if i > 0 {
+            columns.push(Column::default());
+        }
+        match block {
+            Block::Heading(_, text) => {
+                let units = line_units(markup::parse_inline(&text), HEADING_INDENT, chars_per_line);
+                wrap_line(units, chars_per_line, &mut columns);
+            }
+            Block::Separator => {
+                let units = line_units(vec![Inline::Text("＊".to_owned())], chars_per_line / 2 - 1, chars_per_line);
+                wrap_line(units, chars_per_line, &mut columns);
+            }
+            Block::Paragraph(lines) => {
+                for line in lines {
+                    let units = line_units(markup::parse_inline(&line), 0, chars_per_line);
+                    wrap_line(units, chars_per_line, &mut columns);
+                }
+            }
+        }
+    }
+    columns
+}
+
+// 列に並んだ文字（ルビを含む）のうち、フォントに字形が無いもの
+fn missing_chars(columns: &[Column], has_glyph: impl Fn(char) -> bool) -> BTreeSet<char> {
+    columns
+        .iter()
+        .flat_map(|column| &column.units)
+        .flat_map(|unit| unit.chars.iter().copied().chain(unit.ruby.iter().flat_map(|ruby| ruby.chars())))
+        .filter(|&c| !has_glyph(c))
+        .collect()
+}
+
+// 埋め込むフォント。縦書き用の字形（GSUBのvert）に置き換えて使う。
+struct FontData<'a> {
+    face: Face<'a>,
+    vertical: HashMap<u16, u16>,
+}
+
+impl<'a> FontData<'a> {
+    fn parse(data: &'a [u8], index: u32) -> Result<Self, String> {
+        let face = Face::parse(data, index).map_err(|e| format!("フォントを読み込めません: {}", e))?;
+        if face.tables().cff.is_some() || face.tables().cff2.is_some() {
+            return Err("CFF形式のフォントには対応していません。TrueType形式（.ttf / .ttc）のフォントを指定してください".to_owned());
+        }
+        let mut font = Self {
+            face,
+            vertical: HashMap::new(),
+        };
+        font.load_vertical_forms();
+        Ok(font)
+    }
+
+    fn load_vertical_forms(&mut self) {
+        let Some(gsub) = self.face.tables().gsub else {
+            return;
+        };
+        let vert = Tag::from_bytes(b"vert");
+        let lookup_indices: BTreeSet<u16> = gsub
+            .features
+            .into_iter()
+            .filter(|feature| feature.tag == vert)
+            .flat_map(|feature| feature.lookup_indices)
+            .collect();
+        for index in lookup_indices {
+            let Some(lookup) = gsub.lookups.get(index) else {
+                continue;
+            };
+            for i in 0..lookup.subtables.len() {
+                let Some(SubstitutionSubtable::Single(single)) = lookup.subtables.get::<SubstitutionSubtable>(i) else {
+                    continue;
+                };
+                match single {
+                    SingleSubstitution::Format1 { coverage, delta } => {
+                        for glyph in 0..self.face.number_of_glyphs() {
+                            if coverage.contains(GlyphId(glyph)) {
+                                self.vertical.entry(glyph).or_insert(glyph.wrapping_add(delta as u16));
+                            }
+                        }
+                    }
+                    SingleSubstitution::Format2 { coverage, substitutes } => {
+                        for glyph in 0..self.face.number_of_glyphs() {
+                            if let Some(sub) = coverage.get(GlyphId(glyph)).and_then(|i| substitutes.get(i)) {
+                                self.vertical.entry(glyph).or_insert(sub.0);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn glyph(&self, c: char) -> u16 {
+        self.face.glyph_index(c).map_or(0, |g| g.0)
+    }
+
+    fn vertical_glyph(&self, c: char) -> u16 {
+        let glyph = self.glyph(c);
+        self.vertical.get(&glyph).copied().unwrap_or(glyph)
+    }
+
+    fn scale(&self, value: f32) -> f32 {
+        value * 1000.0 / self.face.units_per_em() as f32
+    }
+
+    // 1000単位での横方向の送り幅
+    fn advance(&self, glyph: u16) -> f32 {
+        self.scale(self.face.glyph_hor_advance(GlyphId(glyph)).unwrap_or(0) as f32)
+    }
+
+    // 全角の字面の下端からベースラインまでの高さ（1000単位）
+    fn descent(&self) -> f32 {
+        let descender = self.face.typographic_descender().unwrap_or(self.face.descender());
+        self.scale(-(descender as f32)).clamp(0.0, 300.0)
+    }
+
+    fn postscript_name(&self) -> String {
+        self.face
+            .names()
+            .into_iter()
+            .filter(|name| name.name_id == ttf_parser::name_id::POST_SCRIPT_NAME)
+            .find_map(|name| name.to_string())
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| "StoryComposerFont".to_owned())
+    }
+}
+
+// ページ上のマス目の位置
+struct Grid {
+    cell: f32,
+    pitch: f32,
+    ruby_band: f32,
+    right: f32,
+    top: f32,
+    lines: usize,
+    center_gap: bool,
+}
+
+impl Grid {
+    fn new(options: &PdfOptions) -> Self {
+        let lines = options.lines_per_page;
+        let center_gap = options.grid && lines.is_multiple_of(2);
+        let columns = lines + usize::from(center_gap);
+        let available_width = PAGE_WIDTH - MARGIN_X * 2.0;
+        let available_height = PAGE_HEIGHT - MARGIN_TOP - MARGIN_BOTTOM;
+        let max_pitch = available_width / columns as f32;
+        let cell = (available_height / options.chars_per_line as f32).min(max_pitch / 1.3);
+        let pitch = max_pitch.min(cell * 1.6);
+        let width = pitch * columns as f32;
+        let height = cell * options.chars_per_line as f32;
+        Self {
+            cell,
+            pitch,
+            ruby_band: pitch - cell,
+            right: (PAGE_WIDTH + width) / 2.0,
+            top: PAGE_HEIGHT - MARGIN_TOP - (available_height - height) / 2.0,
+            lines,
+            center_gap,
+        }
+    }
+
+    // 右から数えた列のマスの左端
+    fn column_x(&self, line: usize) -> f32 {
+        let slot = line + usize::from(self.center_gap && line >= self.lines / 2);
+        self.right - slot as f32 * self.pitch - self.ruby_band - self.cell
+    }
+
+    fn cell_bottom(&self, index: usize) -> f32 {
+        self.top - (index + 1) as f32 * self.cell
+    }
+}
+
+fn push_glyph(content: &mut Content, glyph: u16, size: f32, x: f32, y: f32) {
+    content.set_font(Name(b"F1"), size);
+    content.set_text_matrix([1.0, 0.0, 0.0, 1.0, x, y]);
+    content.show(Str(&glyph.to_be_bytes()));
+}
+
+fn render_page(
+    columns: &[Column],
+    page_number: usize,
+    options: &PdfOptions,
+    grid: &Grid,
+    font: &FontData,
+    used: &mut BTreeMap<u16, char>,
+) -> Vec<u8> {
+    let mut content = Content::new();
+
+    if options.grid {
+        content.set_stroke_rgb(0.8, 0.45, 0.35);
+        content.set_line_width(0.4);
+        for line in 0..grid.lines {
+            let x = grid.column_x(line);
+            for index in 0..options.chars_per_line {
+                content.rect(x, grid.cell_bottom(index), grid.cell, grid.cell);
+            }
+        }
+        content.stroke();
+    }
+
+    let size = grid.cell * 0.9;
+    let padding = (grid.cell - size) / 2.0;
+    let descent = font.descent() / 1000.0;
+    let ruby_size = (size / 2.0).min(grid.ruby_band * 0.9);
+    // 傍点はゴマ点（﹅）、フォントに無ければ中黒で代用する
+    let sesame = if font.glyph('﹅') != 0 { '﹅' } else { '・' };
+    let mut draw = |content: &mut Content, c: char, size: f32, left: f32, width: f32, bottom: f32| {
+        let glyph = font.vertical_glyph(c);
+        used.insert(glyph, c);
+        let advance = font.advance(glyph) / 1000.0 * size;
+        push_glyph(content, glyph, size, left + (width - advance) / 2.0, bottom + descent * size);
+    };
+
+    content.begin_text();
+    for (line, column) in columns.iter().enumerate() {
+        let x = grid.column_x(line);
+        let mut index = 0;
+        for unit in &column.units {
+            let start = index;
+            for &c in &unit.chars {
+                let bottom = grid.cell_bottom(index);
+                draw(&mut content, c, size, x, grid.cell, bottom + padding);
+                if unit.emphasis {
+                    let mark_bottom = bottom + (grid.cell - ruby_size) / 2.0;
+                    draw(&mut content, sesame, ruby_size, x + grid.cell, ruby_size, mark_bottom);
+                }
+                index += 1;
+            }
+            // ルビは親文字の右側に、親文字の中央にそろえて置く
+            if let Some(ruby) = &unit.ruby {
+                let count = ruby.chars().count() as f32;
+                let span = (index - start) as f32 * grid.cell;
+                let top = grid.top - start as f32 * grid.cell - (span - count * ruby_size) / 2.0;
+                for (i, c) in ruby.chars().enumerate() {
+                    let bottom = top - (i + 1) as f32 * ruby_size;
+                    draw(&mut content, c, ruby_size, x + grid.cell, ruby_size, bottom);
+                }
+            }
+        }
+    }
+
+    if options.page_numbers {
+        let text = format!("- {} -", page_number);
+        let glyphs: Vec<u16> = text.chars().map(|c| font.glyph(c)).collect();
+        let width: f32 = glyphs.iter().map(|&g| font.advance(g) / 1000.0 * PAGE_NUMBER_SIZE).sum();
+        let mut x = (PAGE_WIDTH - width) / 2.0;
+        for (c, glyph) in text.chars().zip(glyphs) {
+            used.insert(glyph, c);
+            push_glyph(&mut content, glyph, PAGE_NUMBER_SIZE, x, MARGIN_BOTTOM / 2.0);
+            x += font.advance(glyph) / 1000.0 * PAGE_NUMBER_SIZE;
+        }
+    }
+    content.end_text();
+
+    content.finish()
+}
+
+fn compress(data: &[u8]) -> Vec<u8> {
+    miniz_oxide::deflate::compress_to_vec_zlib(data, 6)
+}
+
+pub fn build(doc: &Document, options: &PdfOptions) -> Result<Vec<u8>, String> {
+    if options.chars_per_line < 2 || options.lines_per_page < 1 {
+        return Err("1行の字数は2以上、1ページの行数は1以上にしてください".to_owned());
+    }
+    let font_data = std::fs::read(&options.font_path).map_err(|e| format!("{}: {}", options.font_path.display(), e))?;
+    let font = FontData::parse(&font_data, options.font_index)?;
+
+    let mut columns = layout_columns(doc, options.chars_per_line);
+    if columns.is_empty() {
+        columns.push(Column::default());
+    }
+    // 字形の無い文字は空白のまま書き出されてしまうので、書き出す前に知らせる
+    let missing = missing_chars(&columns, |c| font.face.glyph_index(c).is_some());
+    if !missing.is_empty() {
+        let mut list: String = missing.iter().take(20).collect();
+        if missing.len() > 20 {
+            list.push('…');
+        }
+        return Err(format!(
+            "選んだフォントに無い文字があります: {}\n別のフォントを選ぶか、文字を置き換えてください",
+            list
+        ));
+    }
+    let pages: Vec<&[Column]> = columns.chunks(options.lines_per_page).collect();
+    let grid = Grid::new(options);
+
+    let mut used = BTreeMap::new();
+    used.insert(0, ' ');
+    let contents: Vec<Vec<u8>> = pages
+        .iter()
+        .enumerate()
+        .map(|(i, page)| render_page(page, i + 1, options, &grid, &font, &mut used))
+        .collect();
+
+    let mut pdf = Pdf::new();
+    let mut next_ref = Ref::new(1);
+    let mut alloc = || next_ref.bump();
+    let catalog_id = alloc();
+    let pages_id = alloc();
+    let info_id = alloc();
+    let font_id = alloc();
+    let cid_font_id = alloc();
+    let descriptor_id = alloc();
+    let font_file_id = alloc();
+    let to_unicode_id = alloc();
+    let page_ids: Vec<(Ref, Ref)> = (0..contents.len()).map(|_| (alloc(), alloc())).collect();
+
+    pdf.catalog(catalog_id).pages(pages_id);
+    pdf.pages(pages_id)
+        .kids(page_ids.iter().map(|(page, _)| *page))
+        .count(page_ids.len() as i32);
+    let mut info = pdf.document_info(info_id);
+    if !doc.meta.title.is_empty() {
+        info.title(TextStr(&doc.meta.title));
+    }
+    if !doc.meta.author.is_empty() {
+        info.author(TextStr(&doc.meta.author));
+    }
+    info.creator(TextStr("StoryComposer"));
+    info.finish();
+
+    for ((page_id, content_id), content) in page_ids.iter().zip(&contents) {
+        let mut page = pdf.page(*page_id);
+        page.media_box(Rect::new(0.0, 0.0, PAGE_WIDTH, PAGE_HEIGHT));
+        page.parent(pages_id);
+        page.contents(*content_id);
+        page.resources().fonts().pair(Name(b"F1"), font_id);
+        page.finish();
+        pdf.stream(*content_id, &compress(content)).filter(Filter::FlateDecode);
+    }
+
+    // 使った字形だけを残して埋め込む（字形の番号は変わらない）
+    let glyphs: Vec<u16> = used.keys().copied().collect();
+    let subset = subsetter::subset(&font_data, options.font_index, subsetter::Profile::pdf(&glyphs))
+        .map_err(|e| format!("フォントの埋め込みに失敗しました: {}", e))?;
+    // 部分埋め込みのフォント名には6文字の英大文字を前に付ける決まり
+    let base_font = format!("SCPDFA+{}", font.postscript_name());
+    let system_info = SystemInfo {
+        registry: Str(b"Adobe"),
+        ordering: Str(b"Identity"),
+        supplement: 0,
+    };
+
+    pdf.type0_font(font_id)
+        .base_font(Name(base_font.as_bytes()))
+        .encoding_predefined(Name(b"Identity-H"))
+        .descendant_font(cid_font_id)
+        .to_unicode(to_unicode_id);
+
+    let mut cid_font = pdf.cid_font(cid_font_id);
+    cid_font
+        .subtype(CidFontType::Type2)
+        .base_font(Name(base_font.as_bytes()))
+        .system_info(system_info)
+        .font_descriptor(descriptor_id)
+        .cid_to_gid_map_predefined(Name(b"Identity"));
+    let mut widths = cid_font.widths();
+    for &glyph in &glyphs {
+        widths.consecutive(glyph, [font.advance(glyph)]);
+    }
+    widths.finish();
+    cid_font.finish();
+
+    let bbox = font.face.global_bounding_box();
+    let ascender = font.scale(font.face.ascender() as f32);
+    pdf.font_descriptor(descriptor_id)
+        .name(Name(base_font.as_bytes()))
+        .flags(FontFlags::SYMBOLIC)
+        .bbox(Rect::new(
+            font.scale(bbox.x_min as f32),
+            font.scale(bbox.y_min as f32),
+            font.scale(bbox.x_max as f32),
+            font.scale(bbox.y_max as f32),
+        ))
+        .italic_angle(0.0)
+        .ascent(ascender)
+        .descent(font.scale(font.face.descender() as f32))
+        .cap_height(font.face.capital_height().map_or(ascender, |h| font.scale(h as f32)))
+        .stem_v(80.0)
+        .font_file2(font_file_id);
+
+    pdf.stream(font_file_id, &compress(&subset)).filter(Filter::FlateDecode);
+
+    let mut cmap = UnicodeCmap::new(Name(b"Custom"), system_info);
+    for (&glyph, &c) in &used {
+        if glyph != 0 {
+            cmap.pair(glyph, c);
+        }
+    }
+    pdf.cmap(to_unicode_id, &cmap.finish());
+
+    Ok(pdf.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::{PlotFragment, PlotKind};
+
+    fn text(column: &Column) -> String {
+        column.units.iter().flat_map(|unit| &unit.chars).collect()
+    }
+
+    fn wrap(line: &str, chars_per_line: usize) -> Vec<String> {
+        let mut columns = Vec::new();
+        wrap_line(line_units(markup::parse_inline(line), 0, chars_per_line), chars_per_line, &mut columns);
+        columns.iter().map(text).collect()
+    }
+
+    #[test]
+    fn wrap_line_hangs_punctuation() {
+        // 行頭に置けない句読点は行末にぶら下げる（2字まで）
+        assert_eq!(wrap("あいうえお。", 5), ["あいうえお。"]);
+        assert_eq!(wrap("あいうえお。」", 5), ["あいうえお。」"]);
+        assert_eq!(wrap("あいうえお。」。", 5), ["あいうえ", "お。」。"]);
+    }
+
+    #[test]
+    fn wrap_line_pushes_out_line_start_characters() {
+        // ぶら下げられない行頭禁則の文字は、直前の文字と一緒に次の行へ送る
+        assert_eq!(wrap("あいうえおーか", 5), ["あいうえ", "おーか"]);
+        assert_eq!(wrap("あいうえおっ", 5), ["あいうえ", "おっ"]);
+        // ぶら下げきれない句読点が続く場合は、行頭禁則の文字の前の文字まで戻して送る
+        assert_eq!(wrap("あいうえお」。。", 5), ["あいうえ", "お」。。"]);
+    }
+
+    #[test]
+    fn wrap_line_avoids_line_end_characters() {
+        // 始め括弧は行末に置かずに次の行へ送る
+        assert_eq!(wrap("あいうえ「かき」", 5), ["あいうえ", "「かき」"]);
+    }
+
+    #[test]
+    fn line_units_converts_to_full_width_and_splits_ruby() {
+        let units = line_units(markup::parse_inline("A1 |漢字《かんじ》"), 1, 5);
+        let chars: String = units.iter().flat_map(|unit| &unit.chars).collect();
+        assert_eq!(chars, "　Ａ１　漢字");
+        assert_eq!(units.last().and_then(|unit| unit.ruby.as_deref()), Some("かんじ"));
+
+        // 1行に収まらない親文字は分け、ルビは先頭にだけ付ける
+        let units = line_units(markup::parse_inline("|一二三四五六七《ななもじ》"), 0, 5);
+        let parts: Vec<(String, Option<&str>)> = units
+            .iter()
+            .map(|unit| (unit.chars.iter().collect(), unit.ruby.as_deref()))
+            .collect();
+        assert_eq!(parts, [("一二三四五".to_owned(), Some("ななもじ")), ("六七".to_owned(), None)]);
+
+        let units = line_units(markup::parse_inline("強調［＃「強調」に傍点］"), 0, 5);
+        assert!(units.iter().all(|unit| unit.emphasis && unit.chars.len() == 1));
+    }
+
+    #[test]
+    fn layout_columns_places_headings_and_separators() {
+        let plots = [(PlotKind::Chapter, "第一章"), (PlotKind::Scene, "本文"), (PlotKind::Scene, "続き")]
+            .iter()
+            .enumerate()
+            .map(|(id, &(kind, text))| PlotFragment {
+                text: text.to_owned(),
+                ..PlotFragment::new(id, kind)
+            })
+            .collect();
+        let mut doc = Document::from_parts(plots, String::new());
+        doc.compose();
+        let columns: Vec<String> = layout_columns(&doc, 10).iter().map(text).collect();
+        assert_eq!(columns, ["　　　第一章", "", "本文", "", "　　　　＊", "", "続き"]);
+    }
+
+    #[test]
+    fn missing_chars_includes_ruby() {
+        let mut columns = Vec::new();
+        wrap_line(line_units(markup::parse_inline("|字《𠮷》あ"), 0, 10), 10, &mut columns);
+        let missing = missing_chars(&columns, |c| c != '𠮷' && c != 'あ');
+        assert_eq!(missing.into_iter().collect::<String>(), "あ𠮷");
+    }
+
+    #[test]
+    fn grid_fits_on_page_with_center_gap() {
+        let options = PdfOptions::new(&LAYOUTS[0], PathBuf::new());
+        let grid = Grid::new(&options);
+        assert!(grid.center_gap);
+        // 原稿用紙は中央の柱の分だけ列を空ける
+        assert!((grid.column_x(9) - grid.column_x(10) - grid.pitch * 2.0).abs() < 0.01);
+        assert!(grid.column_x(grid.lines - 1) >= MARGIN_X - 0.01);
+        assert!(grid.column_x(0) + grid.cell + grid.ruby_band <= PAGE_WIDTH - MARGIN_X + 0.01);
+        assert!(grid.cell_bottom(options.chars_per_line - 1) >= MARGIN_BOTTOM - 0.01);
+        assert!(grid.top <= PAGE_HEIGHT - MARGIN_TOP + 0.01);
+
+        let options = PdfOptions::new(&LAYOUTS[1], PathBuf::new());
+        let grid = Grid::new(&options);
+        assert!(!grid.center_gap);
+        assert!((grid.column_x(16) - grid.column_x(17) - grid.pitch).abs() < 0.01);
+        assert!(grid.column_x(grid.lines - 1) >= MARGIN_X - 0.01);
+    }
+}
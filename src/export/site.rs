@@ -0,0 +1,426 @@
+// 小説投稿サイト向けの書き出し。サイトごとの記法に書き換え、1話ずつのファイルに分ける。
+
+use super::{encode_text, find_headings, is_separator, TextOptions};
+use crate::document::Document;
+use crate::markup::{self, Inline};
+use std::collections::HashSet;
+use std::path::Path;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Site {
+    Narou,
+    Kakuyomu,
+    Pixiv,
+}
+
+impl Site {
+    pub fn all() -> &'static [Site] {
+        &[Site::Narou, Site::Kakuyomu, Site::Pixiv]
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "narou" => Some(Site::Narou),
+            "kakuyomu" => Some(Site::Kakuyomu),
+            "pixiv" => Some(Site::Pixiv),
+            _ => None,
+        }
+    }
+
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            Site::Narou => "小説家になろう",
+            Site::Kakuyomu => "カクヨム",
+            Site::Pixiv => "pixiv",
+        }
+    }
+
+    // 1話の本文の文字数の上限
+    pub fn char_limit(&self) -> usize {
+        match self {
+            Site::Narou => 70_000,
+            Site::Kakuyomu => 100_000,
+            Site::Pixiv => 300_000,
+        }
+    }
+
+    // 続けて置ける空行の数（None は制限しない）
+    fn max_blank_lines(&self) -> Option<usize> {
+        match self {
+            Site::Narou | Site::Kakuyomu => None,
+            Site::Pixiv => Some(1),
+        }
+    }
+
+    fn separator(&self) -> &'static str {
+        match self {
+            Site::Narou | Site::Kakuyomu => "＊　＊　＊",
+            Site::Pixiv => "[newpage]",
+        }
+    }
+
+    fn ruby(&self, base: &str, ruby: &str) -> String {
+        match self {
+            Site::Narou | Site::Kakuyomu => format!("｜{}《{}》", base, ruby),
+            Site::Pixiv => format!("[[rb:{} > {}]]", base, ruby),
+        }
+    }
+
+    // 傍点の記法が無いサイトでは1文字ずつ「・」のルビを振る
+    fn emphasis(&self, text: &str) -> String {
+        match self {
+            Site::Kakuyomu => format!("《《{}》》", text),
+            Site::Narou | Site::Pixiv => text.chars().map(|c| self.ruby(&c.to_string(), "・")).collect(),
+        }
+    }
+
+    // 1行をサイトの記法に書き換え、ルビや傍点の途中で切らずに分けられる単位（地の文は1文字ずつ）にする
+    fn render_units(&self, line: &str) -> Vec<String> {
+        markup::parse_inline(line)
+            .into_iter()
+            .flat_map(|inline| match inline {
+                Inline::Text(text) => text.chars().map(String::from).collect(),
+                Inline::Ruby { base, ruby } => vec![self.ruby(&base, &ruby)],
+                Inline::Emphasis(text) => vec![self.emphasis(&text)],
+            })
+            .collect()
+    }
+}
+
+pub struct Episode {
+    pub title: String,
+    pub text: String,
+}
+
+impl Episode {
+    // 連番とタイトルから、ファイル名に使えない文字を除いた名前を作る
+    pub fn file_name(&self, number: usize) -> String {
+        let title: String = self
+            .title
+            .chars()
+            .filter(|c| !c.is_control())
+            .map(|c| if "\\/:*?\"<>|".contains(c) { '_' } else { c })
+            .take(40)
+            .collect();
+        let title = title.trim();
+        if title.is_empty() {
+            format!("{:03}.txt", number)
+        } else {
+            format!("{:03}_{}.txt", number, title)
+        }
+    }
+}
+
+// 空行で区切ったまとまりと、その前にあった空行の数
+struct Chunk<'a> {
+    blank_before: usize,
+    lines: Vec<&'a str>,
+}
+
+fn split_chunks(text: &str) -> Vec<Chunk<'_>> {
+    let mut chunks = Vec::new();
+    let mut blank_before = 0;
+    let mut lines = Vec::new();
+    for line in text.lines().chain(std::iter::once("")) {
+        if !line.trim().is_empty() {
+            lines.push(line);
+            continue;
+        }
+        if lines.is_empty() {
+            blank_before += 1;
+            continue;
+        }
+        chunks.push(Chunk {
+            blank_before,
+            lines: std::mem::take(&mut lines),
+        });
+        blank_before = 1;
+    }
+    chunks
+}
+
+// 組み立て中の1話。chapter は見出しの通し番号で、続きの話に分かれた場合は part が2以降になる
+struct Draft {
+    title: String,
+    chapter: usize,
+    part: usize,
+    text: String,
+}
+
+impl Draft {
+    fn len(&self) -> usize {
+        self.text.chars().count()
+    }
+}
+
+// 上限を超えるまとまりを行ごと（1行が長すぎる場合は render_units の単位ごと）に分ける
+fn split_to_fit(lines: &[Vec<String>], limit: usize) -> Vec<String> {
+    let mut pieces: Vec<String> = Vec::new();
+    let mut current = String::new();
+    let mut current_len = 0;
+    for units in lines {
+        let line_len: usize = units.iter().map(|u| u.chars().count()).sum();
+        let sep = usize::from(!current.is_empty());
+        if current_len + sep + line_len <= limit {
+            if sep == 1 {
+                current.push('\n');
+            }
+            current.extend(units.iter().map(String::as_str));
+            current_len += sep + line_len;
+            continue;
+        }
+        if !current.is_empty() {
+            pieces.push(std::mem::take(&mut current));
+            current_len = 0;
+        }
+        for unit in units {
+            let unit_len = unit.chars().count();
+            if !current.is_empty() && current_len + unit_len > limit {
+                pieces.push(std::mem::take(&mut current));
+                current_len = 0;
+            }
+            current.push_str(unit);
+            current_len += unit_len;
+        }
+    }
+    if !current.is_empty() {
+        pieces.push(current);
+    }
+    pieces
+}
+
+// 幕・章の見出しで話を分け、文字数の上限を超える話はさらに分ける
+pub fn episodes(doc: &Document, site: Site) -> Vec<Episode> {
+    let limit = site.char_limit();
+    let text = doc.composed_text.replace("\r\n", "\n");
+
+    // 見出しの前までの本文と、続く見出しの組に分ける
+    let mut parts = Vec::new();
+    let mut start = 0;
+    for heading in find_headings(doc, &text) {
+        parts.push((&text[start..heading.range.start], Some(heading.title)));
+        start = heading.range.end;
+    }
+    parts.push((&text[start..], None));
+
+    let mut drafts: Vec<Draft> = Vec::new();
+    let mut current = Draft {
+        title: String::new(),
+        chapter: 0,
+        part: 1,
+        text: String::new(),
+    };
+
+    for (body, heading) in parts {
+        for chunk in split_chunks(body) {
+            let lines = if chunk.lines.len() == 1 && is_separator(doc, chunk.lines[0]) {
+                vec![vec![site.separator().to_owned()]]
+            } else {
+                chunk.lines.iter().map(|l| site.render_units(l)).collect()
+            };
+            let blank_lines = site.max_blank_lines().map_or(chunk.blank_before, |max| chunk.blank_before.min(max));
+
+            for piece in split_to_fit(&lines, limit) {
+                let gap = if current.text.is_empty() { 0 } else { blank_lines.max(1) + 1 };
+                if !current.text.is_empty() && current.len() + gap + piece.chars().count() > limit {
+                    let next = Draft {
+                        title: current.title.clone(),
+                        chapter: current.chapter,
+                        part: current.part + 1,
+                        text: String::new(),
+                    };
+                    drafts.push(std::mem::replace(&mut current, next));
+                }
+                if !current.text.is_empty() {
+                    current.text.push_str(&"\n".repeat(blank_lines.max(1) + 1));
+                }
+                current.text.push_str(&piece);
+            }
+        }
+
+        let Some(heading) = heading else {
+            continue;
+        };
+        let title = markup::plain_text(&heading);
+        if current.text.is_empty() {
+            // 幕の直後の章のように見出しが続く場合は、まとめて1つのタイトルにする
+            if !current.title.is_empty() {
+                current.title.push('　');
+            }
+            current.title.push_str(&title);
+        } else {
+            let next = Draft {
+                title,
+                chapter: current.chapter + 1,
+                part: 1,
+                text: String::new(),
+            };
+            drafts.push(std::mem::replace(&mut current, next));
+        }
+    }
+    drafts.push(current);
+
+    // 分かれた話には「（1）」「（2）」と番号を付ける
+    let split_chapters: HashSet<usize> = drafts.iter().filter(|d| d.part > 1).map(|d| d.chapter).collect();
+    drafts
+        .into_iter()
+        .filter(|d| !d.text.is_empty())
+        .map(|d| {
+            let title = if split_chapters.contains(&d.chapter) {
+                format!("{}（{}）", d.title, d.part)
+            } else {
+                d.title
+            };
+            let mut text = d.text;
+            text.push('\n');
+            Episode { title, text }
+        })
+        .collect()
+}
+
+// 書き出したファイルの一覧を残しておくファイル（次回の書き出しで、話の数が減った場合に古い話を消すため）
+const MANIFEST_FILE: &str = ".storycomposer_episodes";
+
+// 一覧に書かれた名前のうち、このフォルダの中の話のファイルとして扱えるもの（「001.txt」「001_タイトル.txt」）
+fn is_episode_file(name: &str) -> bool {
+    let Some(stem) = name.strip_suffix(".txt") else {
+        return false;
+    };
+    let digits = stem.chars().take_while(|c| c.is_ascii_digit()).count();
+    digits == 3 && (stem.len() == 3 || stem[3..].starts_with('_')) && !name.contains(['/', '\\'])
+}
+
+// 1話ずつファイルに書き出し、書き出したファイルの数を返す。
+// 前回このフォルダに書き出したファイル（一覧に残したもの）のうち今回書き出さなかったものは削除する。
+// アプリが書き出していないファイルは消さない。
+pub fn write_episodes(doc: &Document, site: Site, options: &TextOptions, dir: &Path) -> Result<usize, String> {
+    let episodes = episodes(doc, site);
+    if episodes.is_empty() {
+        return Err("出力テキストが空です".to_owned());
+    }
+    // 途中で失敗して一部だけ書き出されることが無いよう、先にすべて変換しておく
+    let files = episodes
+        .iter()
+        .enumerate()
+        .map(|(i, episode)| Ok((episode.file_name(i + 1), encode_text(&episode.text, options)?)))
+        .collect::<Result<Vec<_>, String>>()?;
+    std::fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    let manifest = dir.join(MANIFEST_FILE);
+    let previous = std::fs::read_to_string(&manifest).unwrap_or_default();
+    let names: Vec<String> = files.iter().map(|(name, _)| name.clone()).collect();
+    for (name, data) in files {
+        let path = dir.join(name);
+        std::fs::write(&path, data).map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+    for name in previous.lines().filter(|name| is_episode_file(name) && !names.iter().any(|n| n == name)) {
+        let path = dir.join(name);
+        if path.is_file() {
+            std::fs::remove_file(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        }
+    }
+    let mut list = names.join("\n");
+    list.push('\n');
+    std::fs::write(&manifest, list).map_err(|e| format!("{}: {}", manifest.display(), e))?;
+    Ok(episodes.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::{PlotFragment, PlotKind};
+
+    fn composed_doc(plots: &[(PlotKind, String)]) -> Document {
+        let plots = plots
+            .iter()
+            .enumerate()
+            .map(|(id, (kind, text))| PlotFragment {
+                text: text.clone(),
+                ..PlotFragment::new(id, *kind)
+            })
+            .collect();
+        let mut doc = Document::from_parts(plots, String::new());
+        doc.compose();
+        doc
+    }
+
+    fn summary(episodes: &[Episode]) -> Vec<(String, usize)> {
+        episodes.iter().map(|e| (e.title.clone(), e.text.trim_end().chars().count())).collect()
+    }
+
+    #[test]
+    fn episode_at_char_limit_is_not_split() {
+        let limit = Site::Narou.char_limit();
+        let doc = composed_doc(&[(PlotKind::Chapter, "第一章".to_owned()), (PlotKind::Scene, "あ".repeat(limit))]);
+        assert_eq!(summary(&episodes(&doc, Site::Narou)), [("第一章".to_owned(), limit)]);
+    }
+
+    #[test]
+    fn episode_over_char_limit_is_split() {
+        let limit = Site::Narou.char_limit();
+        let doc = composed_doc(&[
+            (PlotKind::Chapter, "第一章".to_owned()),
+            (PlotKind::Scene, "あ".repeat(limit + 1)),
+            (PlotKind::Chapter, "第二章".to_owned()),
+            (PlotKind::Scene, "い".to_owned()),
+        ]);
+        assert_eq!(
+            summary(&episodes(&doc, Site::Narou)),
+            [("第一章（1）".to_owned(), limit), ("第一章（2）".to_owned(), 1), ("第二章".to_owned(), 1)]
+        );
+    }
+
+    #[test]
+    fn scenes_are_moved_to_next_part_as_a_whole() {
+        let half = Site::Narou.char_limit() / 2;
+        let doc = composed_doc(&[(PlotKind::Scene, "あ".repeat(half)), (PlotKind::Scene, "い".repeat(half))]);
+        let episodes = episodes(&doc, Site::Narou);
+        assert_eq!(summary(&episodes), [("（1）".to_owned(), half + 7), ("（2）".to_owned(), half)]);
+        assert!(episodes[0].text.ends_with("\n\n＊　＊　＊\n"));
+    }
+
+    #[test]
+    fn long_line_is_not_split_inside_ruby() {
+        let limit = Site::Narou.char_limit();
+        let text = format!("{}|漢字《かんじ》{}", "あ".repeat(limit - 5), "い".repeat(10));
+        let doc = composed_doc(&[(PlotKind::Scene, text)]);
+        let episodes = episodes(&doc, Site::Narou);
+        assert_eq!(episodes.len(), 2);
+        assert_eq!(episodes[0].text.trim_end(), "あ".repeat(limit - 5));
+        assert_eq!(episodes[1].text.trim_end(), format!("｜漢字《かんじ》{}", "い".repeat(10)));
+    }
+
+    #[test]
+    fn site_notation() {
+        let doc = composed_doc(&[(PlotKind::Scene, "｜漢字《かんじ》と重要［＃「重要」に傍点］".to_owned())]);
+        let text = |site| episodes(&doc, site).remove(0).text;
+        assert_eq!(text(Site::Narou), "｜漢字《かんじ》と｜重《・》｜要《・》\n");
+        assert_eq!(text(Site::Kakuyomu), "｜漢字《かんじ》と《《重要》》\n");
+        assert_eq!(text(Site::Pixiv), "[[rb:漢字 > かんじ]]と[[rb:重 > ・]][[rb:要 > ・]]\n");
+    }
+
+    #[test]
+    fn write_episodes_removes_only_files_written_before() {
+        let dir = std::env::temp_dir().join(format!("story_composer_site_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["005_手書き.txt", "メモ.txt"] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+        let chapter = |title: &str| (PlotKind::Chapter, title.to_owned());
+        let scene = || (PlotKind::Scene, "本文".to_owned());
+        let long = composed_doc(&[chapter("第一章"), scene(), chapter("第二章"), scene()]);
+        assert_eq!(write_episodes(&long, Site::Kakuyomu, &TextOptions::default(), &dir), Ok(2));
+        let short = composed_doc(&[chapter("序章"), scene()]);
+        assert_eq!(write_episodes(&short, Site::Kakuyomu, &TextOptions::default(), &dir), Ok(1));
+
+        let mut names: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        let manifest = std::fs::read_to_string(dir.join(MANIFEST_FILE)).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(names, [MANIFEST_FILE, "001_序章.txt", "005_手書き.txt", "メモ.txt"]);
+        assert_eq!(manifest, "001_序章.txt\n");
+    }
+}
@@ -0,0 +1,369 @@
+// .scrf ファイルの読み書き（形式の変換・安全な保存・バックアップ）
+
+use crate::document::{ComposeRecord, Document, DocumentMeta, PlotFilter, PlotFragment};
+use crate::template::ComposeTemplate;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+// 保存時にファイルの隣へ残すバックアップの世代数
+pub const BACKUP_GENERATIONS: usize = 5;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SaveData {
+    #[serde(default)]
+    pub version: u32,
+    pub plots: Vec<PlotFragment>,
+    pub composed_text: String,
+    #[serde(default)]
+    pub meta: DocumentMeta,
+    #[serde(default)]
+    pub template: ComposeTemplate,
+    #[serde(default)]
+    pub composed: ComposeRecord,
+    #[serde(default)]
+    pub filter: PlotFilter,
+    #[serde(default)]
+    pub excluded_tags: Vec<String>,
+}
+
+// .scrf 形式の変換処理。MIGRATIONS[n] はバージョン n のデータを n + 1 に変換する。
+// データモデルを変更する場合は、ここに変換処理を追加すること（既存の処理は変更しない）。
+type Migration = fn(&mut serde_json::Value) -> Result<(), String>;
+
+const MIGRATIONS: &[Migration] = &[
+    migrate_v0_to_v1,
+    migrate_v1_to_v2,
+    migrate_v2_to_v3,
+    migrate_v3_to_v4,
+    migrate_v4_to_v5,
+    migrate_v5_to_v6,
+    migrate_v6_to_v7,
+];
+
+pub const FORMAT_VERSION: u32 = MIGRATIONS.len() as u32;
+
+fn plots_mut(data: &mut serde_json::Value) -> Result<&mut Vec<serde_json::Value>, String> {
+    data.get_mut("plots")
+        .and_then(|plots| plots.as_array_mut())
+        .ok_or_else(|| "プロットの一覧が見つかりません".to_string())
+}
+
+fn insert_default(target: &mut serde_json::Value, key: &str, value: serde_json::Value) {
+    if let Some(obj) = target.as_object_mut() {
+        obj.entry(key).or_insert(value);
+    }
+}
+
+// v0 → v1: 幕・章の階層（すべてシーンとして扱う）
+fn migrate_v0_to_v1(data: &mut serde_json::Value) -> Result<(), String> {
+    for plot in plots_mut(data)? {
+        insert_default(plot, "kind", serde_json::json!("Scene"));
+        insert_default(plot, "collapsed", serde_json::json!(false));
+    }
+    Ok(())
+}
+
+// v1 → v2: タイトル・あらすじ・進捗・色ラベル・メモ
+fn migrate_v1_to_v2(data: &mut serde_json::Value) -> Result<(), String> {
+    for plot in plots_mut(data)? {
+        insert_default(plot, "title", serde_json::json!(""));
+        insert_default(plot, "synopsis", serde_json::json!(""));
+        insert_default(plot, "status", serde_json::json!("Idea"));
+        insert_default(plot, "color", serde_json::json!("None"));
+        insert_default(plot, "notes", serde_json::json!(""));
+    }
+    Ok(())
+}
+
+// v2 → v3: 文書の情報（タイトル・著者名・日付）
+fn migrate_v2_to_v3(data: &mut serde_json::Value) -> Result<(), String> {
+    insert_default(data, "meta", serde_json::json!({ "title": "", "author": "", "date": "" }));
+    Ok(())
+}
+
+// v3 → v4: 出力テキストの区間と生成元のプロットの対応（記録の無い文書は次の文書生成で作り直す）
+fn migrate_v3_to_v4(data: &mut serde_json::Value) -> Result<(), String> {
+    insert_default(data, "composed_sections", serde_json::json!([]));
+    Ok(())
+}
+
+// v4 → v5: 文書生成の書式。これまでの記録は標準の書式で生成したものとして引き継ぐ
+fn migrate_v4_to_v5(data: &mut serde_json::Value) -> Result<(), String> {
+    let sections = match data.as_object_mut().and_then(|obj| obj.remove("composed_sections")) {
+        Some(serde_json::Value::Array(sections)) => sections,
+        _ => Vec::new(),
+    };
+    let sections: Vec<serde_json::Value> = sections
+        .into_iter()
+        .map(|section| {
+            let kind = section.get("kind").cloned().unwrap_or(serde_json::json!("Scene"));
+            let text = section.get("text").and_then(|t| t.as_str()).unwrap_or_default();
+            let generated = if kind == "Scene" { text } else { text.trim() };
+            serde_json::json!({
+                "id": section.get("id").cloned().unwrap_or(serde_json::json!(0)),
+                "kind": kind,
+                "generated": generated,
+                "frame": ["", ""],
+            })
+        })
+        .collect();
+    insert_default(
+        data,
+        "composed",
+        serde_json::json!({ "header": "", "footer": "", "separator": "\n\n---\n\n", "sections": sections }),
+    );
+    insert_default(
+        data,
+        "template",
+        serde_json::json!({ "header": "", "footer": "", "heading": "{{text}}", "scene": "{{text}}", "separator": "---" }),
+    );
+    Ok(())
+}
+
+// v5 → v6: 文書生成から除外するプロット
+fn migrate_v5_to_v6(data: &mut serde_json::Value) -> Result<(), String> {
+    for plot in plots_mut(data)? {
+        insert_default(plot, "excluded", serde_json::json!(false));
+    }
+    Ok(())
+}
+
+// v6 → v7: タグと、タグによる絞り込み・除外
+fn migrate_v6_to_v7(data: &mut serde_json::Value) -> Result<(), String> {
+    for plot in plots_mut(data)? {
+        insert_default(plot, "tags", serde_json::json!([]));
+    }
+    insert_default(data, "filter", serde_json::json!({ "tags": [], "compose_only": false }));
+    insert_default(data, "excluded_tags", serde_json::json!([]));
+    Ok(())
+}
+
+// バージョン番号の無いファイルは最初の形式（v0）
+pub fn format_version(data: &serde_json::Value) -> Result<u32, String> {
+    match data.get("version") {
+        None => Ok(0),
+        Some(v) => v
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .ok_or_else(|| format!("バージョン番号が不正です: {}", v)),
+    }
+}
+
+impl SaveData {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::from_json(&content)
+    }
+
+    pub fn from_json(content: &str) -> Result<Self, String> {
+        let data: serde_json::Value = serde_json::from_str(content)
+            .map_err(|e| format!("SCRFファイルとして読み込めません: {}", e))?;
+        Self::from_value(data)
+    }
+
+    pub fn from_value(mut data: serde_json::Value) -> Result<Self, String> {
+        let version = format_version(&data)?;
+        if version > FORMAT_VERSION {
+            return Err(format!(
+                "このファイルは新しいバージョンのStoryComposerで保存されています（形式 v{}）。\n\
+                 このバージョンで開けるのは形式 v{} までです。アプリを更新してください。",
+                version, FORMAT_VERSION
+            ));
+        }
+
+        for migrate in &MIGRATIONS[version as usize..] {
+            migrate(&mut data)?;
+        }
+        if let Some(obj) = data.as_object_mut() {
+            obj.insert("version".to_owned(), serde_json::json!(FORMAT_VERSION));
+        }
+
+        serde_json::from_value(data).map_err(|e| format!("SCRFファイルの内容が不正です: {}", e))
+    }
+
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(self).map_err(|e| e.to_string())
+    }
+}
+
+impl Document {
+    pub fn to_save_data(&self) -> SaveData {
+        SaveData {
+            version: FORMAT_VERSION,
+            plots: self.plots.clone(),
+            composed_text: self.composed_text.clone(),
+            meta: self.meta.clone(),
+            template: self.template.clone(),
+            composed: self.composed.clone(),
+            filter: self.filter.clone(),
+            excluded_tags: self.excluded_tags.clone(),
+        }
+    }
+
+    pub fn from_save_data(data: SaveData) -> Self {
+        let mut doc = Self::from_parts(data.plots, data.composed_text);
+        doc.meta = data.meta;
+        doc.template = data.template;
+        doc.composed = data.composed;
+        doc.filter = data.filter;
+        doc.excluded_tags = data.excluded_tags;
+        doc
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        SaveData::load(path).map(Self::from_save_data)
+    }
+
+    // 既存のファイルはバックアップに回してから置き換える
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let json = self.to_save_data().to_json()?;
+        rotate_backups(path).map_err(|e| format!("バックアップの作成に失敗しました: {}", e))?;
+        write_atomic(path, json.as_bytes()).map_err(|e| e.to_string())
+    }
+}
+
+// 一時ファイルに書き込んでから置き換えるので、書き込み途中で落ちても元のファイルは壊れない
+pub fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let mut file = std::fs::File::create(&tmp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    drop(file);
+
+    std::fs::rename(&tmp_path, path)
+}
+
+// foo.scrf の n 世代前のバックアップは foo.scrf.n.bak
+pub fn backup_path(path: &Path, generation: usize) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{}.bak", generation));
+    path.with_file_name(name)
+}
+
+// 既存のファイルを1世代目のバックアップにし、古い世代を1つずつずらす
+pub fn rotate_backups(path: &Path) -> std::io::Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    for generation in (1..BACKUP_GENERATIONS).rev() {
+        let from = backup_path(path, generation);
+        if from.exists() {
+            std::fs::rename(&from, backup_path(path, generation + 1))?;
+        }
+    }
+    std::fs::copy(path, backup_path(path, 1))?;
+    Ok(())
+}
+
+pub struct BackupEntry {
+    pub path: PathBuf,
+    pub generation: usize,
+    pub modified: SystemTime,
+}
+
+pub fn list_backups(path: &Path) -> Vec<BackupEntry> {
+    (1..=BACKUP_GENERATIONS)
+        .filter_map(|generation| {
+            let backup = backup_path(path, generation);
+            let modified = std::fs::metadata(&backup).and_then(|m| m.modified()).ok()?;
+            Some(BackupEntry {
+                path: backup,
+                generation,
+                modified,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::{PlotColor, PlotKind, PlotStatus};
+
+    // バージョン番号の無い最初の形式（プロットはIDと本文だけ）
+    const V0: &str = r#"{
+        "plots": [{ "id": 0, "text": "一" }, { "id": 3, "text": "二" }],
+        "composed_text": "一\n\n---\n\n二"
+    }"#;
+
+    #[test]
+    fn migrates_v0_to_current() {
+        let data = SaveData::from_json(V0).unwrap();
+        assert_eq!(data.version, FORMAT_VERSION);
+        assert_eq!(data.plots.len(), 2);
+        let plot = &data.plots[1];
+        assert_eq!((plot.id, plot.text.as_str()), (3, "二"));
+        assert_eq!(plot.kind, PlotKind::Scene);
+        assert!(!plot.collapsed);
+        assert!(plot.title.is_empty() && plot.synopsis.is_empty() && plot.notes.is_empty());
+        assert_eq!(plot.status, PlotStatus::Idea);
+        assert_eq!(plot.color, PlotColor::None);
+        assert!(!plot.excluded);
+        assert!(plot.tags.is_empty());
+        assert_eq!(data.composed_text, "一\n\n---\n\n二");
+        assert!(data.meta.title.is_empty());
+        assert_eq!(data.template, ComposeTemplate::default());
+        assert!(data.composed.sections.is_empty());
+        assert!(!data.filter.is_active());
+        assert!(data.excluded_tags.is_empty());
+
+        // 新しいプロットには既存のどのIDとも重ならないIDを振る
+        let mut doc = Document::from_save_data(data);
+        assert_eq!(doc.allocate_id(), 4);
+    }
+
+    #[test]
+    fn migrates_v4_sections_to_compose_record() {
+        let v4 = r#"{
+            "version": 4,
+            "plots": [
+                { "id": 0, "text": "第一章", "kind": "Chapter", "collapsed": false, "title": "", "synopsis": "",
+                  "status": "Idea", "color": "None", "notes": "" },
+                { "id": 1, "text": "本文", "kind": "Scene", "collapsed": false, "title": "", "synopsis": "",
+                  "status": "Done", "color": "Red", "notes": "" }
+            ],
+            "composed_text": "第一章\n\n本文",
+            "meta": { "title": "題", "author": "", "date": "" },
+            "composed_sections": [
+                { "id": 0, "kind": "Chapter", "text": " 第一章 " },
+                { "id": 1, "kind": "Scene", "text": "本文" }
+            ]
+        }"#;
+        let data = SaveData::from_json(v4).unwrap();
+        assert_eq!(data.plots[1].status, PlotStatus::Done);
+        let record = &data.composed;
+        assert_eq!(record.separator, "\n\n---\n\n");
+        let generated: Vec<&str> = record.sections.iter().map(|s| s.generated.as_str()).collect();
+        assert_eq!(generated, ["第一章", "本文"]);
+        assert_eq!(record.sections[0].kind, PlotKind::Chapter);
+        assert_eq!(record.sections[1].frame, Some((String::new(), String::new())));
+        assert_eq!(record.text(), data.composed_text);
+    }
+
+    #[test]
+    fn rejects_newer_and_invalid_versions() {
+        let newer = format!(r#"{{ "version": {}, "plots": [], "composed_text": "" }}"#, FORMAT_VERSION + 1);
+        assert!(SaveData::from_json(&newer).err().unwrap().contains("新しいバージョン"));
+        assert!(SaveData::from_json(r#"{ "version": "1", "plots": [], "composed_text": "" }"#).is_err());
+        assert!(SaveData::from_json(r#"{ "composed_text": "" }"#).is_err());
+    }
+
+    #[test]
+    fn round_trips_current_format() {
+        let mut doc = Document::from_save_data(SaveData::from_json(V0).unwrap());
+        doc.plots[0].tags = vec!["主人公".to_owned()];
+        doc.excluded_tags = vec!["没".to_owned()];
+        doc.compose();
+        let json = doc.to_save_data().to_json().unwrap();
+        let loaded = Document::from_save_data(SaveData::from_json(&json).unwrap());
+        assert_eq!(loaded.plots[0].tags, ["主人公"]);
+        assert_eq!(loaded.excluded_tags, ["没"]);
+        assert_eq!(loaded.composed_text, doc.composed_text);
+        assert_eq!(loaded.composed.sections.len(), 2);
+    }
+}
@@ -0,0 +1,66 @@
+// 元に戻す／やり直しの履歴
+
+use std::collections::VecDeque;
+
+pub struct History<T> {
+    undo_stack: VecDeque<T>,
+    redo_stack: VecDeque<T>,
+    limit: usize,
+}
+
+impl<T> History<T> {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            undo_stack: VecDeque::new(),
+            redo_stack: VecDeque::new(),
+            limit,
+        }
+    }
+
+    // 変更前の状態を積む。新しい操作をしたらやり直しはできなくなる
+    pub fn push(&mut self, state: T) {
+        Self::push_limited(&mut self.undo_stack, state, self.limit);
+        self.redo_stack.clear();
+    }
+
+    // 現在の状態と引き換えに、1つ前の状態を返す
+    pub fn undo(&mut self, current: T) -> Option<T> {
+        let state = self.undo_stack.pop_back()?;
+        Self::push_limited(&mut self.redo_stack, current, self.limit);
+        Some(state)
+    }
+
+    pub fn redo(&mut self, current: T) -> Option<T> {
+        let state = self.redo_stack.pop_back()?;
+        Self::push_limited(&mut self.undo_stack, current, self.limit);
+        Some(state)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+
+    pub fn undo_len(&self) -> usize {
+        self.undo_stack.len()
+    }
+
+    pub fn redo_len(&self) -> usize {
+        self.redo_stack.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+    }
+
+    fn push_limited(stack: &mut VecDeque<T>, state: T, limit: usize) {
+        stack.push_back(state);
+        if stack.len() > limit {
+            stack.pop_front();
+        }
+    }
+}
@@ -0,0 +1,215 @@
+// テキスト・Markdownファイルを区切りごとのプロットに分けて取り込む
+
+use crate::document::{PlotFragment, PlotKind};
+use regex::Regex;
+
+#[derive(Clone, Copy, PartialEq, Default)]
+pub enum SplitRule {
+    // 空行ごと
+    #[default]
+    BlankLines,
+    // "---" だけの行ごと
+    Separator,
+    // Markdownの見出し（# は幕、## は章、### 以下はシーンのタイトル）
+    Headings,
+    // 正規表現に一致する行ごと
+    Regex,
+}
+
+impl SplitRule {
+    pub fn all() -> &'static [SplitRule] {
+        &[SplitRule::BlankLines, SplitRule::Separator, SplitRule::Headings, SplitRule::Regex]
+    }
+
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            SplitRule::BlankLines => "空行",
+            SplitRule::Separator => "区切り線（---）",
+            SplitRule::Headings => "見出し（#）",
+            SplitRule::Regex => "正規表現",
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct ImportOptions {
+    pub rule: SplitRule,
+    // SplitRule::Regex で区切りとする行のパターン
+    pub pattern: String,
+}
+
+// BOMの有無に関わらずUTF-8として読めなければShift_JIS（CP932）とみなす
+pub fn decode_text(bytes: &[u8]) -> String {
+    let bytes = bytes.strip_prefix("\u{FEFF}".as_bytes()).unwrap_or(bytes);
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_owned(),
+        Err(_) => encoding_rs::SHIFT_JIS.decode(bytes).0.into_owned(),
+    }
+}
+
+// Markdownの先頭の --- で囲まれたフロントマターを取り除く
+fn strip_front_matter(text: &str) -> &str {
+    let Some(rest) = text.strip_prefix("---\n") else {
+        return text;
+    };
+    match rest.find("\n---\n") {
+        Some(end) => &rest[end + "\n---\n".len()..],
+        None => text,
+    }
+}
+
+fn markdown_heading(line: &str) -> Option<(usize, &str)> {
+    let level = line.chars().take_while(|&c| c == '#').count();
+    let title = line[level..].strip_prefix(' ')?;
+    (level > 0).then(|| (level, title.trim().trim_end_matches('#').trim()))
+}
+
+// 区切りまでの行をまとめたもの
+struct Section {
+    kind: PlotKind,
+    title: String,
+    lines: Vec<String>,
+}
+
+impl Section {
+    fn new(kind: PlotKind, title: &str) -> Self {
+        Self {
+            kind,
+            title: title.to_owned(),
+            lines: Vec::new(),
+        }
+    }
+
+    fn text(&self) -> String {
+        let start = self.lines.iter().position(|l| !l.trim().is_empty()).unwrap_or(self.lines.len());
+        let end = self.lines.iter().rposition(|l| !l.trim().is_empty()).map_or(start, |i| i + 1);
+        self.lines[start..end].join("\n")
+    }
+}
+
+// 区切りの行は取り除き、正規表現に一致した行は次のプロットのタイトルにする。
+// 返すプロットのIDは未割り当て（取り込む文書で振り直す）。
+pub fn split(text: &str, markdown: bool, options: &ImportOptions) -> Result<Vec<PlotFragment>, String> {
+    let regex = match options.rule {
+        SplitRule::Regex if options.pattern.is_empty() => return Err("正規表現を入力してください".to_owned()),
+        SplitRule::Regex => Some(Regex::new(&options.pattern).map_err(|e| format!("正規表現が不正です: {}", e))?),
+        _ => None,
+    };
+    let text = text.replace("\r\n", "\n");
+    let text = if markdown { strip_front_matter(&text) } else { &text };
+
+    let mut sections = vec![Section::new(PlotKind::Scene, "")];
+    for line in text.split('\n') {
+        let next = match options.rule {
+            SplitRule::BlankLines if line.trim().is_empty() => Some(Section::new(PlotKind::Scene, "")),
+            SplitRule::Separator if line.trim() == "---" => Some(Section::new(PlotKind::Scene, "")),
+            SplitRule::Headings => markdown_heading(line).map(|(level, title)| match level {
+                1 => Section::new(PlotKind::Act, title),
+                2 => Section::new(PlotKind::Chapter, title),
+                _ => Section::new(PlotKind::Scene, title),
+            }),
+            SplitRule::Regex if regex.as_ref().is_some_and(|r| r.is_match(line)) => {
+                Some(Section::new(PlotKind::Scene, line.trim()))
+            }
+            _ => None,
+        };
+        match next {
+            Some(section) => sections.push(section),
+            None => sections.last_mut().unwrap().lines.push(line.to_owned()),
+        }
+    }
+
+    let mut plots = Vec::new();
+    for section in sections {
+        let body = section.text();
+        if section.kind.is_heading() {
+            // 幕・章は見出しの文字列を本文とする（見出しの下の文章は続くシーンにする）
+            plots.push(PlotFragment {
+                text: section.title,
+                ..PlotFragment::new(0, section.kind)
+            });
+            if !body.is_empty() {
+                plots.push(PlotFragment {
+                    text: body,
+                    ..PlotFragment::new(0, PlotKind::Scene)
+                });
+            }
+        } else if !body.is_empty() || !section.title.is_empty() {
+            plots.push(PlotFragment {
+                title: section.title,
+                text: body,
+                ..PlotFragment::new(0, PlotKind::Scene)
+            });
+        }
+    }
+    Ok(plots)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(rule: SplitRule, pattern: &str) -> ImportOptions {
+        ImportOptions {
+            rule,
+            pattern: pattern.to_owned(),
+        }
+    }
+
+    fn summary(plots: &[PlotFragment]) -> Vec<(PlotKind, &str, &str)> {
+        plots.iter().map(|p| (p.kind, p.title.as_str(), p.text.as_str())).collect()
+    }
+
+    #[test]
+    fn split_by_blank_lines() {
+        let plots = split("一行目\r\n続き\r\n\r\n二つ目\n\n\n三つ目\n", false, &ImportOptions::default()).unwrap();
+        let texts: Vec<&str> = plots.iter().map(|p| p.text.as_str()).collect();
+        assert_eq!(texts, ["一行目\n続き", "二つ目", "三つ目"]);
+        assert!(plots.iter().all(|p| p.kind == PlotKind::Scene));
+    }
+
+    #[test]
+    fn split_by_separator() {
+        let plots = split("一\n\n続き\n---\n二\n  ---  \n\n---\n", false, &options(SplitRule::Separator, "")).unwrap();
+        let texts: Vec<&str> = plots.iter().map(|p| p.text.as_str()).collect();
+        assert_eq!(texts, ["一\n\n続き", "二"]);
+    }
+
+    #[test]
+    fn split_by_markdown_headings() {
+        let text = "---\ntitle: 題\n---\n# 第一部\n## 第一章 ##\n前書き\n### 出会い\n本文\n#タグ\n";
+        let plots = split(text, true, &options(SplitRule::Headings, "")).unwrap();
+        assert_eq!(
+            summary(&plots),
+            [
+                (PlotKind::Act, "", "第一部"),
+                (PlotKind::Chapter, "", "第一章"),
+                (PlotKind::Scene, "", "前書き"),
+                (PlotKind::Scene, "出会い", "本文\n#タグ"),
+            ]
+        );
+    }
+
+    #[test]
+    fn split_by_regex() {
+        let text = "序\n第1話\n本文1\n第2話\n\n本文2";
+        let plots = split(text, false, &options(SplitRule::Regex, "^第.+話$")).unwrap();
+        assert_eq!(
+            summary(&plots),
+            [
+                (PlotKind::Scene, "", "序"),
+                (PlotKind::Scene, "第1話", "本文1"),
+                (PlotKind::Scene, "第2話", "本文2"),
+            ]
+        );
+        assert!(split(text, false, &options(SplitRule::Regex, "")).is_err());
+        assert!(split(text, false, &options(SplitRule::Regex, "(")).is_err());
+    }
+
+    #[test]
+    fn decode_utf8_and_shift_jis() {
+        assert_eq!(decode_text("\u{FEFF}本文".as_bytes()), "本文");
+        let (sjis, _, _) = encoding_rs::SHIFT_JIS.encode("日本語");
+        assert_eq!(decode_text(&sjis), "日本語");
+    }
+}
@@ -0,0 +1,19 @@
+// StoryComposer の文書モデル。
+// GUI（egui）に依存しない部分をまとめ、コマンドラインや外部のツールからも.scrfファイルを扱えるようにする。
+
+pub mod decompose;
+pub mod document;
+pub mod export;
+pub mod format;
+pub mod history;
+pub mod import;
+pub mod markup;
+pub mod recompose;
+pub mod search;
+pub mod template;
+
+pub use document::{compose_plots, Document, DocumentMeta, PlotColor, PlotFragment, PlotKind, PlotStatus, MAX_PLOTS};
+pub use format::{SaveData, FORMAT_VERSION};
+pub use history::History;
+pub use search::{SearchLocation, SearchResult};
+pub use template::ComposeTemplate;
@@ -1,21 +1,44 @@
 #![windows_subsystem = "windows"]
 
+mod cli;
+
 use eframe::egui::{self, FontData, FontDefinitions, FontFamily};
 use serde::{Deserialize, Serialize};
-use std::collections::VecDeque;
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use story_composer::export::epub::{self, EpubOptions};
+use story_composer::export::pdf::{self, PdfOptions, LAYOUTS};
+use story_composer::export::site::{self, Site};
+use story_composer::export::{self, docx, odt, ExportFormat, LineEnding, TextEncoding, TextOptions};
+use story_composer::decompose::{self, DiffLine, PlotChange};
+use story_composer::format::{self, BackupEntry};
+use story_composer::import::{self, ImportOptions, SplitRule};
+use story_composer::markup::{self, Inline};
+use story_composer::recompose::{self, Recompose};
+use story_composer::{
+    ComposeTemplate, Document, History, PlotColor, PlotFragment, PlotKind, PlotStatus, SaveData, SearchLocation,
+    SearchResult, MAX_PLOTS,
+};
 
-const MAX_PLOTS: usize = 1024;
 const MAX_UNDO_HISTORY: usize = 100;
 const SETTINGS_FILE: &str = "sc.dat";
+const RECOVERY_FILE: &str = "sc_recovery.scrf";
+// 未保存の内容をメモリ上に控える間隔と、復元用ファイルに書き出す間隔
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(3);
+const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(30);
+const INDENT_WIDTH: f32 = 20.0;
+const CARD_SIZE: egui::Vec2 = egui::vec2(200.0, 130.0);
 
 // Available font sizes
 const FONT_SIZES: &[f32] = &[12.0, 14.0, 16.0, 18.0, 20.0, 24.0, 28.0, 32.0];
 const DEFAULT_FONT_SIZE: f32 = 20.0;
 
 // Available fonts
-#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Default, Serialize, Deserialize)]
 enum FontChoice {
+    #[default]
     YuGothic,
     Meiryo,
     MSGothic,
@@ -46,16 +69,31 @@ impl FontChoice {
     }
 }
 
-impl Default for FontChoice {
-    fn default() -> Self {
-        FontChoice::YuGothic
-    }
+// プロットの表示方法（一覧・コルクボード）
+#[derive(Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+enum PlotView {
+    #[default]
+    List,
+    Corkboard,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 struct AppSettings {
     font_size: f32,
     font_choice: FontChoice,
+    // PDFの書き出しで埋め込むフォント
+    #[serde(default)]
+    pdf_font_path: Option<PathBuf>,
+    #[serde(default)]
+    pdf_font_index: u32,
+    // 前回テキストを書き出したときの文字コードと改行コード
+    #[serde(default)]
+    text_export: TextOptions,
+    #[serde(default)]
+    plot_view: PlotView,
+    // アウトラインの表示
+    #[serde(default)]
+    show_outline: bool,
 }
 
 impl Default for AppSettings {
@@ -63,19 +101,27 @@ impl Default for AppSettings {
         Self {
             font_size: DEFAULT_FONT_SIZE,
             font_choice: FontChoice::default(),
+            pdf_font_path: None,
+            pdf_font_index: 0,
+            text_export: TextOptions::default(),
+            plot_view: PlotView::default(),
+            show_outline: false,
         }
     }
 }
 
+// 設定ファイルなどは実行ファイルと同じフォルダに置く
+fn app_data_path(file_name: &str) -> Option<PathBuf> {
+    let exe_path = std::env::current_exe().ok()?;
+    Some(exe_path.parent()?.join(file_name))
+}
+
 impl AppSettings {
     fn load() -> Self {
-        if let Ok(exe_path) = std::env::current_exe() {
-            if let Some(exe_dir) = exe_path.parent() {
-                let settings_path = exe_dir.join(SETTINGS_FILE);
-                if let Ok(content) = std::fs::read_to_string(&settings_path) {
-                    if let Ok(settings) = serde_json::from_str(&content) {
-                        return settings;
-                    }
+        if let Some(settings_path) = app_data_path(SETTINGS_FILE) {
+            if let Ok(content) = std::fs::read_to_string(&settings_path) {
+                if let Ok(settings) = serde_json::from_str(&content) {
+                    return settings;
                 }
             }
         }
@@ -83,12 +129,9 @@ impl AppSettings {
     }
 
     fn save(&self) {
-        if let Ok(exe_path) = std::env::current_exe() {
-            if let Some(exe_dir) = exe_path.parent() {
-                let settings_path = exe_dir.join(SETTINGS_FILE);
-                if let Ok(json) = serde_json::to_string_pretty(self) {
-                    let _ = std::fs::write(&settings_path, json);
-                }
+        if let Some(settings_path) = app_data_path(SETTINGS_FILE) {
+            if let Ok(json) = serde_json::to_string_pretty(self) {
+                let _ = std::fs::write(&settings_path, json);
             }
         }
     }
@@ -119,33 +162,143 @@ fn setup_font(ctx: &egui::Context, font_choice: &FontChoice) {
     }
 }
 
-#[derive(Clone, Serialize, Deserialize)]
-struct PlotFragment {
-    id: usize,
+// テキスト・Markdownの取り込みダイアログ。区切り方を変えるたびに分割結果を作り直す
+struct ImportDialog {
+    path: PathBuf,
     text: String,
+    options: ImportOptions,
+    preview: Result<Vec<PlotFragment>, String>,
 }
 
-#[derive(Clone, Serialize, Deserialize)]
-struct SaveData {
-    plots: Vec<PlotFragment>,
-    composed_text: String,
+impl ImportDialog {
+    fn open(path: PathBuf) -> Result<Self, String> {
+        let bytes = std::fs::read(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut dialog = Self {
+            path,
+            text: import::decode_text(&bytes),
+            options: ImportOptions::default(),
+            preview: Ok(Vec::new()),
+        };
+        if dialog.is_markdown() {
+            dialog.options.rule = SplitRule::Headings;
+        }
+        dialog.update_preview();
+        Ok(dialog)
+    }
+
+    fn is_markdown(&self) -> bool {
+        self.path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("md") || e.eq_ignore_ascii_case("markdown"))
+    }
+
+    fn update_preview(&mut self) {
+        self.preview = import::split(&self.text, self.is_markdown(), &self.options);
+    }
 }
 
-#[derive(Clone)]
-struct AppState {
-    plots: Vec<PlotFragment>,
-    composed_text: String,
+// 出力テキストの編集をプロットに戻すダイアログ。変更ごとに反映するかを選ぶ
+struct DecomposeItem {
+    change: PlotChange,
+    diff: Vec<DiffLine>,
+    accept: bool,
+}
+
+struct DecomposeDialog {
+    items: Vec<DecomposeItem>,
+    problems: Vec<String>,
+}
+
+// 文書生成で出力テキストの手直しが失われる恐れがある場合の確認
+enum RecomposeDialog {
+    // 区間を対応付けられない場合に、すべて作り直すかを確認する
+    ConfirmFull(String),
+    // プロットと出力テキストの両方が変更された区間ごとに、どちらを使うかを選ぶ
+    Conflicts {
+        result: Recompose,
+        diffs: Vec<Vec<DiffLine>>,
+        use_generated: Vec<bool>,
+    },
+}
+
+// 変更のある行の前後だけを残し、離れた変更の間は省略する
+const DIFF_CONTEXT: usize = 2;
+
+fn diff_view(ui: &mut egui::Ui, diff: &[DiffLine]) {
+    let changed: Vec<usize> = (0..diff.len()).filter(|&i| !matches!(diff[i], DiffLine::Same(_))).collect();
+    let near_change = |i: usize| changed.iter().any(|&c| c.abs_diff(i) <= DIFF_CONTEXT);
+    let mut skipped = false;
+    for (i, line) in diff.iter().enumerate() {
+        let (prefix, text, color) = match line {
+            DiffLine::Same(text) => {
+                if !near_change(i) {
+                    if !skipped {
+                        ui.weak("…");
+                        skipped = true;
+                    }
+                    continue;
+                }
+                ("  ", text, ui.visuals().weak_text_color())
+            }
+            DiffLine::Removed(text) => ("- ", text, egui::Color32::from_rgb(230, 110, 110)),
+            DiffLine::Added(text) => ("+ ", text, egui::Color32::from_rgb(110, 200, 120)),
+        };
+        skipped = false;
+        ui.label(egui::RichText::new(format!("{}{}", prefix, text)).monospace().color(color));
+    }
+}
+
+// 異常終了時に復元するための控え。元のファイルの場所も一緒に残す
+#[derive(Serialize)]
+struct RecoveryData {
+    file_path: Option<PathBuf>,
+    data: SaveData,
+}
+
+impl RecoveryData {
+    fn load() -> Option<Self> {
+        let path = app_data_path(RECOVERY_FILE)?;
+        let content = std::fs::read_to_string(path).ok()?;
+        let mut value: serde_json::Value = serde_json::from_str(&content).ok()?;
+        let file_path = serde_json::from_value(value["file_path"].take()).ok()?;
+        let data = SaveData::from_value(value["data"].take()).ok()?;
+        Some(Self { file_path, data })
+    }
+
+    fn write(json: &str) {
+        if let Some(path) = app_data_path(RECOVERY_FILE) {
+            if let Err(e) = format::write_atomic(&path, json.as_bytes()) {
+                eprintln!("Autosave error: {}", e);
+            }
+        }
+    }
+
+    fn remove() {
+        if let Some(path) = app_data_path(RECOVERY_FILE) {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+// パニック時にも最後の控えを書き出す
+fn install_recovery_hook(snapshot: Arc<Mutex<Option<String>>>) {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        if let Ok(snapshot) = snapshot.try_lock() {
+            if let Some(ref json) = *snapshot {
+                RecoveryData::write(json);
+            }
+        }
+        default_hook(info);
+    }));
 }
 
 struct StoryComposerApp {
-    plots: Vec<PlotFragment>,
-    composed_text: String,
-    next_id: usize,
+    doc: Document,
     current_file_path: Option<PathBuf>,
 
     // Undo/Redo
-    undo_stack: VecDeque<AppState>,
-    redo_stack: VecDeque<AppState>,
+    history: History<Document>,
 
     // Search/Replace dialog
     show_search_dialog: bool,
@@ -157,6 +310,22 @@ struct StoryComposerApp {
 
     // UI state
     delete_confirm_id: Option<usize>,
+    // 番号を指定して移動するプロットのIDと、移動先の番号（1から）
+    move_dialog: Option<(Vec<usize>, usize)>,
+
+    // 複数選択（プロットのID）。Shift+クリックの範囲は前のフレームで表示した順に選ぶ
+    selection: Vec<usize>,
+    selection_anchor: Option<usize>,
+    visible_plot_ids: Vec<usize>,
+    batch_delete_confirm: bool,
+    batch_tag_input: String,
+
+    // コルクボードのカードから開いたプロットの編集ウィンドウ
+    card_editor: Option<usize>,
+
+    // アウトラインで選んだプロット（ID）と出力テキストの位置（文字単位）まで次のフレームでスクロールする
+    scroll_to_plot: Option<usize>,
+    scroll_to_composed: Option<usize>,
     pending_action: Option<(usize, PlotAction)>,
 
     // Font settings
@@ -166,32 +335,57 @@ struct StoryComposerApp {
     // Document state
     is_dirty: bool,
     show_close_confirm: bool,
-}
 
-#[derive(Clone)]
-#[allow(dead_code)]
-struct SearchResult {
-    location: SearchLocation,
-    plot_index: Option<usize>,
-    start: usize,
-    end: usize,
-}
+    // Crash recovery
+    recovery_snapshot: Arc<Mutex<Option<String>>>,
+    last_snapshot: Instant,
+    last_autosave: Instant,
+    autosave_pending: bool,
+    recovered: Option<RecoveryData>,
 
-#[derive(Clone, PartialEq)]
-enum SearchLocation {
-    Plot,
-    ComposedText,
+    // Backup restore dialog
+    backup_entries: Option<Vec<BackupEntry>>,
+
+    // Document info dialog
+    show_meta_dialog: bool,
+
+    // Compose template dialog
+    show_template_dialog: bool,
+
+    // プロットごとのタグの入力欄
+    tag_inputs: HashMap<usize, String>,
+
+    // EPUB export dialog
+    epub_dialog: Option<EpubOptions>,
+
+    // PDF export dialog
+    pdf_dialog: Option<PdfOptions>,
+
+    // テキストの書き出しダイアログ
+    text_dialog: Option<TextOptions>,
+
+    // テキスト・Markdownの取り込みダイアログ
+    import_dialog: Option<ImportDialog>,
+
+    // 出力テキストをプロットに反映するダイアログ
+    decompose_dialog: Option<DecomposeDialog>,
+
+    // 文書生成の確認ダイアログ
+    recompose_dialog: Option<RecomposeDialog>,
+
+    // 投稿サイト向けの書き出しダイアログ
+    site_dialog: Option<(Site, TextOptions)>,
+
+    // 出力テキストをルビ付きのプレビューで表示する
+    show_preview: bool,
 }
 
 impl StoryComposerApp {
-    fn new(settings: AppSettings) -> Self {
+    fn new(settings: AppSettings, recovery_snapshot: Arc<Mutex<Option<String>>>) -> Self {
         Self {
-            plots: vec![PlotFragment { id: 0, text: String::new() }],
-            composed_text: String::new(),
-            next_id: 1,
+            doc: Document::new(),
             current_file_path: None,
-            undo_stack: VecDeque::new(),
-            redo_stack: VecDeque::new(),
+            history: History::new(MAX_UNDO_HISTORY),
             show_search_dialog: false,
             show_replace_dialog: false,
             search_text: String::new(),
@@ -199,282 +393,927 @@ impl StoryComposerApp {
             search_results: Vec::new(),
             current_search_index: 0,
             delete_confirm_id: None,
+            move_dialog: None,
+            selection: Vec::new(),
+            selection_anchor: None,
+            visible_plot_ids: Vec::new(),
+            batch_delete_confirm: false,
+            batch_tag_input: String::new(),
+            card_editor: None,
+            scroll_to_plot: None,
+            scroll_to_composed: None,
             pending_action: None,
             settings,
             font_changed: false,
             is_dirty: false,
             show_close_confirm: false,
+            recovery_snapshot,
+            last_snapshot: Instant::now(),
+            last_autosave: Instant::now(),
+            autosave_pending: false,
+            recovered: RecoveryData::load(),
+            backup_entries: None,
+            show_meta_dialog: false,
+            show_template_dialog: false,
+            tag_inputs: HashMap::new(),
+            epub_dialog: None,
+            pdf_dialog: None,
+            text_dialog: None,
+            import_dialog: None,
+            decompose_dialog: None,
+            recompose_dialog: None,
+            site_dialog: None,
+            show_preview: false,
         }
     }
 
     fn save_state_for_undo(&mut self) {
-        let state = AppState {
-            plots: self.plots.clone(),
-            composed_text: self.composed_text.clone(),
-        };
-        self.undo_stack.push_back(state);
-        if self.undo_stack.len() > MAX_UNDO_HISTORY {
-            self.undo_stack.pop_front();
-        }
-        self.redo_stack.clear();
+        self.history.push(self.doc.clone());
         self.is_dirty = true;
     }
 
+    // 文書を変更する操作を実行し、変更があれば1回の元に戻す単位として履歴に積む
+    fn edit(&mut self, f: impl FnOnce(&mut Document) -> bool) {
+        let before = self.doc.clone();
+        if f(&mut self.doc) {
+            self.history.push(before);
+            self.is_dirty = true;
+        }
+    }
+
     fn undo(&mut self) {
-        if let Some(state) = self.undo_stack.pop_back() {
-            let current = AppState {
-                plots: self.plots.clone(),
-                composed_text: self.composed_text.clone(),
-            };
-            self.redo_stack.push_back(current);
-            if self.redo_stack.len() > MAX_UNDO_HISTORY {
-                self.redo_stack.pop_front();
+        if self.history.can_undo() {
+            if let Some(state) = self.history.undo(self.doc.clone()) {
+                self.doc = state;
             }
-            self.plots = state.plots;
-            self.composed_text = state.composed_text;
         }
     }
 
     fn redo(&mut self) {
-        if let Some(state) = self.redo_stack.pop_back() {
-            let current = AppState {
-                plots: self.plots.clone(),
-                composed_text: self.composed_text.clone(),
-            };
-            self.undo_stack.push_back(current);
-            if self.undo_stack.len() > MAX_UNDO_HISTORY {
-                self.undo_stack.pop_front();
+        if self.history.can_redo() {
+            if let Some(state) = self.history.redo(self.doc.clone()) {
+                self.doc = state;
             }
-            self.plots = state.plots;
-            self.composed_text = state.composed_text;
         }
     }
 
+    // 出力テキストの手直しを残し、前回から変更のあったプロットの区間だけを作り直す
     fn compose(&mut self) {
+        if self.doc.composed_text.trim().is_empty() {
+            self.compose_all();
+            return;
+        }
+        if self.doc.composed.sections.is_empty() {
+            // 生成元の記録が無い文書（以前の形式のファイルなど）
+            if self.doc.composed_text == self.doc.compose_record().text() {
+                self.compose_all();
+            } else {
+                self.recompose_dialog = Some(RecomposeDialog::ConfirmFull(
+                    "出力テキストには生成元のプロットの記録がありません。".to_owned(),
+                ));
+            }
+            return;
+        }
+        match recompose::recompose(&self.doc) {
+            Ok(result) if result.conflicts.is_empty() && result.removed_edits.is_empty() => {
+                self.apply_recompose(&result);
+            }
+            Ok(result) => {
+                let diffs = result
+                    .conflicts
+                    .iter()
+                    .map(|c| decompose::diff_lines(&c.edited, &c.generated))
+                    .collect();
+                let use_generated = vec![false; result.conflicts.len()];
+                self.recompose_dialog = Some(RecomposeDialog::Conflicts {
+                    result,
+                    diffs,
+                    use_generated,
+                });
+            }
+            Err(e) => {
+                self.recompose_dialog = Some(RecomposeDialog::ConfirmFull(format!(
+                    "出力テキストをプロットごとの区間に対応付けられません（{}）。",
+                    e
+                )));
+            }
+        }
+    }
+
+    fn compose_all(&mut self) {
         self.save_state_for_undo();
-        self.composed_text = self.plots
-            .iter()
-            .map(|p| p.text.as_str())
-            .collect::<Vec<&str>>()
-            .join("\n\n---\n\n");
+        self.doc.compose();
     }
 
-    fn new_document(&mut self) {
+    fn apply_recompose(&mut self, result: &Recompose) {
         self.save_state_for_undo();
-        self.plots = vec![PlotFragment { id: 0, text: String::new() }];
-        self.composed_text = String::new();
-        self.next_id = 1;
+        self.doc.set_composed(result.text(), result.record().clone());
+    }
+
+    // 出力テキストをプロットごとの区間に分け、変更点を確認するダイアログを開く
+    fn open_decompose_dialog(&mut self) {
+        match decompose::decompose(&self.doc) {
+            Ok(result) => {
+                let items = result
+                    .changes
+                    .into_iter()
+                    .map(|change| DecomposeItem {
+                        diff: decompose::diff_lines(&change.old, &change.new),
+                        change,
+                        accept: true,
+                    })
+                    .collect();
+                self.decompose_dialog = Some(DecomposeDialog {
+                    items,
+                    problems: result.problems,
+                });
+            }
+            Err(e) => show_error("プロットに反映", &e),
+        }
+    }
+
+    // 選んだ変更だけをまとめて1回の操作としてプロットに書き戻す
+    fn apply_decompose(&mut self, items: &[DecomposeItem]) {
+        self.edit(|doc| {
+            let mut changed = false;
+            for item in items.iter().filter(|item| item.accept) {
+                if let Some(index) = doc.plot_index(item.change.id) {
+                    doc.plots[index].text = item.change.new.clone();
+                    doc.mark_composed(item.change.id);
+                    changed = true;
+                }
+            }
+            changed
+        });
+    }
+
+    fn new_document(&mut self) {
+        self.doc = Document::new();
+        self.clear_selection();
+        self.card_editor = None;
         self.current_file_path = None;
-        self.undo_stack.clear();
-        self.redo_stack.clear();
+        self.history.clear();
         self.is_dirty = false;
+        self.clear_recovery();
     }
 
-    fn add_plot_after(&mut self, index: usize) {
-        if self.plots.len() >= MAX_PLOTS {
-            return;
-        }
-        self.save_state_for_undo();
-        let new_plot = PlotFragment {
-            id: self.next_id,
-            text: String::new(),
-        };
-        self.next_id += 1;
-        self.plots.insert(index + 1, new_plot);
+    fn add_plot_after(&mut self, index: usize, kind: PlotKind) {
+        self.edit(|doc| doc.add_plot_after(index, kind));
     }
 
     fn remove_plot(&mut self, index: usize) {
-        if self.plots.len() <= 1 {
-            return;
-        }
-        self.save_state_for_undo();
-        self.plots.remove(index);
+        self.edit(|doc| doc.remove_plot(index));
+    }
+
+    fn set_plot_kind(&mut self, index: usize, kind: PlotKind) {
+        self.edit(|doc| doc.set_plot_kind(index, kind));
+    }
+
+    fn set_plot_status(&mut self, index: usize, status: PlotStatus) {
+        self.edit(|doc| {
+            doc.plots[index].status = status;
+            true
+        });
+    }
+
+    fn set_plot_excluded(&mut self, index: usize, excluded: bool) {
+        self.edit(|doc| {
+            doc.plots[index].excluded = excluded;
+            true
+        });
+    }
+
+    fn set_plot_color(&mut self, index: usize, color: PlotColor) {
+        self.edit(|doc| {
+            doc.plots[index].color = color;
+            true
+        });
     }
 
     fn move_plot_up(&mut self, index: usize) {
-        if index == 0 {
-            return;
-        }
-        self.save_state_for_undo();
-        self.plots.swap(index, index - 1);
+        self.edit(|doc| doc.move_plot_up(index));
     }
 
     fn move_plot_down(&mut self, index: usize) {
-        if index >= self.plots.len() - 1 {
-            return;
-        }
-        self.save_state_for_undo();
-        self.plots.swap(index, index + 1);
+        self.edit(|doc| doc.move_plot_down(index));
     }
 
-    fn save_file(&mut self, path: &PathBuf) -> Result<(), String> {
-        let save_data = SaveData {
-            plots: self.plots.clone(),
-            composed_text: self.composed_text.clone(),
+    // 見出しは配下のシーンごと、target の位置のプロットの直前へ移動する。
+    // 複数選択したプロットの1つを動かした場合は、選択したものをまとめて移動する
+    fn move_plot_before(&mut self, index: usize, target: usize) {
+        let indices = if self.selection.len() > 1 && self.selection.contains(&self.doc.plots[index].id) {
+            self.selected_indices()
+        } else {
+            self.doc.block_indices(index)
         };
-        let json = serde_json::to_string_pretty(&save_data)
-            .map_err(|e| e.to_string())?;
-        std::fs::write(path, json).map_err(|e| e.to_string())?;
-        self.is_dirty = false;
-        Ok(())
+        self.edit(|doc| doc.move_plots(&indices, target));
     }
 
-    fn load_file(&mut self, path: &PathBuf) -> Result<(), String> {
-        let content = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
-        let save_data: SaveData = serde_json::from_str(&content).map_err(|e| e.to_string())?;
-        self.save_state_for_undo();
-        self.plots = save_data.plots;
-        self.composed_text = save_data.composed_text;
-        self.next_id = self.plots.iter().map(|p| p.id).max().unwrap_or(0) + 1;
-        Ok(())
+    // 先頭が position の位置になるようにまとめて移動する
+    fn move_plots_to(&mut self, ids: &[usize], position: usize) {
+        let mut indices: Vec<usize> = ids.iter().filter_map(|&id| self.doc.plot_index(id)).collect();
+        indices.sort_unstable();
+        self.edit(|doc| doc.move_plots_to(&indices, position));
     }
 
-    fn get_default_dir() -> Option<PathBuf> {
-        dirs::document_dir()
+    // 選択したプロットの位置（昇順）
+    fn selected_indices(&self) -> Vec<usize> {
+        let mut indices: Vec<usize> = self.selection.iter().filter_map(|&id| self.doc.plot_index(id)).collect();
+        indices.sort_unstable();
+        indices
     }
 
-    fn search(&mut self) {
-        self.search_results.clear();
-        self.current_search_index = 0;
+    // クリックで1つだけ選択（選択済みなら解除）、Ctrl+クリックで追加・解除、Shift+クリックで範囲を選択する
+    fn select_plot(&mut self, id: usize, modifiers: egui::Modifiers) {
+        if modifiers.shift {
+            let position = |id| self.visible_plot_ids.iter().position(|&v| v == id);
+            if let Some((a, b)) = self.selection_anchor.and_then(position).zip(position(id)) {
+                self.selection = self.visible_plot_ids[a.min(b)..=a.max(b)].to_vec();
+                return;
+            }
+        }
+        if modifiers.command {
+            if let Some(k) = self.selection.iter().position(|&s| s == id) {
+                self.selection.remove(k);
+            } else {
+                self.selection.push(id);
+            }
+        } else if self.selection == [id] {
+            self.selection.clear();
+        } else {
+            self.selection = vec![id];
+        }
+        self.selection_anchor = Some(id);
+    }
 
-        if self.search_text.is_empty() {
+    fn clear_selection(&mut self) {
+        self.selection.clear();
+        self.selection_anchor = None;
+    }
+
+    // 選択したプロットへの一括操作（それぞれ1回の操作として元に戻せる）
+    fn remove_selected(&mut self) {
+        let indices = self.selected_indices();
+        if indices.len() >= self.doc.plots.len() {
+            show_error("削除", "すべてのプロットを削除することはできません。");
             return;
         }
+        self.edit(|doc| doc.remove_plots(&indices));
+        self.clear_selection();
+    }
 
-        // Search in plots
-        for (i, plot) in self.plots.iter().enumerate() {
-            let mut start = 0;
-            while let Some(pos) = plot.text[start..].find(&self.search_text) {
-                let actual_start = start + pos;
-                self.search_results.push(SearchResult {
-                    location: SearchLocation::Plot,
-                    plot_index: Some(i),
-                    start: actual_start,
-                    end: actual_start + self.search_text.len(),
-                });
-                start = actual_start + 1;
-            }
+    fn duplicate_selected(&mut self) {
+        let indices = self.selected_indices();
+        let mut copies = None;
+        self.edit(|doc| {
+            copies = doc.duplicate_plots(&indices);
+            copies.is_some()
+        });
+        match copies {
+            Some(ids) => self.selection = ids,
+            None => show_error("複製", &format!("プロット数が上限（{}）を超えるため複製できません。", MAX_PLOTS)),
         }
+    }
 
-        // Search in composed text
-        let mut start = 0;
-        while let Some(pos) = self.composed_text[start..].find(&self.search_text) {
-            let actual_start = start + pos;
-            self.search_results.push(SearchResult {
-                location: SearchLocation::ComposedText,
-                plot_index: None,
-                start: actual_start,
-                end: actual_start + self.search_text.len(),
-            });
-            start = actual_start + 1;
+    fn merge_selected(&mut self) {
+        let indices = self.selected_indices();
+        self.merge_plots(&indices);
+    }
+
+    fn merge_plots(&mut self, indices: &[usize]) {
+        let mut result = Ok(false);
+        self.edit(|doc| {
+            result = doc.merge_plots(indices);
+            matches!(result, Ok(true))
+        });
+        match result {
+            Ok(_) => self.selection.retain(|&id| self.doc.plot_index(id).is_some()),
+            Err(e) => show_error("結合", &e),
         }
     }
 
-    fn replace_all(&mut self) {
-        if self.search_text.is_empty() {
-            return;
+    // 結合の相手にする、同じ幕・章の中で前（または次）に表示されているシーン。
+    // 絞り込みや折りたたみで隠れているプロットは飛ばし、見出しを越えては探さない。
+    fn merge_neighbor(&self, index: usize, previous: bool) -> Option<usize> {
+        let plots = &self.doc.plots;
+        if plots[index].kind.is_heading() {
+            return None;
         }
-        self.save_state_for_undo();
+        let candidates: Vec<usize> = if previous {
+            (0..index).rev().collect()
+        } else {
+            (index + 1..plots.len()).collect()
+        };
+        candidates
+            .into_iter()
+            .take_while(|&i| !plots[i].kind.is_heading())
+            .find(|&i| self.visible_plot_ids.contains(&plots[i].id))
+    }
 
-        // Replace in plots
-        for plot in &mut self.plots {
-            plot.text = plot.text.replace(&self.search_text, &self.replace_text);
+    fn split_plot(&mut self, index: usize, cursor: usize) {
+        if self.doc.plots.len() >= MAX_PLOTS {
+            show_error("分割", &format!("プロット数が上限（{}）に達しているため分割できません。", MAX_PLOTS));
+            return;
+        }
+        let mut split = false;
+        self.edit(|doc| {
+            split = doc.split_plot(index, cursor);
+            split
+        });
+        if !split {
+            show_error("分割", "カーソルの前後のどちらかが空になる位置では分割できません。");
         }
+    }
 
-        // Replace in composed text
-        self.composed_text = self.composed_text.replace(&self.search_text, &self.replace_text);
+    // 出力テキストの中のプロットごとの区間の範囲。対応が取れない場合は空
+    fn composed_ranges(&self) -> Vec<(usize, std::ops::Range<usize>)> {
+        if self.doc.composed_text.contains('\r') {
+            return Vec::new();
+        }
+        decompose::locate_ranges(&self.doc.composed, &self.doc.composed_text).unwrap_or_default()
+    }
 
-        self.search_results.clear();
+    // プロットに対応する出力テキストの位置（文字単位）。区間の記録が使えない場合は最初の行を探す
+    fn composed_offset(&self, index: usize) -> Option<usize> {
+        let plot = &self.doc.plots[index];
+        let text = &self.doc.composed_text;
+        let start = self
+            .composed_ranges()
+            .into_iter()
+            .find(|(id, _)| *id == plot.id)
+            .map(|(_, range)| range.start)
+            .or_else(|| {
+                let line = plot.text.lines().map(str::trim).find(|l| !l.is_empty())?;
+                text.find(line)
+            })?;
+        Some(text[..start].chars().count())
     }
 
-    fn export_text(&self) {
-        if let Some(default_dir) = Self::get_default_dir() {
-            let file = rfd::FileDialog::new()
-                .add_filter("テキストファイル", &["txt"])
-                .set_directory(&default_dir)
-                .set_file_name("export.txt")
-                .save_file();
-            if let Some(mut path) = file {
-                if path.extension().is_none() {
-                    path.set_extension("txt");
-                }
-                // Convert LF to CRLF
-                let crlf_text = self.composed_text.replace('\n', "\r\n");
-                // Write as UTF-8 with BOM for better compatibility
-                if let Err(e) = std::fs::write(&path, crlf_text.as_bytes()) {
-                    eprintln!("Export error: {}", e);
+    // 検索に一致したプロット（出力テキストで一致した場合は、その区間の生成元のプロット）
+    fn search_match_ids(&self) -> HashSet<usize> {
+        let mut ids = HashSet::new();
+        if self.search_results.is_empty() {
+            return ids;
+        }
+        let ranges = self.composed_ranges();
+        for result in &self.search_results {
+            let id = match result.location {
+                SearchLocation::Plot => result.plot_index.and_then(|i| self.doc.plots.get(i)).map(|p| p.id),
+                SearchLocation::ComposedText => {
+                    ranges.iter().find(|(_, range)| range.contains(&result.start)).map(|(id, _)| *id)
                 }
-            }
+            };
+            ids.extend(id);
         }
+        ids
     }
-}
 
-#[allow(dead_code)]
-fn flat_button(ui: &mut egui::Ui, text: &str, size: egui::Vec2) -> egui::Response {
-    let (rect, response) = ui.allocate_exact_size(size, egui::Sense::click());
+    // アウトラインで選んだプロットを表示する。折りたたんだ見出しは開き、絞り込みで隠れている場合は絞り込みを解除する
+    fn navigate_to(&mut self, index: usize) {
+        let id = self.doc.plots[index].id;
+        for h in 0..index {
+            if self.doc.plots[h].kind.is_heading() && self.doc.block_end(h) > index && self.doc.plots[h].collapsed {
+                self.doc.plots[h].collapsed = false;
+                self.is_dirty = true;
+            }
+        }
+        if !self.doc.filter_matches()[index] {
+            self.doc.filter.tags.clear();
+            self.is_dirty = true;
+        }
+        self.selection = vec![id];
+        self.selection_anchor = Some(id);
+        self.scroll_to_plot = Some(id);
+        self.scroll_to_composed = self.composed_offset(index);
+    }
 
-    if ui.is_rect_visible(rect) {
-        let visuals = ui.style().interact(&response);
-        let bg_color = if response.hovered() {
-            egui::Color32::from_rgb(70, 130, 180)
+    // アウトラインの項目（#番号 タイトルか本文の最初の行）
+    fn outline_label(&self, index: usize, matches: &HashSet<usize>) -> egui::RichText {
+        let plot = &self.doc.plots[index];
+        let name = if plot.kind.is_heading() {
+            format!("{}　{}", plot.kind.display_name(), plot.text.trim())
+        } else if !plot.title.trim().is_empty() {
+            plot.title.trim().to_owned()
         } else {
-            egui::Color32::from_rgb(50, 100, 150)
+            plot.text.lines().map(str::trim).find(|l| !l.is_empty()).unwrap_or("（空）").to_owned()
         };
+        let name: String = name.chars().take(40).collect();
+        let mut text = egui::RichText::new(format!("#{} {}", index + 1, name));
+        if matches.contains(&plot.id) {
+            text = text.color(egui::Color32::from_rgb(230, 190, 60)).strong();
+        } else if self.doc.is_excluded(index) {
+            text = text.weak();
+        }
+        text
+    }
 
-        ui.painter().rect_filled(rect, 4.0, bg_color);
-        ui.painter().text(
-            rect.center(),
-            egui::Align2::CENTER_CENTER,
-            text,
-            egui::FontId::proportional(14.0),
-            visuals.text_color(),
-        );
+    // start..end のプロットを、幕・章は開閉できる節として入れ子に並べる
+    fn outline_entries(
+        &self,
+        ui: &mut egui::Ui,
+        start: usize,
+        end: usize,
+        matches: &HashSet<usize>,
+        clicked: &mut Option<usize>,
+    ) {
+        let mut i = start;
+        while i < end {
+            let plot = &self.doc.plots[i];
+            let selected = self.selection.contains(&plot.id);
+            if !plot.kind.is_heading() {
+                if ui.selectable_label(selected, self.outline_label(i, matches)).clicked() {
+                    *clicked = Some(i);
+                }
+                i += 1;
+                continue;
+            }
+            let block_end = self.doc.block_end(i);
+            let id = ui.make_persistent_id(("outline", plot.id));
+            egui::collapsing_header::CollapsingState::load_with_default_open(ui.ctx(), id, true)
+                .show_header(ui, |ui| {
+                    if ui.selectable_label(selected, self.outline_label(i, matches)).clicked() {
+                        *clicked = Some(i);
+                    }
+                })
+                .body(|ui| self.outline_entries(ui, i + 1, block_end, matches, clicked));
+            i = block_end;
+        }
     }
 
-    response
-}
+    // コルクボード表示。シーンをカードとして折り返して並べ、幕・章の見出しで段を分ける
+    fn corkboard_ui(&mut self, ui: &mut egui::Ui, font_size: f32) {
+        // 一覧と同じく、折りたたまれた見出しの配下と絞り込みに合わないプロットは表示しない
+        let filter_matches = self.doc.filter_matches();
+        let mut entries = Vec::new();
+        let mut skip_until = 0;
+        let mut excluded_until = 0;
+        for (i, &matched) in filter_matches.iter().enumerate() {
+            if i < skip_until {
+                continue;
+            }
+            let block_end = self.doc.block_end(i);
+            let dimmed = self.doc.is_excluded(i) || i < excluded_until;
+            if self.doc.is_excluded(i) {
+                excluded_until = excluded_until.max(block_end);
+            }
+            if self.doc.plots[i].kind.is_heading() && self.doc.plots[i].collapsed {
+                skip_until = block_end;
+            }
+            if matched {
+                entries.push((i, dimmed));
+            }
+        }
+        self.visible_plot_ids = entries.iter().map(|&(i, _)| self.doc.plots[i].id).collect();
+
+        let accent = egui::Stroke::new(2.0, egui::Color32::from_rgb(80, 140, 190));
+        let drag = egui::DragAndDrop::payload::<PlotDrag>(ui.ctx());
+        let released = ui.input(|input| input.pointer.any_released());
+        let pointer = ui.ctx().pointer_interact_pos();
+        let mut drop_target = None;
+
+        egui::ScrollArea::vertical().id_salt("corkboard_scroll").show(ui, |ui| {
+            let mut k = 0;
+            while k < entries.len() {
+                let (i, dimmed) = entries[k];
+                let plot = &self.doc.plots[i];
+                if plot.kind.is_heading() {
+                    ui.add_space(6.0);
+                    let row = ui.horizontal(|ui| {
+                        if dimmed {
+                            ui.multiply_opacity(0.4);
+                        }
+                        if small_flat_button(ui, if plot.collapsed { "▶" } else { "▼" }).clicked() {
+                            self.pending_action = Some((i, PlotAction::ToggleCollapse));
+                        }
+                        let label = format!("{}　{}", plot.kind.display_name(), plot.text.trim());
+                        let response = ui.add(
+                            egui::Label::new(egui::RichText::new(label).size(font_size + 2.0).strong())
+                                .sense(egui::Sense::click()),
+                        );
+                        if response.double_clicked() {
+                            self.card_editor = Some(plot.id);
+                        }
+                    });
+                    if self.scroll_to_plot == Some(plot.id) {
+                        ui.scroll_to_rect(row.response.rect, Some(egui::Align::TOP));
+                        self.scroll_to_plot = None;
+                    }
+                    // 見出しの上半分なら前、下半分なら後ろ（折りたたんだ見出しは配下の後ろ）に移動する
+                    let rect = row.response.rect.expand2(egui::vec2(0.0, 5.0));
+                    if let Some(pointer) = pointer.filter(|p| drag.is_some() && rect.contains(*p)) {
+                        let before = pointer.y < rect.center().y;
+                        let target = match (before, plot.collapsed) {
+                            (true, _) => i,
+                            (false, true) => self.doc.block_end(i),
+                            (false, false) => i + 1,
+                        };
+                        let y = if before { rect.top() } else { rect.bottom() };
+                        ui.painter().hline(rect.x_range(), y, accent);
+                        drop_target = Some(target);
+                    }
+                    ui.add_space(6.0);
+                    k += 1;
+                    continue;
+                }
 
-// Vertical offset for Japanese font centering (adjust as needed)
-const TEXT_Y_OFFSET: f32 = 3.0;
-const MENU_BUTTON_Y_OFFSET: f32 = 5.0; // Additional offset for menu buttons (ファイル, 編集, 検索)
-#[allow(dead_code)]
-const MENU_LABEL_Y_OFFSET: f32 = 5.0; // Offset for menu bar labels (文字サイズ:, フォント:)
+                // 次の見出しまでのシーンを折り返して並べる
+                let run_end = entries[k..]
+                    .iter()
+                    .position(|&(j, _)| self.doc.plots[j].kind.is_heading())
+                    .map_or(entries.len(), |pos| k + pos);
+                ui.horizontal_wrapped(|ui| {
+                    ui.spacing_mut().item_spacing = egui::vec2(10.0, 10.0);
+                    for &(i, dimmed) in &entries[k..run_end] {
+                        let plot_id = self.doc.plots[i].id;
+                        let selected = self.selection.contains(&plot_id);
+                        let card = ui
+                            .scope(|ui| {
+                                if dimmed {
+                                    ui.multiply_opacity(0.4);
+                                }
+                                ui.dnd_drag_source(egui::Id::new(("card_drag", plot_id)), PlotDrag(plot_id), |ui| {
+                                    plot_card(ui, &self.doc.plots[i], i + 1, selected)
+                                })
+                                .inner
+                            })
+                            .inner;
+                        if card.double_clicked() {
+                            self.card_editor = Some(plot_id);
+                        } else if card.clicked() {
+                            let modifiers = ui.input(|input| input.modifiers);
+                            self.select_plot(plot_id, modifiers);
+                        }
 
-// メニューバー
-const MENU_BAR_HEIGHT: f32 = 40.0;
-const MENU_ROW_HEIGHT: f32 = 36.0;
+                        if self.scroll_to_plot == Some(plot_id) {
+                            ui.scroll_to_rect(card.rect, Some(egui::Align::TOP));
+                            self.scroll_to_plot = None;
+                        }
 
-// ComboBox（TEXT + PAD*2 <= HEIGHT を満たすこと）
-const MENU_COMBO_TEXT_SIZE: f32 = 15.0;
-const MENU_COMBO_PAD_Y: f32 = 6.0;              // 6*2 + 15 = 27 <= 36
-const MENU_COMBO_HEIGHT: f32 = MENU_ROW_HEIGHT;
+                        // カードの左半分なら前、右半分なら後ろに移動先の線を引く
+                        let rect = card.rect.expand(5.0);
+                        if let Some(pointer) = pointer.filter(|p| drag.is_some() && rect.contains(*p)) {
+                            let before = pointer.x < rect.center().x;
+                            let x = if before { rect.left() } else { rect.right() };
+                            ui.painter().vline(x, rect.y_range(), accent);
+                            drop_target = Some(if before { i } else { i + 1 });
+                        }
+                    }
+                });
+                k = run_end;
+            }
+            ui.add_space(20.0);
+            ui.label(format!("プロット数: {} / {}", self.doc.plots.len(), MAX_PLOTS));
+        });
 
-fn menu_bar_label(ui: &mut egui::Ui, text: &str) {
-    let font_id = egui::FontId::proportional(13.0);
+        if let (Some(drag), Some(target), true) = (drag, drop_target, released) {
+            if let Some(index) = self.doc.plot_index(drag.0) {
+                self.pending_action = Some((index, PlotAction::MoveBefore(target)));
+            }
+        }
+    }
 
-    // このブロック（allocate_ui_with_layout）の高さをそのまま使う
-    let h = ui.available_height();
-    let galley = ui
-        .painter()
-        .layout_no_wrap(text.to_owned(), font_id.clone(), egui::Color32::WHITE);
+    fn set_selected(&mut self, f: impl Fn(&mut PlotFragment) -> bool) {
+        let indices = self.selected_indices();
+        self.edit(|doc| indices.iter().fold(false, |changed, &i| f(&mut doc.plots[i]) | changed));
+    }
 
-    let desired_size = egui::vec2(galley.size().x, h);
-    let (rect, _response) = ui.allocate_exact_size(desired_size, egui::Sense::hover());
+    fn add_tag_to_selected(&mut self, tag: &str) {
+        let indices = self.selected_indices();
+        self.edit(|doc| indices.iter().fold(false, |changed, &i| doc.add_tag(i, tag) | changed));
+    }
 
-    if ui.is_rect_visible(rect) {
-        ui.painter().text(
-            egui::pos2(rect.min.x, rect.center().y + TEXT_Y_OFFSET),
-            egui::Align2::LEFT_CENTER,
-            text,
-            font_id,
-            egui::Color32::WHITE,
-        );
+    // 取り込んだプロットはまとめて1回の操作として元に戻せる
+    fn import_plots(&mut self, plots: Vec<PlotFragment>) {
+        self.edit(|doc| doc.append_plots(plots));
     }
-}
 
-fn menu_combo_scope<R>(ui: &mut egui::Ui, f: impl FnOnce(&mut egui::Ui) -> R) -> R {
-    ui.scope(|ui| {
-        let mut s = ui.style().as_ref().clone();
+    // 読み込んだ文書に置き換える（元に戻すことが出来る）
+    fn replace_document(&mut self, doc: Document) {
+        self.save_state_for_undo();
+        self.doc = doc;
+        self.clear_selection();
+    }
+
+    fn save_file(&mut self, path: &Path) -> Result<(), String> {
+        self.doc.save(path)?;
+        self.is_dirty = false;
+        self.clear_recovery();
+        Ok(())
+    }
+
+    // 保存に失敗した場合は（バックアップの作成の失敗も含め）エラーを表示し、成功すればそのファイルを現在のファイルにする
+    fn save_and_report(&mut self, path: PathBuf) {
+        match self.save_file(&path) {
+            Ok(()) => self.current_file_path = Some(path),
+            Err(e) => {
+                eprintln!("Save error: {}", e);
+                show_error("保存エラー", &e);
+            }
+        }
+    }
+
+    fn load_file(&mut self, path: &Path) -> Result<(), String> {
+        let doc = Document::load(path)?;
+        self.replace_document(doc);
+        self.mark_loaded();
+        Ok(())
+    }
+
+    // 読み込んだばかりの文書は未変更として扱い、復元用の控えを取らない
+    fn mark_loaded(&mut self) {
+        self.is_dirty = false;
+        self.clear_recovery();
+    }
+
+    // 未保存の変更があれば一定間隔で控えを取り、復元用ファイルに書き出す
+    fn autosave(&mut self, ctx: &egui::Context) {
+        // 復元するかどうかの返事を待つ間は、前回の控えを上書きしない
+        if !self.is_dirty || self.recovered.is_some() {
+            return;
+        }
+        let now = Instant::now();
+        if now.duration_since(self.last_snapshot) >= SNAPSHOT_INTERVAL {
+            self.last_snapshot = now;
+            let recovery = RecoveryData {
+                file_path: self.current_file_path.clone(),
+                data: self.doc.to_save_data(),
+            };
+            if let Ok(json) = serde_json::to_string(&recovery) {
+                if let Ok(mut snapshot) = self.recovery_snapshot.lock() {
+                    *snapshot = Some(json);
+                    self.autosave_pending = true;
+                }
+            }
+        }
+        if self.autosave_pending && now.duration_since(self.last_autosave) >= AUTOSAVE_INTERVAL {
+            self.last_autosave = now;
+            self.autosave_pending = false;
+            if let Ok(snapshot) = self.recovery_snapshot.lock() {
+                if let Some(ref json) = *snapshot {
+                    RecoveryData::write(json);
+                }
+            }
+        }
+        ctx.request_repaint_after(SNAPSHOT_INTERVAL);
+    }
+
+    // 復元するかどうかの返事を待つ間は、前回の控えを消さない（返事をせずに終了した場合も次回また尋ねる）
+    fn clear_recovery(&mut self) {
+        if self.recovered.is_some() {
+            return;
+        }
+        if let Ok(mut snapshot) = self.recovery_snapshot.lock() {
+            *snapshot = None;
+        }
+        self.autosave_pending = false;
+        RecoveryData::remove();
+    }
+
+    // 現在のファイルの場所は変えずに、バックアップの内容を読み込む。
+    // ファイルの内容とは異なるため、未保存の変更として扱う
+    fn restore_backup(&mut self, backup: &Path) -> Result<(), String> {
+        let doc = Document::load(backup)?;
+        self.replace_document(doc);
+        Ok(())
+    }
+
+    fn restore_recovery(&mut self) {
+        if let Some(recovered) = self.recovered.take() {
+            self.replace_document(Document::from_save_data(recovered.data));
+            self.current_file_path = recovered.file_path;
+        }
+    }
+
+    fn get_default_dir() -> Option<PathBuf> {
+        dirs::document_dir()
+    }
+
+    fn search(&mut self) {
+        self.search_results = self.doc.search(&self.search_text);
+        self.current_search_index = 0;
+    }
+
+    fn replace_all(&mut self) {
+        let (query, replacement) = (self.search_text.clone(), self.replace_text.clone());
+        self.edit(|doc| doc.replace_all(&query, &replacement));
+        self.search_results.clear();
+    }
+
+    // 前回の文書生成の後のプロットの変更を反映した、書き出し用の文書。
+    // 手直しとプロットの変更がぶつかった箇所などがあれば、このまま書き出すか確かめる
+    fn export_document(&self) -> Option<Document> {
+        match export::prepare(&self.doc) {
+            Ok(prepared) => {
+                if let Some(warning) = prepared.warning() {
+                    let result = rfd::MessageDialog::new()
+                        .set_level(rfd::MessageLevel::Warning)
+                        .set_title("エクスポート")
+                        .set_description(format!(
+                            "{}\n選び直す場合は「文書生成」を行ってください。このまま書き出しますか？",
+                            warning
+                        ))
+                        .set_buttons(rfd::MessageButtons::YesNo)
+                        .show();
+                    if result != rfd::MessageDialogResult::Yes {
+                        return None;
+                    }
+                }
+                Some(prepared.doc)
+            }
+            Err(e) => {
+                show_error("エクスポートエラー", &e);
+                None
+            }
+        }
+    }
+
+    fn export_as(&self, format: ExportFormat) {
+        let Some(doc) = self.export_document() else {
+            return;
+        };
+        if let Some(default_dir) = Self::get_default_dir() {
+            let file = rfd::FileDialog::new()
+                .add_filter(format.filter_name(), &[format.extension()])
+                .set_directory(&default_dir)
+                .set_file_name(format!("export.{}", format.extension()))
+                .save_file();
+            if let Some(mut path) = file {
+                if path.extension().is_none() {
+                    path.set_extension(format.extension());
+                }
+                let text = export::render(format, &doc);
+                if let Err(e) = std::fs::write(&path, text.as_bytes()) {
+                    eprintln!("Export error: {}", e);
+                    show_error("エクスポートエラー", &e.to_string());
+                }
+            }
+        }
+    }
+
+    // zipやPDFなど文字列ではない形式の書き出し
+    fn export_file(&self, filter_name: &str, extension: &str, build: impl FnOnce(&Document) -> Result<Vec<u8>, String>) {
+        let Some(doc) = self.export_document() else {
+            return;
+        };
+        if let Some(default_dir) = Self::get_default_dir() {
+            let file = rfd::FileDialog::new()
+                .add_filter(filter_name, &[extension])
+                .set_directory(&default_dir)
+                .set_file_name(format!("export.{}", extension))
+                .save_file();
+            if let Some(mut path) = file {
+                if path.extension().is_none() {
+                    path.set_extension(extension);
+                }
+                let result = build(&doc).and_then(|data| std::fs::write(&path, data).map_err(|e| e.to_string()));
+                if let Err(e) = result {
+                    eprintln!("Export error: {}", e);
+                    show_error("エクスポートエラー", &e);
+                }
+            }
+        }
+    }
+
+    // 前回使ったフォントが無ければ、原稿向けにMS 明朝を使う
+    fn open_pdf_dialog(&mut self) {
+        let font_path = self
+            .settings
+            .pdf_font_path
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(FontChoice::MSMincho.font_path()));
+        let mut options = PdfOptions::new(&LAYOUTS[1], font_path);
+        options.font_index = self.settings.pdf_font_index;
+        self.pdf_dialog = Some(options);
+    }
+
+    fn export_pdf(&mut self, options: &PdfOptions) {
+        self.settings.pdf_font_path = Some(options.font_path.clone());
+        self.settings.pdf_font_index = options.font_index;
+        self.settings.save();
+        self.export_file("PDF", "pdf", |doc| pdf::build(doc, options));
+    }
+
+    fn export_text(&mut self, options: &TextOptions) {
+        self.settings.text_export = *options;
+        self.settings.save();
+        self.export_file("テキストファイル", "txt", |doc| export::encode_text(&doc.composed_text, options));
+    }
+
+    // 選んだフォルダに1話ずつのテキストファイルを書き出す
+    fn export_site(&mut self, site: Site, options: &TextOptions) {
+        self.settings.text_export = *options;
+        self.settings.save();
+        let Some(doc) = self.export_document() else {
+            return;
+        };
+        let mut dialog = rfd::FileDialog::new();
+        if let Some(default_dir) = Self::get_default_dir() {
+            dialog = dialog.set_directory(default_dir);
+        }
+        if let Some(dir) = dialog.pick_folder() {
+            if let Err(e) = site::write_episodes(&doc, site, options, &dir) {
+                eprintln!("Export error: {}", e);
+                show_error("エクスポートエラー", &e);
+            }
+        }
+    }
+}
+
+// 文字コードと改行コードの選択（テキスト・投稿サイト向けの書き出しで共用）
+fn text_options_ui(ui: &mut egui::Ui, id_salt: &str, options: &mut TextOptions) {
+    egui::Grid::new(id_salt).num_columns(2).show(ui, |ui| {
+        ui.label("文字コード:");
+        ui.horizontal(|ui| {
+            for &encoding in TextEncoding::all() {
+                ui.radio_value(&mut options.encoding, encoding, encoding.display_name());
+            }
+        });
+        ui.end_row();
+        ui.label("改行コード:");
+        ui.horizontal(|ui| {
+            for &line_ending in LineEnding::all() {
+                ui.radio_value(&mut options.line_ending, line_ending, line_ending.display_name());
+            }
+        });
+        ui.end_row();
+    });
+}
+
+fn show_error(title: &str, message: &str) {
+    rfd::MessageDialog::new()
+        .set_level(rfd::MessageLevel::Error)
+        .set_title(title)
+        .set_description(message)
+        .set_buttons(rfd::MessageButtons::Ok)
+        .show();
+}
+
+#[allow(dead_code)]
+fn flat_button(ui: &mut egui::Ui, text: &str, size: egui::Vec2) -> egui::Response {
+    let (rect, response) = ui.allocate_exact_size(size, egui::Sense::click());
+
+    if ui.is_rect_visible(rect) {
+        let visuals = ui.style().interact(&response);
+        let bg_color = if response.hovered() {
+            egui::Color32::from_rgb(70, 130, 180)
+        } else {
+            egui::Color32::from_rgb(50, 100, 150)
+        };
+
+        ui.painter().rect_filled(rect, 4.0, bg_color);
+        ui.painter().text(
+            rect.center(),
+            egui::Align2::CENTER_CENTER,
+            text,
+            egui::FontId::proportional(14.0),
+            visuals.text_color(),
+        );
+    }
+
+    response
+}
+
+// Vertical offset for Japanese font centering (adjust as needed)
+const TEXT_Y_OFFSET: f32 = 3.0;
+const MENU_BUTTON_Y_OFFSET: f32 = 5.0; // Additional offset for menu buttons (ファイル, 編集, 検索)
+#[allow(dead_code)]
+const MENU_LABEL_Y_OFFSET: f32 = 5.0; // Offset for menu bar labels (文字サイズ:, フォント:)
+
+// メニューバー
+const MENU_BAR_HEIGHT: f32 = 40.0;
+const MENU_ROW_HEIGHT: f32 = 36.0;
+
+// ComboBox（TEXT + PAD*2 <= HEIGHT を満たすこと）
+const MENU_COMBO_TEXT_SIZE: f32 = 15.0;
+const MENU_COMBO_PAD_Y: f32 = 6.0;              // 6*2 + 15 = 27 <= 36
+const MENU_COMBO_HEIGHT: f32 = MENU_ROW_HEIGHT;
+
+fn menu_bar_label(ui: &mut egui::Ui, text: &str) {
+    let font_id = egui::FontId::proportional(13.0);
+
+    // このブロック（allocate_ui_with_layout）の高さをそのまま使う
+    let h = ui.available_height();
+    let galley = ui
+        .painter()
+        .layout_no_wrap(text.to_owned(), font_id.clone(), egui::Color32::WHITE);
+
+    let desired_size = egui::vec2(galley.size().x, h);
+    let (rect, _response) = ui.allocate_exact_size(desired_size, egui::Sense::hover());
+
+    if ui.is_rect_visible(rect) {
+        ui.painter().text(
+            egui::pos2(rect.min.x, rect.center().y + TEXT_Y_OFFSET),
+            egui::Align2::LEFT_CENTER,
+            text,
+            font_id,
+            egui::Color32::WHITE,
+        );
+    }
+}
+
+fn menu_combo_scope<R>(ui: &mut egui::Ui, f: impl FnOnce(&mut egui::Ui) -> R) -> R {
+    ui.scope(|ui| {
+        let mut s = ui.style().as_ref().clone();
 
         // ComboBoxはボタン扱いなので TextStyle::Button が効く
         s.text_styles.insert(
@@ -577,6 +1416,206 @@ fn small_flat_button(ui: &mut egui::Ui, text: &str) -> egui::Response {
     response
 }
 
+fn plot_color32(color: PlotColor) -> egui::Color32 {
+    match color.rgb() {
+        Some([r, g, b]) => egui::Color32::from_rgb(r, g, b),
+        None => egui::Color32::from_rgb(70, 75, 85),
+    }
+}
+
+fn status_color(status: PlotStatus) -> egui::Color32 {
+    match status {
+        PlotStatus::Idea => egui::Color32::from_rgb(95, 100, 110),
+        PlotStatus::Draft => egui::Color32::from_rgb(190, 140, 40),
+        PlotStatus::Done => egui::Color32::from_rgb(46, 139, 87),
+    }
+}
+
+fn status_chip(ui: &mut egui::Ui, status: PlotStatus) -> egui::Response {
+    let font_id = egui::FontId::proportional(11.0);
+    let size = egui::vec2(52.0, 20.0);
+    let (rect, response) = ui.allocate_exact_size(size, egui::Sense::click());
+
+    if ui.is_rect_visible(rect) {
+        ui.painter().rect_filled(rect, 10.0, status_color(status));
+
+        ui.painter().text(
+            egui::pos2(rect.center().x, rect.center().y + TEXT_Y_OFFSET * 0.5),
+            egui::Align2::CENTER_CENTER,
+            status.display_name(),
+            font_id,
+            egui::Color32::WHITE,
+        );
+    }
+
+    response
+}
+
+// 指定した行数に収まらない部分を「…」で省略した文字列
+fn elided_galley(
+    ui: &egui::Ui,
+    text: &str,
+    font_id: egui::FontId,
+    color: egui::Color32,
+    width: f32,
+    max_rows: usize,
+) -> Arc<egui::Galley> {
+    let mut job = egui::text::LayoutJob::simple(text.to_owned(), font_id, color, width);
+    job.wrap.max_rows = max_rows;
+    job.wrap.break_anywhere = true;
+    ui.fonts(|fonts| fonts.layout_job(job))
+}
+
+// コルクボードのカード（色ラベルの帯・番号・進捗・タイトル・あらすじか本文の最初の数行）
+fn plot_card(ui: &mut egui::Ui, plot: &PlotFragment, number: usize, selected: bool) -> egui::Response {
+    let (rect, response) = ui.allocate_exact_size(CARD_SIZE, egui::Sense::click());
+
+    if ui.is_rect_visible(rect) {
+        let visuals = ui.visuals();
+        let stroke = if selected {
+            egui::Stroke::new(2.0, egui::Color32::from_rgb(80, 140, 190))
+        } else if response.hovered() {
+            visuals.widgets.hovered.bg_stroke
+        } else {
+            visuals.widgets.noninteractive.bg_stroke
+        };
+        let painter = ui.painter();
+        painter.rect(rect, 6.0, visuals.faint_bg_color, stroke);
+        if plot.color != PlotColor::None {
+            let strip = egui::Rect::from_min_size(rect.min, egui::vec2(rect.width(), 6.0));
+            let rounding = egui::Rounding { nw: 6.0, ne: 6.0, sw: 0.0, se: 0.0 };
+            painter.rect_filled(strip, rounding, plot_color32(plot.color));
+        }
+
+        let inner = rect.shrink2(egui::vec2(10.0, 12.0));
+        let weak = visuals.weak_text_color();
+        painter.text(
+            inner.left_top(),
+            egui::Align2::LEFT_TOP,
+            format!("#{}", number),
+            egui::FontId::proportional(11.0),
+            weak,
+        );
+        painter.text(
+            inner.right_top(),
+            egui::Align2::RIGHT_TOP,
+            plot.status.display_name(),
+            egui::FontId::proportional(11.0),
+            status_color(plot.status),
+        );
+
+        let title = if plot.title.trim().is_empty() { "（無題）" } else { plot.title.trim() };
+        let title = elided_galley(ui, title, egui::FontId::proportional(15.0), visuals.strong_text_color(), inner.width(), 1);
+        let title_pos = inner.left_top() + egui::vec2(0.0, 18.0);
+        let body_pos = title_pos + egui::vec2(0.0, title.size().y + 6.0);
+        painter.galley(title_pos, title, visuals.text_color());
+
+        let body = if plot.synopsis.trim().is_empty() { plot.text.trim() } else { plot.synopsis.trim() };
+        let body = elided_galley(ui, body, egui::FontId::proportional(12.0), visuals.text_color(), inner.width(), 4);
+        painter.galley(body_pos, body, visuals.text_color());
+    }
+
+    response
+}
+
+// 色ラベルの見本。クリックで色の選択肢を表示する
+fn color_label_button(ui: &mut egui::Ui, id_salt: impl std::hash::Hash, color: PlotColor) -> Option<PlotColor> {
+    let size = egui::vec2(52.0, 12.0);
+    let (rect, response) = ui.allocate_exact_size(size, egui::Sense::click());
+    let popup_id = ui.make_persistent_id(id_salt);
+
+    if response.clicked() {
+        ui.memory_mut(|m| m.toggle_popup(popup_id));
+    }
+
+    if ui.is_rect_visible(rect) {
+        ui.painter().rect_filled(rect, 3.0, plot_color32(color));
+        if response.hovered() {
+            ui.painter().rect_stroke(rect, 3.0, egui::Stroke::new(1.0, egui::Color32::WHITE));
+        }
+    }
+
+    let mut selected = None;
+    egui::popup::popup_below_widget(ui, popup_id, &response, egui::PopupCloseBehavior::CloseOnClick, |ui| {
+        ui.set_min_width(80.0);
+        for &choice in PlotColor::all() {
+            ui.horizontal(|ui| {
+                let (swatch, _) = ui.allocate_exact_size(egui::vec2(14.0, 14.0), egui::Sense::hover());
+                ui.painter().rect_filled(swatch, 3.0, plot_color32(choice));
+                if ui.selectable_label(choice == color, choice.display_name()).clicked() {
+                    selected = Some(choice);
+                }
+            });
+        }
+    });
+
+    selected.filter(|&c| c != color)
+}
+
+// プロットのタグ（クリックで外す）と、Enterでタグを加える入力欄
+fn tag_editor(ui: &mut egui::Ui, tags: &[String], input: &mut String) -> Option<PlotAction> {
+    let mut action = None;
+    ui.horizontal_wrapped(|ui| {
+        ui.spacing_mut().item_spacing.x = 4.0;
+        for tag in tags {
+            let chip = egui::Button::new(egui::RichText::new(format!("#{} ×", tag)).small());
+            if ui.add(chip).on_hover_text("クリックでタグを外す").clicked() {
+                action = Some(PlotAction::RemoveTag(tag.clone()));
+            }
+        }
+        let response = ui.add(egui::TextEdit::singleline(input).hint_text("+タグ").desired_width(70.0));
+        if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) && !input.trim().is_empty() {
+            action = Some(PlotAction::AddTag(std::mem::take(input)));
+        }
+    });
+    action
+}
+
+// 親文字の上にルビ（または傍点）を中央揃えで描く
+fn ruby_label(ui: &mut egui::Ui, base: &str, ruby: &str, font_size: f32) {
+    let color = ui.visuals().text_color();
+    let painter = ui.painter();
+    let base_galley = painter.layout_no_wrap(base.to_owned(), egui::FontId::proportional(font_size), color);
+    let ruby_galley = painter.layout_no_wrap(ruby.to_owned(), egui::FontId::proportional(font_size * 0.5), color);
+    let size = egui::vec2(
+        base_galley.size().x.max(ruby_galley.size().x),
+        base_galley.size().y + ruby_galley.size().y,
+    );
+    let (rect, _) = ui.allocate_exact_size(size, egui::Sense::hover());
+    if ui.is_rect_visible(rect) {
+        let ruby_pos = egui::pos2(rect.center().x - ruby_galley.size().x / 2.0, rect.top());
+        let base_pos = egui::pos2(rect.center().x - base_galley.size().x / 2.0, rect.bottom() - base_galley.size().y);
+        ui.painter().galley(ruby_pos, ruby_galley, color);
+        ui.painter().galley(base_pos, base_galley, color);
+    }
+}
+
+// ルビ・傍点の記法を組んだ状態で表示するプレビュー
+fn markup_preview(ui: &mut egui::Ui, text: &str, font_size: f32) {
+    ui.spacing_mut().item_spacing = egui::vec2(0.0, 2.0);
+    for line in text.lines() {
+        let layout = egui::Layout::left_to_right(egui::Align::Max).with_main_wrap(true);
+        ui.with_layout(layout, |ui| {
+            if line.is_empty() {
+                ui.label(egui::RichText::new(" ").size(font_size));
+            }
+            for inline in markup::parse_inline(line) {
+                match inline {
+                    Inline::Text(text) => {
+                        ui.label(egui::RichText::new(text).size(font_size));
+                    }
+                    Inline::Ruby { base, ruby } => ruby_label(ui, &base, &ruby, font_size),
+                    Inline::Emphasis(text) => {
+                        for c in text.chars() {
+                            ruby_label(ui, &c.to_string(), "・", font_size);
+                        }
+                    }
+                }
+            }
+        });
+    }
+}
+
 fn custom_menu_button<R>(
     ui: &mut egui::Ui,
     text: &str,
@@ -622,6 +1661,11 @@ fn custom_menu_button<R>(
 }
 
 impl eframe::App for StoryComposerApp {
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        // 正常に終了した場合は復元用の控えを残さない
+        self.clear_recovery();
+    }
+
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         // IME入力中（Preeditあり）またはCommit時はEnterキーを消費して改行を抑制
         // これにより、変換確定時にEnterキーを押しても改行されなくなる
@@ -649,11 +1693,9 @@ impl eframe::App for StoryComposerApp {
         }
 
         // Handle close request
-        if ctx.input(|i| i.viewport().close_requested()) {
-            if self.is_dirty {
-                ctx.send_viewport_cmd(egui::ViewportCommand::CancelClose);
-                self.show_close_confirm = true;
-            }
+        if ctx.input(|i| i.viewport().close_requested()) && self.is_dirty {
+            ctx.send_viewport_cmd(egui::ViewportCommand::CancelClose);
+            self.show_close_confirm = true;
         }
 
         // Close confirmation dialog
@@ -672,6 +1714,7 @@ impl eframe::App for StoryComposerApp {
                             if ui.button("はい").clicked() {
                                 self.show_close_confirm = false;
                                 self.is_dirty = false;
+                                self.clear_recovery();
                                 ctx.send_viewport_cmd(egui::ViewportCommand::Close);
                             }
                             if ui.button("いいえ").clicked() {
@@ -682,7 +1725,37 @@ impl eframe::App for StoryComposerApp {
                 });
         }
 
-        // Apply font change if needed
+        // Recovery dialog (前回のセッションが保存されずに終了した場合)
+        if self.recovered.is_some() {
+            egui::Window::new("復元")
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .show(ctx, |ui| {
+                    ui.vertical_centered(|ui| {
+                        ui.label("前回保存されずに終了した文書があります。復元しますか？");
+                        if let Some(path) = self.recovered.as_ref().and_then(|r| r.file_path.as_ref()) {
+                            ui.label(format!("ファイル: {}", path.display()));
+                        }
+                        ui.add_space(8.0);
+                        ui.horizontal(|ui| {
+                            let total_width = ui.available_width();
+                            ui.add_space(total_width / 2.0 - 70.0);
+                            if ui.button("復元する").clicked() {
+                                self.restore_recovery();
+                            }
+                            if ui.button("破棄する").clicked() {
+                                self.recovered = None;
+                                self.clear_recovery();
+                            }
+                        });
+                    });
+                });
+        }
+
+        self.autosave(ctx);
+
+        // Apply font change if needed
         if self.font_changed {
             setup_font(ctx, &self.settings.font_choice);
             self.settings.save();
@@ -738,6 +1811,9 @@ impl eframe::App for StoryComposerApp {
                         if styled_menu_button(ui, "文書生成", egui::Color32::from_rgb(46, 139, 87)).clicked() {
                             self.compose();
                         }
+                        if styled_menu_button_w(ui, "プロットに反映", egui::Color32::from_rgb(70, 110, 160), 110.0).clicked() {
+                            self.open_decompose_dialog();
+                        }
 
                     ui.add_space(20.0);
                     ui.separator();
@@ -762,20 +1838,14 @@ impl eframe::App for StoryComposerApp {
                                     if path.extension().is_none() || path.extension().unwrap() != "scrf" {
                                         path.set_extension("scrf");
                                     }
-                                    if let Err(e) = self.save_file(&path) {
-                                        eprintln!("Save error: {}", e);
-                                    } else {
-                                        self.current_file_path = Some(path);
-                                    }
+                                    self.save_and_report(path);
                                 }
                             }
                             ui.close_menu();
                         }
                         if menu_item(ui, "上書き保存").clicked() {
-                            if let Some(ref path) = self.current_file_path.clone() {
-                                if let Err(e) = self.save_file(path) {
-                                    eprintln!("Save error: {}", e);
-                                }
+                            if let Some(path) = self.current_file_path.clone() {
+                                self.save_and_report(path);
                             } else {
                                 if let Some(default_dir) = Self::get_default_dir() {
                                     let file = rfd::FileDialog::new()
@@ -787,11 +1857,7 @@ impl eframe::App for StoryComposerApp {
                                         if path.extension().is_none() || path.extension().unwrap() != "scrf" {
                                             path.set_extension("scrf");
                                         }
-                                        if let Err(e) = self.save_file(&path) {
-                                            eprintln!("Save error: {}", e);
-                                        } else {
-                                            self.current_file_path = Some(path);
-                                        }
+                                        self.save_and_report(path);
                                     }
                                 }
                             }
@@ -806,6 +1872,7 @@ impl eframe::App for StoryComposerApp {
                                 if let Some(path) = file {
                                     if let Err(e) = self.load_file(&path) {
                                         eprintln!("Load error: {}", e);
+                                        show_error("読み込みエラー", &e);
                                     } else {
                                         self.current_file_path = Some(path);
                                     }
@@ -813,6 +1880,69 @@ impl eframe::App for StoryComposerApp {
                             }
                             ui.close_menu();
                         }
+                        if menu_item(ui, "テキストを取り込む...").clicked() {
+                            let mut dialog = rfd::FileDialog::new().add_filter("テキスト・Markdown", &["txt", "md", "markdown"]);
+                            if let Some(default_dir) = Self::get_default_dir() {
+                                dialog = dialog.set_directory(default_dir);
+                            }
+                            if let Some(path) = dialog.pick_file() {
+                                match ImportDialog::open(path) {
+                                    Ok(dialog) => self.import_dialog = Some(dialog),
+                                    Err(e) => show_error("読み込みエラー", &e),
+                                }
+                            }
+                            ui.close_menu();
+                        }
+                        ui.separator();
+                        if menu_item(ui, "文書情報...").clicked() {
+                            self.show_meta_dialog = true;
+                            ui.close_menu();
+                        }
+                        if menu_item(ui, "文書生成の書式...").clicked() {
+                            self.show_template_dialog = true;
+                            ui.close_menu();
+                        }
+                        if menu_item(ui, "Markdownにエクスポート...").clicked() {
+                            self.export_as(ExportFormat::Markdown);
+                            ui.close_menu();
+                        }
+                        if menu_item(ui, "HTMLにエクスポート...").clicked() {
+                            self.export_as(ExportFormat::Html);
+                            ui.close_menu();
+                        }
+                        if menu_item(ui, "Wordにエクスポート...").clicked() {
+                            self.export_file("Word文書", "docx", docx::build);
+                            ui.close_menu();
+                        }
+                        if menu_item(ui, "ODTにエクスポート...").clicked() {
+                            self.export_file("OpenDocumentテキスト", "odt", odt::build);
+                            ui.close_menu();
+                        }
+                        if menu_item(ui, "EPUBにエクスポート...").clicked() {
+                            self.epub_dialog = Some(EpubOptions {
+                                vertical: true,
+                                cover_image: None,
+                            });
+                            ui.close_menu();
+                        }
+                        if menu_item(ui, "PDF原稿にエクスポート...").clicked() {
+                            self.open_pdf_dialog();
+                            ui.close_menu();
+                        }
+                        if menu_item(ui, "投稿サイト向けにエクスポート...").clicked() {
+                            self.site_dialog = Some((Site::Narou, self.settings.text_export));
+                            ui.close_menu();
+                        }
+                        ui.separator();
+                        if menu_item(ui, "バックアップから復元...").clicked() {
+                            self.backup_entries = Some(
+                                self.current_file_path
+                                    .as_deref()
+                                    .map(format::list_backups)
+                                    .unwrap_or_default(),
+                            );
+                            ui.close_menu();
+                        }
                     });
 
                     ui.add_space(5.0);
@@ -855,6 +1985,13 @@ impl eframe::App for StoryComposerApp {
                             self.show_search_dialog = false;
                             ui.close_menu();
                         }
+                        ui.separator();
+                        let label = if self.settings.show_outline { "アウトラインを隠す" } else { "アウトラインを表示" };
+                        if menu_item(ui, label).clicked() {
+                            self.settings.show_outline = !self.settings.show_outline;
+                            self.settings.save();
+                            ui.close_menu();
+                        }
                     });
 
                     ui.add_space(5.0);
@@ -959,7 +2096,7 @@ impl eframe::App for StoryComposerApp {
                     // Export button - right aligned
                     ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
                         if styled_menu_button_w(ui, "テキストにエクスポート", egui::Color32::from_rgb(46, 139, 87), 160.0).clicked() {
-                            self.export_text();
+                            self.text_dialog = Some(self.settings.text_export);
                         }
                     });
                     });
@@ -974,8 +2111,8 @@ impl eframe::App for StoryComposerApp {
             self.redo();
         }
         if ctx.input(|i| i.modifiers.ctrl && i.key_pressed(egui::Key::S)) {
-            if let Some(ref path) = self.current_file_path.clone() {
-                let _ = self.save_file(path);
+            if let Some(path) = self.current_file_path.clone() {
+                self.save_and_report(path);
             }
         }
         if ctx.input(|i| i.modifiers.ctrl && i.key_pressed(egui::Key::F)) {
@@ -1046,7 +2183,7 @@ impl eframe::App for StoryComposerApp {
                     ui.label("本当にこのプロットを削除しますか？");
                     ui.horizontal(|ui| {
                         if ui.button("はい").clicked() {
-                            if let Some(index) = self.plots.iter().position(|p| p.id == delete_id) {
+                            if let Some(index) = self.doc.plot_index(delete_id) {
                                 self.remove_plot(index);
                             }
                             self.delete_confirm_id = None;
@@ -1058,14 +2195,735 @@ impl eframe::App for StoryComposerApp {
                 });
         }
 
+        // Move to position dialog
+        if let Some((ids, mut position)) = self.move_dialog.take() {
+            let mut open = true;
+            let mut apply = false;
+            egui::Window::new("番号を指定して移動")
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .show(ctx, |ui| {
+                    let count = ids.iter().filter(|&&id| self.doc.plot_index(id).is_some()).count();
+                    if count == 0 {
+                        open = false;
+                        return;
+                    }
+                    match self.doc.plot_index(ids[0]) {
+                        Some(index) if count == 1 => ui.label(format!("#{} を移動します。", index + 1)),
+                        _ => ui.label(format!("{}件のプロットをまとめて移動します。", count)),
+                    };
+                    ui.horizontal(|ui| {
+                        ui.label("移動先の番号:");
+                        let last = self.doc.plots.len() - count + 1;
+                        ui.add(egui::DragValue::new(&mut position).range(1..=last));
+                    });
+                    ui.add_space(8.0);
+                    ui.horizontal(|ui| {
+                        if ui.button("移動").clicked() {
+                            apply = true;
+                        }
+                        if ui.button("キャンセル").clicked() {
+                            open = false;
+                        }
+                    });
+                });
+            if apply {
+                self.move_plots_to(&ids, position - 1);
+            } else if open {
+                self.move_dialog = Some((ids, position));
+            }
+        }
+
+        // Batch delete confirmation dialog
+        if self.batch_delete_confirm {
+            egui::Window::new("確認")
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .show(ctx, |ui| {
+                    ui.label(format!("選択した{}件のプロットを削除しますか？", self.selection.len()));
+                    ui.horizontal(|ui| {
+                        if ui.button("はい").clicked() {
+                            self.remove_selected();
+                            self.batch_delete_confirm = false;
+                        }
+                        if ui.button("いいえ").clicked() {
+                            self.batch_delete_confirm = false;
+                        }
+                    });
+                });
+        }
+
+        // Plot editor opened from the corkboard
+        if let Some(plot_id) = self.card_editor {
+            let mut open = self.doc.plot_index(plot_id).is_some();
+            if let Some(index) = self.doc.plot_index(plot_id) {
+                let font_size = self.settings.font_size;
+                egui::Window::new(format!("#{} の編集", index + 1))
+                    .id(egui::Id::new("card_editor"))
+                    .open(&mut open)
+                    .default_width(520.0)
+                    .show(ctx, |ui| {
+                        let plot = &mut self.doc.plots[index];
+                        let mut changed = false;
+                        if plot.kind.is_heading() {
+                            ui.label(plot.kind.display_name());
+                            let text_edit = egui::TextEdit::singleline(&mut plot.text)
+                                .desired_width(f32::INFINITY)
+                                .font(egui::FontId::proportional(font_size + 4.0));
+                            changed |= ui.add(text_edit).changed();
+                        } else {
+                            let title_edit = egui::TextEdit::singleline(&mut plot.title)
+                                .hint_text("タイトル")
+                                .desired_width(f32::INFINITY)
+                                .font(egui::FontId::proportional(font_size));
+                            changed |= ui.add(title_edit).changed();
+                            let synopsis_edit = egui::TextEdit::singleline(&mut plot.synopsis)
+                                .hint_text("あらすじ（一行）")
+                                .desired_width(f32::INFINITY);
+                            changed |= ui.add(synopsis_edit).changed();
+                            egui::ScrollArea::vertical().max_height(420.0).show(ui, |ui| {
+                                let text_edit = egui::TextEdit::multiline(&mut plot.text)
+                                    .desired_width(f32::INFINITY)
+                                    .desired_rows(15)
+                                    .font(egui::FontId::monospace(font_size));
+                                changed |= ui.add(text_edit).changed();
+                            });
+                            let notes_title = if plot.notes.is_empty() { "メモ" } else { "メモ *" };
+                            egui::CollapsingHeader::new(notes_title)
+                                .id_salt(("card_notes", plot_id))
+                                .show(ui, |ui| {
+                                    let notes_edit = egui::TextEdit::multiline(&mut plot.notes)
+                                        .desired_width(f32::INFINITY)
+                                        .desired_rows(3);
+                                    changed |= ui.add(notes_edit).changed();
+                                });
+                        }
+                        if changed {
+                            self.is_dirty = true;
+                        }
+                    });
+            }
+            if !open {
+                self.card_editor = None;
+            }
+        }
+
+        // Document info dialog
+        if self.show_meta_dialog {
+            egui::Window::new("文書情報")
+                .collapsible(false)
+                .resizable(false)
+                .show(ctx, |ui| {
+                    egui::Grid::new("meta_grid").num_columns(2).show(ui, |ui| {
+                        let meta = &mut self.doc.meta;
+                        let mut changed = false;
+                        ui.label("タイトル:");
+                        changed |= ui.text_edit_singleline(&mut meta.title).changed();
+                        ui.end_row();
+                        ui.label("著者:");
+                        changed |= ui.text_edit_singleline(&mut meta.author).changed();
+                        ui.end_row();
+                        ui.label("日付:");
+                        changed |= ui
+                            .add(egui::TextEdit::singleline(&mut meta.date).hint_text("空欄の場合は書き出した日付"))
+                            .changed();
+                        ui.end_row();
+                        if changed {
+                            self.is_dirty = true;
+                        }
+                    });
+                    ui.add_space(8.0);
+                    if ui.button("閉じる").clicked() {
+                        self.show_meta_dialog = false;
+                    }
+                });
+        }
+
+        // Compose template dialog
+        if self.show_template_dialog {
+            egui::Window::new("文書生成の書式")
+                .collapsible(false)
+                .resizable(false)
+                .show(ctx, |ui| {
+                    let template = &mut self.doc.template;
+                    let mut changed = false;
+                    ui.horizontal(|ui| {
+                        ui.label("既定の書式:");
+                        egui::ComboBox::from_id_salt("template_builtin")
+                            .selected_text(template.builtin_name().unwrap_or("カスタム"))
+                            .show_ui(ui, |ui| {
+                                for (name, builtin) in ComposeTemplate::builtins() {
+                                    if ui.selectable_label(*template == builtin, name).clicked() && *template != builtin {
+                                        *template = builtin;
+                                        changed = true;
+                                    }
+                                }
+                            });
+                    });
+                    ui.add_space(4.0);
+                    egui::Grid::new("template_grid").num_columns(2).show(ui, |ui| {
+                        ui.label("ヘッダー:");
+                        changed |= ui.add(egui::TextEdit::multiline(&mut template.header).desired_rows(1)).changed();
+                        ui.end_row();
+                        ui.label("見出し:");
+                        changed |= ui.add(egui::TextEdit::multiline(&mut template.heading).desired_rows(1)).changed();
+                        ui.end_row();
+                        ui.label("シーン:");
+                        changed |= ui.add(egui::TextEdit::multiline(&mut template.scene).desired_rows(3)).changed();
+                        ui.end_row();
+                        ui.label("区切り線:");
+                        changed |= ui
+                            .add(egui::TextEdit::singleline(&mut template.separator).hint_text("空欄の場合は空行のみ"))
+                            .changed();
+                        ui.end_row();
+                        ui.label("フッター:");
+                        changed |= ui.add(egui::TextEdit::multiline(&mut template.footer).desired_rows(1)).changed();
+                        ui.end_row();
+                    });
+                    if changed {
+                        self.is_dirty = true;
+                    }
+                    ui.add_space(4.0);
+                    ui.label(
+                        egui::RichText::new(
+                            "{{text}} 本文　{{title}} プロットのタイトル　{{index}} シーン番号　{{chapter}} 章の見出し\n\
+                             ヘッダー・フッターでは {{title}} は文書のタイトル、{{author}} は著者名",
+                        )
+                        .small()
+                        .weak(),
+                    );
+                    let problem = template.validate().err();
+                    if let Some(problem) = &problem {
+                        ui.colored_label(egui::Color32::from_rgb(230, 110, 110), problem.as_str());
+                    }
+                    ui.add_space(8.0);
+                    if ui.add_enabled(problem.is_none(), egui::Button::new("閉じる")).clicked() {
+                        self.show_template_dialog = false;
+                    }
+                });
+        }
+
+        // EPUB export dialog
+        if let Some(mut options) = self.epub_dialog.take() {
+            let mut open = true;
+            let mut export = false;
+            egui::Window::new("EPUBにエクスポート")
+                .collapsible(false)
+                .resizable(false)
+                .show(ctx, |ui| {
+                    egui::Grid::new("epub_grid").num_columns(2).show(ui, |ui| {
+                        ui.label("文字の向き:");
+                        ui.horizontal(|ui| {
+                            ui.radio_value(&mut options.vertical, true, "縦書き");
+                            ui.radio_value(&mut options.vertical, false, "横書き");
+                        });
+                        ui.end_row();
+                        let meta = &mut self.doc.meta;
+                        let mut changed = false;
+                        ui.label("タイトル:");
+                        changed |= ui.text_edit_singleline(&mut meta.title).changed();
+                        ui.end_row();
+                        ui.label("著者:");
+                        changed |= ui.text_edit_singleline(&mut meta.author).changed();
+                        ui.end_row();
+                        if changed {
+                            self.is_dirty = true;
+                        }
+                        ui.label("表紙画像:");
+                        ui.horizontal(|ui| {
+                            let name = options
+                                .cover_image
+                                .as_deref()
+                                .and_then(|p| p.file_name())
+                                .map_or("なし".to_owned(), |n| n.to_string_lossy().into_owned());
+                            ui.label(name);
+                            if ui.button("選択...").clicked() {
+                                if let Some(path) = rfd::FileDialog::new()
+                                    .add_filter("画像", &["jpg", "jpeg", "png", "gif"])
+                                    .pick_file()
+                                {
+                                    options.cover_image = Some(path);
+                                }
+                            }
+                            if options.cover_image.is_some() && ui.button("解除").clicked() {
+                                options.cover_image = None;
+                            }
+                        });
+                        ui.end_row();
+                    });
+                    ui.add_space(8.0);
+                    ui.horizontal(|ui| {
+                        if ui.button("書き出す").clicked() {
+                            export = true;
+                        }
+                        if ui.button("キャンセル").clicked() {
+                            open = false;
+                        }
+                    });
+                });
+            if export {
+                self.export_file("EPUB", "epub", |doc| epub::build(doc, &options));
+            } else if open {
+                self.epub_dialog = Some(options);
+            }
+        }
+
+        // PDF export dialog
+        if let Some(mut options) = self.pdf_dialog.take() {
+            let mut open = true;
+            let mut export = false;
+            egui::Window::new("PDF原稿にエクスポート")
+                .collapsible(false)
+                .resizable(false)
+                .show(ctx, |ui| {
+                    egui::Grid::new("pdf_grid").num_columns(2).show(ui, |ui| {
+                        ui.label("書式:");
+                        let current = LAYOUTS.iter().find(|l| {
+                            l.chars_per_line == options.chars_per_line && l.lines_per_page == options.lines_per_page
+                        });
+                        egui::ComboBox::from_id_salt("pdf_layout")
+                            .selected_text(current.map_or("カスタム", |l| l.name))
+                            .show_ui(ui, |ui| {
+                                for layout in LAYOUTS {
+                                    if ui.selectable_label(current.is_some_and(|c| c.name == layout.name), layout.name).clicked() {
+                                        options.apply_layout(layout);
+                                    }
+                                }
+                            });
+                        ui.end_row();
+                        ui.label("字数×行数:");
+                        ui.horizontal(|ui| {
+                            ui.add(egui::DragValue::new(&mut options.chars_per_line).range(10..=60));
+                            ui.label("字 ×");
+                            ui.add(egui::DragValue::new(&mut options.lines_per_page).range(5..=60));
+                            ui.label("行");
+                        });
+                        ui.end_row();
+                        ui.label("");
+                        ui.horizontal(|ui| {
+                            ui.checkbox(&mut options.grid, "マス目を描く");
+                            ui.checkbox(&mut options.page_numbers, "ページ番号");
+                        });
+                        ui.end_row();
+                        ui.label("フォント:");
+                        ui.horizontal(|ui| {
+                            let name = options
+                                .font_path
+                                .file_name()
+                                .map_or(String::new(), |n| n.to_string_lossy().into_owned());
+                            ui.label(name);
+                            if ui.button("選択...").clicked() {
+                                if let Some(path) = rfd::FileDialog::new()
+                                    .add_filter("TrueTypeフォント", &["ttf", "ttc"])
+                                    .pick_file()
+                                {
+                                    options.font_path = path;
+                                    options.font_index = 0;
+                                }
+                            }
+                        });
+                        ui.end_row();
+                        let is_collection = options
+                            .font_path
+                            .extension()
+                            .is_some_and(|e| e.eq_ignore_ascii_case("ttc"));
+                        if is_collection {
+                            ui.label("フォント番号:");
+                            ui.add(egui::DragValue::new(&mut options.font_index).range(0..=31));
+                            ui.end_row();
+                        }
+                    });
+                    ui.add_space(8.0);
+                    ui.horizontal(|ui| {
+                        if ui.button("書き出す").clicked() {
+                            export = true;
+                        }
+                        if ui.button("キャンセル").clicked() {
+                            open = false;
+                        }
+                    });
+                });
+            if export {
+                self.export_pdf(&options);
+            } else if open {
+                self.pdf_dialog = Some(options);
+            }
+        }
+
+        // Recompose confirmation dialog
+        if let Some(mut dialog) = self.recompose_dialog.take() {
+            let mut open = true;
+            let mut confirm = false;
+            egui::Window::new("文書生成")
+                .collapsible(false)
+                .resizable(true)
+                .default_width(600.0)
+                .show(ctx, |ui| {
+                    match &mut dialog {
+                        RecomposeDialog::ConfirmFull(reason) => {
+                            ui.label(reason.as_str());
+                            ui.label("すべてのプロットから作り直すと、出力テキストの手直しは失われます（元に戻すことは出来ます）。");
+                        }
+                        RecomposeDialog::Conflicts {
+                            result,
+                            diffs,
+                            use_generated,
+                        } => {
+                            if !result.conflicts.is_empty() {
+                                ui.label("プロットと出力テキストの両方が変更された箇所があります。使う内容を選んでください。");
+                            }
+                            if !result.removed_edits.is_empty() {
+                                ui.colored_label(
+                                    egui::Color32::from_rgb(220, 160, 60),
+                                    format!(
+                                        "削除・除外したプロットの区間にあった手直し（{}件）は出力テキストから除かれます。",
+                                        result.removed_edits.len()
+                                    ),
+                                );
+                            }
+                            ui.separator();
+                            egui::ScrollArea::vertical().max_height(480.0).show(ui, |ui| {
+                                for (k, conflict) in result.conflicts.iter().enumerate() {
+                                    let Some(index) = self.doc.plot_index(conflict.id) else {
+                                        continue;
+                                    };
+                                    let plot = &self.doc.plots[index];
+                                    let name = if plot.title.is_empty() {
+                                        plot.text.lines().next().unwrap_or("").chars().take(30).collect()
+                                    } else {
+                                        plot.title.clone()
+                                    };
+                                    ui.label(egui::RichText::new(format!("{}. {}", index + 1, name)).strong());
+                                    ui.horizontal(|ui| {
+                                        ui.radio_value(&mut use_generated[k], false, "出力の手直しを残す");
+                                        ui.radio_value(&mut use_generated[k], true, "プロットの内容にする");
+                                    });
+                                    ui.indent(("recompose_diff", conflict.id), |ui| diff_view(ui, &diffs[k]));
+                                    ui.add_space(6.0);
+                                }
+                                for (k, removed) in result.removed_edits.iter().enumerate() {
+                                    egui::CollapsingHeader::new(format!(
+                                        "除かれる手直し: {}",
+                                        removed.lines().next().unwrap_or("").chars().take(30).collect::<String>()
+                                    ))
+                                    .id_salt(("removed_edit", k))
+                                    .show(ui, |ui| {
+                                        ui.label(removed.as_str());
+                                    });
+                                }
+                            });
+                        }
+                    }
+                    ui.add_space(8.0);
+                    ui.horizontal(|ui| {
+                        let label = match dialog {
+                            RecomposeDialog::ConfirmFull(_) => "すべて作り直す",
+                            RecomposeDialog::Conflicts { .. } => "生成する",
+                        };
+                        if ui.button(label).clicked() {
+                            confirm = true;
+                        }
+                        if ui.button("キャンセル").clicked() {
+                            open = false;
+                        }
+                    });
+                });
+            if confirm {
+                match dialog {
+                    RecomposeDialog::ConfirmFull(_) => self.compose_all(),
+                    RecomposeDialog::Conflicts {
+                        mut result,
+                        use_generated,
+                        ..
+                    } => {
+                        let ids: Vec<usize> = result.conflicts.iter().map(|c| c.id).collect();
+                        for (id, generated) in ids.into_iter().zip(use_generated) {
+                            if generated {
+                                result.use_generated(id);
+                            }
+                        }
+                        self.apply_recompose(&result);
+                    }
+                }
+            } else if open {
+                self.recompose_dialog = Some(dialog);
+            }
+        }
+
+        // Decompose dialog
+        if let Some(mut dialog) = self.decompose_dialog.take() {
+            let mut open = true;
+            let mut apply = false;
+            egui::Window::new("プロットに反映")
+                .collapsible(false)
+                .resizable(true)
+                .default_width(600.0)
+                .show(ctx, |ui| {
+                    for problem in &dialog.problems {
+                        ui.colored_label(egui::Color32::from_rgb(220, 80, 80), format!("反映できません: {}", problem));
+                    }
+                    if dialog.items.is_empty() {
+                        ui.label("プロットと異なる箇所はありません");
+                    } else {
+                        ui.label(format!("{}件のプロットに変更があります。反映するものを選んでください。", dialog.items.len()));
+                        ui.horizontal(|ui| {
+                            if ui.button("すべて選択").clicked() {
+                                dialog.items.iter_mut().for_each(|item| item.accept = true);
+                            }
+                            if ui.button("すべて解除").clicked() {
+                                dialog.items.iter_mut().for_each(|item| item.accept = false);
+                            }
+                        });
+                    }
+                    ui.separator();
+                    egui::ScrollArea::vertical().max_height(480.0).show(ui, |ui| {
+                        for item in &mut dialog.items {
+                            let Some(index) = self.doc.plot_index(item.change.id) else {
+                                continue;
+                            };
+                            let plot = &self.doc.plots[index];
+                            let name = if plot.title.is_empty() {
+                                plot.text.lines().next().unwrap_or("").chars().take(30).collect()
+                            } else {
+                                plot.title.clone()
+                            };
+                            ui.checkbox(&mut item.accept, format!("{}. {}", index + 1, name));
+                            ui.indent(("decompose_diff", item.change.id), |ui| diff_view(ui, &item.diff));
+                            ui.add_space(6.0);
+                        }
+                    });
+                    ui.add_space(8.0);
+                    ui.horizontal(|ui| {
+                        let any = dialog.items.iter().any(|item| item.accept);
+                        if ui.add_enabled(any, egui::Button::new("反映する")).clicked() {
+                            apply = true;
+                        }
+                        if ui.button("キャンセル").clicked() {
+                            open = false;
+                        }
+                    });
+                });
+            if apply {
+                self.apply_decompose(&dialog.items);
+            } else if open {
+                self.decompose_dialog = Some(dialog);
+            }
+        }
+
+        // Import dialog
+        if let Some(mut dialog) = self.import_dialog.take() {
+            let mut open = true;
+            let mut import = false;
+            egui::Window::new("テキストを取り込む")
+                .collapsible(false)
+                .resizable(false)
+                .show(ctx, |ui| {
+                    let file_name = dialog.path.file_name().map_or(String::new(), |n| n.to_string_lossy().into_owned());
+                    ui.label(format!("ファイル: {}", file_name));
+                    let mut changed = false;
+                    ui.horizontal(|ui| {
+                        ui.label("区切り:");
+                        for &rule in SplitRule::all() {
+                            changed |= ui.radio_value(&mut dialog.options.rule, rule, rule.display_name()).changed();
+                        }
+                    });
+                    if dialog.options.rule == SplitRule::Regex {
+                        ui.horizontal(|ui| {
+                            ui.label("区切りの行:");
+                            changed |= ui
+                                .add(egui::TextEdit::singleline(&mut dialog.options.pattern).hint_text("^第.+話"))
+                                .changed();
+                        });
+                        ui.label("一致した行は次のプロットのタイトルになります。");
+                    }
+                    if changed {
+                        dialog.update_preview();
+                    }
+                    ui.separator();
+
+                    let mut can_import = false;
+                    match &dialog.preview {
+                        Err(e) => {
+                            ui.colored_label(egui::Color32::from_rgb(220, 80, 80), e);
+                        }
+                        Ok(plots) if plots.is_empty() => {
+                            ui.label("取り込む文章がありません");
+                        }
+                        Ok(plots) => {
+                            let total = self.doc.plots.len() + plots.len();
+                            if total > MAX_PLOTS {
+                                ui.colored_label(
+                                    egui::Color32::from_rgb(220, 80, 80),
+                                    format!("プロット数が上限（{}）を超えます: {}", MAX_PLOTS, total),
+                                );
+                            } else {
+                                ui.label(format!("{}件のプロットに分かれます", plots.len()));
+                                can_import = true;
+                            }
+                            egui::ScrollArea::vertical().max_height(320.0).show(ui, |ui| {
+                                for (i, plot) in plots.iter().enumerate() {
+                                    let first_line = plot.text.lines().next().unwrap_or("");
+                                    let summary: String = first_line.chars().take(40).collect();
+                                    let label = if plot.kind.is_heading() || plot.title.is_empty() {
+                                        format!("{}. [{}] {}", i + 1, plot.kind.display_name(), summary)
+                                    } else {
+                                        format!("{}. [{}] {} ／ {}", i + 1, plot.kind.display_name(), plot.title, summary)
+                                    };
+                                    ui.label(label);
+                                }
+                            });
+                        }
+                    }
+
+                    ui.add_space(8.0);
+                    ui.horizontal(|ui| {
+                        if ui.add_enabled(can_import, egui::Button::new("取り込む")).clicked() {
+                            import = true;
+                        }
+                        if ui.button("キャンセル").clicked() {
+                            open = false;
+                        }
+                    });
+                });
+            if import {
+                if let Ok(plots) = dialog.preview {
+                    self.import_plots(plots);
+                }
+            } else if open {
+                self.import_dialog = Some(dialog);
+            }
+        }
+
+        // Text export dialog
+        if let Some(mut options) = self.text_dialog.take() {
+            let mut open = true;
+            let mut export = false;
+            egui::Window::new("テキストにエクスポート")
+                .collapsible(false)
+                .resizable(false)
+                .show(ctx, |ui| {
+                    text_options_ui(ui, "text_options", &mut options);
+                    ui.add_space(8.0);
+                    ui.horizontal(|ui| {
+                        if ui.button("書き出す").clicked() {
+                            export = true;
+                        }
+                        if ui.button("キャンセル").clicked() {
+                            open = false;
+                        }
+                    });
+                });
+            if export {
+                self.export_text(&options);
+            } else if open {
+                self.text_dialog = Some(options);
+            }
+        }
+
+        // Posting site export dialog
+        if let Some((mut site, mut text_options)) = self.site_dialog.take() {
+            let mut open = true;
+            let mut export = false;
+            egui::Window::new("投稿サイト向けにエクスポート")
+                .collapsible(false)
+                .resizable(false)
+                .show(ctx, |ui| {
+                    for &choice in Site::all() {
+                        ui.radio_value(&mut site, choice, choice.display_name());
+                    }
+                    ui.add_space(4.0);
+                    ui.label(format!(
+                        "ルビ・傍点をサイトの記法に変換し、幕・章ごと（1話{}字まで）にファイルを分けて書き出します。",
+                        site.char_limit()
+                    ));
+                    ui.add_space(4.0);
+                    text_options_ui(ui, "site_text_options", &mut text_options);
+                    ui.add_space(8.0);
+                    ui.horizontal(|ui| {
+                        if ui.button("フォルダを選んで書き出す").clicked() {
+                            export = true;
+                        }
+                        if ui.button("キャンセル").clicked() {
+                            open = false;
+                        }
+                    });
+                });
+            if export {
+                self.export_site(site, &text_options);
+            } else if open {
+                self.site_dialog = Some((site, text_options));
+            }
+        }
+
+        // Backup restore dialog
+        if self.backup_entries.is_some() {
+            let mut restore_path = None;
+            let mut close = false;
+            egui::Window::new("バックアップから復元")
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .show(ctx, |ui| {
+                    let entries = self.backup_entries.as_deref().unwrap_or_default();
+                    if self.current_file_path.is_none() {
+                        ui.label("ファイルが保存されていません。");
+                    } else if entries.is_empty() {
+                        ui.label("バックアップがありません。");
+                    } else {
+                        ui.label("復元するバックアップを選んでください。");
+                        ui.add_space(4.0);
+                        egui::Grid::new("backup_grid").striped(true).show(ui, |ui| {
+                            for entry in entries {
+                                ui.label(format!("{}世代前", entry.generation));
+                                let modified: chrono::DateTime<chrono::Local> = entry.modified.into();
+                                ui.label(modified.format("%Y/%m/%d %H:%M:%S").to_string());
+                                if ui.button("復元").clicked() {
+                                    restore_path = Some(entry.path.clone());
+                                }
+                                ui.end_row();
+                            }
+                        });
+                    }
+                    ui.add_space(8.0);
+                    if ui.button("閉じる").clicked() {
+                        close = true;
+                    }
+                });
+            if let Some(path) = restore_path {
+                if let Err(e) = self.restore_backup(&path) {
+                    eprintln!("Load error: {}", e);
+                    show_error("読み込みエラー", &e);
+                }
+                close = true;
+            }
+            if close {
+                self.backup_entries = None;
+            }
+        }
+
         // Collect pending action from previous frame
         let pending_action = self.pending_action.take();
         if let Some((index, act)) = pending_action {
             match act {
-                PlotAction::AddAfter => self.add_plot_after(index),
+                PlotAction::AddAfter(kind) => self.add_plot_after(index, kind),
                 PlotAction::RequestDelete(id) => self.delete_confirm_id = Some(id),
+                PlotAction::SetKind(kind) => self.set_plot_kind(index, kind),
+                PlotAction::SetStatus(status) => self.set_plot_status(index, status),
+                PlotAction::SetColor(color) => self.set_plot_color(index, color),
+                PlotAction::SetExcluded(excluded) => self.set_plot_excluded(index, excluded),
+                PlotAction::AddTag(tag) => self.edit(|doc| doc.add_tag(index, &tag)),
+                PlotAction::RemoveTag(tag) => self.edit(|doc| doc.remove_tag(index, &tag)),
+                PlotAction::ToggleCollapse => {
+                    self.doc.plots[index].collapsed = !self.doc.plots[index].collapsed;
+                    self.is_dirty = true;
+                }
                 PlotAction::MoveUp => self.move_plot_up(index),
                 PlotAction::MoveDown => self.move_plot_down(index),
+                PlotAction::MoveBefore(target) => self.move_plot_before(index, target),
+                PlotAction::SplitAt(cursor) => self.split_plot(index, cursor),
+                PlotAction::MergeWith(other) => self.merge_plots(&[index.min(other), index.max(other)]),
             }
         }
 
@@ -1078,13 +2936,35 @@ impl eframe::App for StoryComposerApp {
                     ui.label("ファイル: 未保存");
                 }
                 ui.separator();
-                ui.label(format!("Undo: {} | Redo: {}", self.undo_stack.len(), self.redo_stack.len()));
+                ui.label(format!("Undo: {} | Redo: {}", self.history.undo_len(), self.history.redo_len()));
             });
         });
 
         // Get current font size for text areas
         let font_size = self.settings.font_size;
 
+        // Outline navigator
+        if self.settings.show_outline {
+            let matches = self.search_match_ids();
+            let mut clicked = None;
+            egui::SidePanel::left("outline_panel")
+                .resizable(true)
+                .default_width(240.0)
+                .show(ctx, |ui| {
+                    ui.heading("アウトライン");
+                    if !self.search_results.is_empty() {
+                        ui.label(format!("「{}」に一致: {}件のプロット", self.search_text, matches.len()));
+                    }
+                    ui.add_space(6.0);
+                    egui::ScrollArea::vertical().id_salt("outline_scroll").show(ui, |ui| {
+                        self.outline_entries(ui, 0, self.doc.plots.len(), &matches, &mut clicked);
+                    });
+                });
+            if let Some(index) = clicked {
+                self.navigate_to(index);
+            }
+        }
+
         // Main content - fixed 50/50 split
         egui::CentralPanel::default().show(ctx, |ui| {
             let total_width = ui.available_width();
@@ -1095,57 +2975,435 @@ impl eframe::App for StoryComposerApp {
                 // Left pane - Plots (fixed 50%)
                 ui.allocate_ui(egui::vec2(panel_width, panel_height), |ui| {
                     ui.vertical(|ui| {
-                        ui.heading("プロット");
+                        ui.horizontal(|ui| {
+                            ui.heading("プロット");
+                            ui.add_space(20.0);
+                            let mut view = self.settings.plot_view;
+                            ui.selectable_value(&mut view, PlotView::List, "一覧");
+                            ui.selectable_value(&mut view, PlotView::Corkboard, "コルクボード");
+                            if view != self.settings.plot_view {
+                                self.settings.plot_view = view;
+                                self.settings.save();
+                            }
+                        });
                         ui.add_space(10.0);
 
-                        egui::ScrollArea::vertical()
-                            .id_salt("left_scroll")
-                            .show(ui, |ui| {
-                                let plots_len = self.plots.len();
-                                let text_width = panel_width - 120.0;
-
-                                for i in 0..plots_len {
-                                    let plot_id = self.plots[i].id;
-
-                                    ui.horizontal(|ui| {
-                                        // Plot number
-                                        ui.label(format!("#{:3}", i + 1));
-
-                                        // Calculate rows based on content (minimum 10, expand as needed)
-                                        let line_count = self.plots[i].text.lines().count().max(1);
-                                        let display_rows = line_count.max(10);
-
-                                        // Text area - expands with content
-                                        let text_edit = egui::TextEdit::multiline(&mut self.plots[i].text)
-                                            .desired_width(text_width)
-                                            .desired_rows(display_rows)
-                                            .font(egui::FontId::monospace(font_size));
-                                        if ui.add(text_edit).changed() {
+                        // タグで絞り込み
+                        let all_tags = self.doc.all_tags();
+                        ui.horizontal_wrapped(|ui| {
+                            ui.label("絞り込み:");
+                            let filter = &mut self.doc.filter;
+                            let mut removed = None;
+                            for (k, tag) in filter.tags.iter().enumerate() {
+                                if ui.button(format!("#{} ×", tag)).clicked() {
+                                    removed = Some(k);
+                                }
+                            }
+                            let mut changed = false;
+                            if let Some(k) = removed {
+                                filter.tags.remove(k);
+                                changed = true;
+                            }
+                            let available: Vec<&String> = all_tags.iter().filter(|t| !filter.tags.contains(t)).collect();
+                            ui.add_enabled_ui(!available.is_empty(), |ui| {
+                                egui::ComboBox::from_id_salt("tag_filter_add")
+                                    .selected_text(if all_tags.is_empty() { "タグがありません" } else { "タグを選択" })
+                                    .show_ui(ui, |ui| {
+                                        for tag in available {
+                                            if ui.selectable_label(false, tag).clicked() {
+                                                filter.tags.push(tag.clone());
+                                                changed = true;
+                                            }
+                                        }
+                                    });
+                            });
+                            if filter.is_active() {
+                                changed |= ui.checkbox(&mut filter.compose_only, "絞り込んだプロットだけ文書生成").changed();
+                            }
+                            // 絞り込みはファイルに保存するため、未保存の変更として扱う
+                            if changed {
+                                self.is_dirty = true;
+                            }
+                            ui.add_enabled_ui(!all_tags.is_empty(), |ui| {
+                                ui.menu_button("除外するタグ", |ui| {
+                                    for tag in &all_tags {
+                                        let mut excluded = self.doc.excluded_tags.contains(tag);
+                                        if ui.checkbox(&mut excluded, tag).changed() {
+                                            if excluded {
+                                                self.doc.excluded_tags.push(tag.clone());
+                                            } else {
+                                                self.doc.excluded_tags.retain(|t| t != tag);
+                                            }
                                             self.is_dirty = true;
                                         }
+                                    }
+                                });
+                            });
+                        });
+                        ui.add_space(6.0);
+
+                        // 選択したプロットへの一括操作
+                        self.selection.retain(|&id| self.doc.plot_index(id).is_some());
+                        if !self.selection.is_empty() {
+                            ui.horizontal_wrapped(|ui| {
+                                ui.label(format!("{}件を選択中:", self.selection.len()));
+                                if ui.button("削除").clicked() {
+                                    self.batch_delete_confirm = true;
+                                }
+                                if ui.button("複製").clicked() {
+                                    self.duplicate_selected();
+                                }
+                                if ui.button("移動...").clicked() {
+                                    let indices = self.selected_indices();
+                                    let ids = indices.iter().map(|&i| self.doc.plots[i].id).collect();
+                                    self.move_dialog = Some((ids, indices[0] + 1));
+                                }
+                                if ui.add_enabled(self.selection.len() > 1, egui::Button::new("結合")).clicked() {
+                                    self.merge_selected();
+                                }
+                                ui.menu_button("進捗", |ui| {
+                                    for &status in PlotStatus::all() {
+                                        if ui.button(status.display_name()).clicked() {
+                                            self.set_selected(|plot| {
+                                                let changed = !plot.kind.is_heading() && plot.status != status;
+                                                if changed {
+                                                    plot.status = status;
+                                                }
+                                                changed
+                                            });
+                                            ui.close_menu();
+                                        }
+                                    }
+                                });
+                                if ui.button("文書生成に含める").clicked() {
+                                    self.set_selected(|plot| std::mem::replace(&mut plot.excluded, false));
+                                }
+                                if ui.button("除外").clicked() {
+                                    self.set_selected(|plot| !std::mem::replace(&mut plot.excluded, true));
+                                }
+                                let response = ui.add(
+                                    egui::TextEdit::singleline(&mut self.batch_tag_input)
+                                        .hint_text("タグ")
+                                        .desired_width(70.0),
+                                );
+                                let entered = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                                if (ui.button("タグを付ける").clicked() || entered) && !self.batch_tag_input.trim().is_empty() {
+                                    let tag = std::mem::take(&mut self.batch_tag_input);
+                                    self.add_tag_to_selected(&tag);
+                                }
+                                if ui.button("選択解除").clicked() {
+                                    self.clear_selection();
+                                }
+                            });
+                            ui.add_space(6.0);
+                        }
 
-                                        // Buttons
-                                        ui.vertical(|ui| {
-                                            if small_flat_button(ui, "+").clicked() && plots_len < MAX_PLOTS {
-                                                self.pending_action = Some((i, PlotAction::AddAfter));
+                        if self.settings.plot_view == PlotView::Corkboard {
+                            self.corkboard_ui(ui, font_size);
+                        } else {
+                            egui::ScrollArea::vertical()
+                                .id_salt("left_scroll")
+                                .show(ui, |ui| {
+                                    let plots_len = self.doc.plots.len();
+                                    let text_width = panel_width - 150.0;
+
+                                    // 折りたたまれた見出しの配下はスキップし、階層に応じて字下げする
+                                    let mut skip_until = 0;
+                                    let mut excluded_until = 0;
+                                    let filter_matches = self.doc.filter_matches();
+                                    let mut visible_ids = Vec::new();
+                                    let mut in_act = false;
+                                    let mut in_chapter = false;
+
+                                    for i in 0..plots_len {
+                                        if i < skip_until {
+                                            continue;
+                                        }
+                                        let plot_id = self.doc.plots[i].id;
+                                        let kind = self.doc.plots[i].kind;
+                                        let depth = match kind {
+                                            PlotKind::Act => {
+                                                in_act = true;
+                                                in_chapter = false;
+                                                0
                                             }
-                                            if small_flat_button(ui, "-").clicked() && plots_len > 1 {
-                                                self.pending_action = Some((i, PlotAction::RequestDelete(plot_id)));
+                                            PlotKind::Chapter => {
+                                                in_chapter = true;
+                                                in_act as usize
                                             }
-                                            if small_flat_button(ui, "↑").clicked() && i > 0 {
-                                                self.pending_action = Some((i, PlotAction::MoveUp));
+                                            PlotKind::Scene => in_act as usize + in_chapter as usize,
+                                        };
+                                        let indent = depth as f32 * INDENT_WIDTH;
+                                        let block_end = self.doc.block_end(i);
+                                        let is_last_block = block_end >= plots_len;
+                                        let excluded = self.doc.plots[i].excluded;
+                                        // 文書生成から除外したプロット（除外するタグや、除外した見出しの配下を含む）は薄く表示する
+                                        let dimmed = self.doc.is_excluded(i) || i < excluded_until;
+                                        if self.doc.is_excluded(i) {
+                                            excluded_until = excluded_until.max(block_end);
+                                        }
+                                        if filter_matches.get(i) == Some(&false) {
+                                            continue;
+                                        }
+                                        visible_ids.push(plot_id);
+
+                                        let row = ui.horizontal(|ui| {
+                                            if dimmed {
+                                                ui.multiply_opacity(0.4);
+                                            }
+                                            ui.add_space(indent);
+
+                                            // ドラッグで並べ替え（見出しは配下のシーンごと移動する）
+                                            ui.dnd_drag_source(egui::Id::new(("plot_drag", plot_id)), PlotDrag(plot_id), |ui| {
+                                                ui.label(egui::RichText::new("≡").size(18.0).weak());
+                                            })
+                                            .response
+                                            .on_hover_text("ドラッグで移動");
+
+                                            // Plot number (右クリックで見出しの追加・種類の変更)
+                                            // シーンは番号の下に進捗と色ラベルを並べる
+                                            let number = ui.vertical(|ui| {
+                                                let number = ui.add(
+                                                    egui::Label::new(format!("#{:3}", i + 1))
+                                                        .sense(egui::Sense::click()),
+                                                );
+                                                if !kind.is_heading() {
+                                                    let plot = &self.doc.plots[i];
+                                                    let status = plot.status;
+                                                    if status_chip(ui, status)
+                                                        .on_hover_text("クリックで進捗を切り替え")
+                                                        .clicked()
+                                                    {
+                                                        self.pending_action = Some((i, PlotAction::SetStatus(status.next())));
+                                                    }
+                                                    if let Some(color) = color_label_button(ui, ("plot_color", plot_id), plot.color) {
+                                                        self.pending_action = Some((i, PlotAction::SetColor(color)));
+                                                    }
+                                                }
+                                                number
+                                            }).inner;
+                                            let number = number.on_hover_text("クリックで選択（Ctrl: 追加、Shift: 範囲）");
+                                            if number.clicked() {
+                                                let modifiers = ui.input(|input| input.modifiers);
+                                                self.select_plot(plot_id, modifiers);
                                             }
-                                            if small_flat_button(ui, "↓").clicked() && i < plots_len - 1 {
-                                                self.pending_action = Some((i, PlotAction::MoveDown));
+                                            number.context_menu(|ui| {
+                                                for &new_kind in PlotKind::all() {
+                                                    let label = format!("後ろに{}を追加", new_kind.display_name());
+                                                    if ui.button(label).clicked() && plots_len < MAX_PLOTS {
+                                                        self.pending_action = Some((i, PlotAction::AddAfter(new_kind)));
+                                                        ui.close_menu();
+                                                    }
+                                                }
+                                                ui.separator();
+                                                for &new_kind in PlotKind::all() {
+                                                    let label = format!("{}に変更", new_kind.display_name());
+                                                    if ui.add_enabled(new_kind != kind, egui::Button::new(label)).clicked() {
+                                                        self.pending_action = Some((i, PlotAction::SetKind(new_kind)));
+                                                        ui.close_menu();
+                                                    }
+                                                }
+                                                ui.separator();
+                                                if ui.add_enabled(plots_len > 1, egui::Button::new("番号を指定して移動...")).clicked() {
+                                                    let ids = self.doc.block_indices(i).into_iter().map(|k| self.doc.plots[k].id).collect();
+                                                    self.move_dialog = Some((ids, i + 1));
+                                                    ui.close_menu();
+                                                }
+                                                let label = if excluded { "文書生成に含める" } else { "文書生成から除外" };
+                                                if ui.button(label).clicked() {
+                                                    self.pending_action = Some((i, PlotAction::SetExcluded(!excluded)));
+                                                    ui.close_menu();
+                                                }
+                                            });
+
+                                            if kind.is_heading() {
+                                                let collapsed = self.doc.plots[i].collapsed;
+                                                if small_flat_button(ui, if collapsed { "▶" } else { "▼" }).clicked() {
+                                                    self.pending_action = Some((i, PlotAction::ToggleCollapse));
+                                                }
+                                                ui.label(kind.display_name());
+
+                                                let heading_width = panel_width - 290.0 - indent;
+                                                ui.vertical(|ui| {
+                                                    let text_edit = egui::TextEdit::singleline(&mut self.doc.plots[i].text)
+                                                        .desired_width(heading_width)
+                                                        .font(egui::FontId::proportional(font_size + 4.0));
+                                                    if ui.add(text_edit).changed() {
+                                                        self.is_dirty = true;
+                                                    }
+                                                    let input = self.tag_inputs.entry(plot_id).or_default();
+                                                    if let Some(action) = tag_editor(ui, &self.doc.plots[i].tags, input) {
+                                                        self.pending_action = Some((i, action));
+                                                    }
+                                                });
+
+                                                if collapsed {
+                                                    ui.label(format!("({}件)", block_end - i - 1));
+                                                }
+                                            } else {
+                                                ui.vertical(|ui| {
+                                                    let previous = self.merge_neighbor(i, true);
+                                                    let next = self.merge_neighbor(i, false);
+                                                    let plot = &mut self.doc.plots[i];
+                                                    let mut changed = false;
+
+                                                    let title_edit = egui::TextEdit::singleline(&mut plot.title)
+                                                        .hint_text("タイトル")
+                                                        .desired_width(text_width - indent)
+                                                        .font(egui::FontId::proportional(font_size));
+                                                    changed |= ui.add(title_edit).changed();
+
+                                                    let synopsis_edit = egui::TextEdit::singleline(&mut plot.synopsis)
+                                                        .hint_text("あらすじ（一行）")
+                                                        .desired_width(text_width - indent);
+                                                    changed |= ui.add(synopsis_edit).changed();
+
+                                                    let input = self.tag_inputs.entry(plot_id).or_default();
+                                                    if let Some(action) = tag_editor(ui, &plot.tags, input) {
+                                                        self.pending_action = Some((i, action));
+                                                    }
+
+                                                    // Calculate rows based on content (minimum 10, expand as needed)
+                                                    let line_count = plot.text.lines().count().max(1);
+                                                    let display_rows = line_count.max(10);
+
+                                                    // Text area - expands with content
+                                                    let text_id = egui::Id::new(("plot_text", plot_id));
+                                                    let text_edit = egui::TextEdit::multiline(&mut plot.text)
+                                                        .id(text_id)
+                                                        .desired_width(text_width - indent)
+                                                        .desired_rows(display_rows)
+                                                        .font(egui::FontId::monospace(font_size));
+                                                    let text_response = ui.add(text_edit);
+                                                    changed |= text_response.changed();
+                                                    // 右クリックでカーソル位置での分割・前後のシーンとの結合
+                                                    text_response.context_menu(|ui| {
+                                                        if ui.button("カーソル位置で分割").clicked() {
+                                                            let cursor = egui::TextEdit::load_state(ui.ctx(), text_id)
+                                                                .and_then(|state| state.cursor.char_range())
+                                                                .map(|range| range.primary.index);
+                                                            if let Some(cursor) = cursor {
+                                                                self.pending_action = Some((i, PlotAction::SplitAt(cursor)));
+                                                            }
+                                                            ui.close_menu();
+                                                        }
+                                                        ui.separator();
+                                                        let merge_items = [(previous, "前のシーンと結合"), (next, "次のシーンと結合")];
+                                                        for (other, label) in merge_items {
+                                                            if ui.add_enabled(other.is_some(), egui::Button::new(label)).clicked() {
+                                                                if let Some(other) = other {
+                                                                    self.pending_action = Some((i, PlotAction::MergeWith(other)));
+                                                                }
+                                                                ui.close_menu();
+                                                            }
+                                                        }
+                                                    });
+
+                                                    // 作者用メモ（出力テキストには含まれない）
+                                                    let notes_title = if plot.notes.is_empty() { "メモ" } else { "メモ *" };
+                                                    egui::CollapsingHeader::new(notes_title)
+                                                        .id_salt(("plot_notes", plot_id))
+                                                        .show(ui, |ui| {
+                                                            let notes_edit = egui::TextEdit::multiline(&mut plot.notes)
+                                                                .desired_width(text_width - indent - 20.0)
+                                                                .desired_rows(3);
+                                                            changed |= ui.add(notes_edit).changed();
+                                                        });
+
+                                                    if markup::has_markup(&plot.text) {
+                                                        egui::CollapsingHeader::new("プレビュー")
+                                                            .id_salt(("plot_preview", plot_id))
+                                                            .show(ui, |ui| {
+                                                                ui.set_max_width(text_width - indent - 20.0);
+                                                                markup_preview(ui, &plot.text, font_size);
+                                                            });
+                                                    }
+
+                                                    if changed {
+                                                        self.is_dirty = true;
+                                                    }
+                                                });
                                             }
+
+                                            // Buttons (見出しは横並び、シーンは縦並び)
+                                            let layout = if kind.is_heading() {
+                                                egui::Layout::left_to_right(egui::Align::Center)
+                                            } else {
+                                                egui::Layout::top_down(egui::Align::Min)
+                                            };
+                                            ui.with_layout(layout, |ui| {
+                                                if small_flat_button(ui, "+").clicked() && plots_len < MAX_PLOTS {
+                                                    self.pending_action = Some((i, PlotAction::AddAfter(PlotKind::Scene)));
+                                                }
+                                                if small_flat_button(ui, "-").clicked() && plots_len > 1 {
+                                                    self.pending_action = Some((i, PlotAction::RequestDelete(plot_id)));
+                                                }
+                                                if small_flat_button(ui, "↑").clicked() && i > 0 {
+                                                    self.pending_action = Some((i, PlotAction::MoveUp));
+                                                }
+                                                if small_flat_button(ui, "↓").clicked() && !is_last_block {
+                                                    self.pending_action = Some((i, PlotAction::MoveDown));
+                                                }
+                                                let hover = if excluded {
+                                                    "文書生成から除外中（クリックで含める）"
+                                                } else {
+                                                    "文書生成に含める（クリックで除外）"
+                                                };
+                                                if small_flat_button(ui, if excluded { "×" } else { "○" }).on_hover_text(hover).clicked() {
+                                                    self.pending_action = Some((i, PlotAction::SetExcluded(!excluded)));
+                                                }
+                                            });
                                         });
-                                    });
-                                    ui.add_space(10.0);
-                                }
 
-                                ui.add_space(20.0);
-                                ui.label(format!("プロット数: {} / {}", self.plots.len(), MAX_PLOTS));
-                            });
+                                        if self.scroll_to_plot == Some(plot_id) {
+                                            ui.scroll_to_rect(row.response.rect, Some(egui::Align::TOP));
+                                            self.scroll_to_plot = None;
+                                        }
+                                        if self.selection.contains(&plot_id) {
+                                            ui.painter().rect_stroke(
+                                                row.response.rect.expand(3.0),
+                                                4.0,
+                                                egui::Stroke::new(1.5, egui::Color32::from_rgb(80, 140, 190)),
+                                            );
+                                        }
+
+                                        // ドラッグ中は、行の上半分なら前、下半分なら後ろ（折りたたんだ見出しは配下の後ろ）に移動先の線を引く
+                                        if let Some(drag) = egui::DragAndDrop::payload::<PlotDrag>(ui.ctx()) {
+                                            let rect = row.response.rect.expand2(egui::vec2(0.0, 5.0));
+                                            if let Some(pointer) = ui.ctx().pointer_interact_pos().filter(|p| rect.contains(*p)) {
+                                                let before = pointer.y < rect.center().y;
+                                                let collapsed = kind.is_heading() && self.doc.plots[i].collapsed;
+                                                let target = match (before, collapsed) {
+                                                    (true, _) => i,
+                                                    (false, true) => block_end,
+                                                    (false, false) => i + 1,
+                                                };
+                                                let y = if before { rect.top() } else { rect.bottom() };
+                                                ui.painter().hline(
+                                                    rect.x_range(),
+                                                    y,
+                                                    egui::Stroke::new(2.0, egui::Color32::from_rgb(80, 140, 190)),
+                                                );
+                                                if ui.input(|input| input.pointer.any_released()) {
+                                                    if let Some(index) = self.doc.plot_index(drag.0) {
+                                                        self.pending_action = Some((index, PlotAction::MoveBefore(target)));
+                                                    }
+                                                }
+                                            }
+                                        }
+                                        ui.add_space(10.0);
+
+                                        if kind.is_heading() && self.doc.plots[i].collapsed {
+                                            skip_until = block_end;
+                                        }
+                                    }
+
+                                    self.visible_plot_ids = visible_ids;
+
+                                    ui.add_space(20.0);
+                                    ui.label(format!("プロット数: {} / {}", self.doc.plots.len(), MAX_PLOTS));
+                                });
+                        }
                     });
                 });
 
@@ -1155,7 +3413,11 @@ impl eframe::App for StoryComposerApp {
                 // Right pane - Composed text (fixed 50%)
                 ui.allocate_ui(egui::vec2(panel_width, panel_height), |ui| {
                     ui.vertical(|ui| {
-                        ui.heading("出力テキスト");
+                        ui.horizontal(|ui| {
+                            ui.heading("出力テキスト");
+                            ui.add_space(10.0);
+                            ui.checkbox(&mut self.show_preview, "プレビュー");
+                        });
                         ui.add_space(10.0);
 
                         egui::ScrollArea::vertical()
@@ -1163,17 +3425,36 @@ impl eframe::App for StoryComposerApp {
                             .show(ui, |ui| {
                                 let text_width = panel_width - 40.0;
 
+                                if self.show_preview {
+                                    ui.set_max_width(text_width);
+                                    markup_preview(ui, &self.doc.composed_text, font_size);
+                                    self.scroll_to_composed = None;
+                                    return;
+                                }
+
                                 // Calculate rows based on content (minimum 60, expand as needed)
-                                let line_count = self.composed_text.lines().count().max(1);
+                                let line_count = self.doc.composed_text.lines().count().max(1);
                                 let display_rows = line_count.max(60);
 
-                                let text_edit = egui::TextEdit::multiline(&mut self.composed_text)
+                                let text_id = egui::Id::new("composed_text");
+                                let text_edit = egui::TextEdit::multiline(&mut self.doc.composed_text)
+                                    .id(text_id)
                                     .desired_width(text_width)
                                     .desired_rows(display_rows)
                                     .font(egui::FontId::monospace(font_size));
-                                if ui.add(text_edit).changed() {
+                                let mut output = text_edit.show(ui);
+                                if output.response.changed() {
                                     self.is_dirty = true;
                                 }
+
+                                // アウトラインで選んだプロットの区間の先頭へカーソルを置いてスクロールする
+                                if let Some(offset) = self.scroll_to_composed.take() {
+                                    let cursor = egui::text::CCursor::new(offset);
+                                    let rect = output.galley.pos_from_ccursor(cursor).translate(output.galley_pos.to_vec2());
+                                    ui.scroll_to_rect(rect, Some(egui::Align::TOP));
+                                    output.state.cursor.set_char_range(Some(egui::text::CCursorRange::one(cursor)));
+                                    output.state.store(ui.ctx(), text_id);
+                                }
                             });
                     });
                 });
@@ -1182,14 +3463,28 @@ impl eframe::App for StoryComposerApp {
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone)]
 enum PlotAction {
-    AddAfter,
+    AddAfter(PlotKind),
     RequestDelete(usize),
+    SetKind(PlotKind),
+    SetStatus(PlotStatus),
+    SetColor(PlotColor),
+    SetExcluded(bool),
+    AddTag(String),
+    RemoveTag(String),
+    ToggleCollapse,
     MoveUp,
     MoveDown,
+    MoveBefore(usize),
+    SplitAt(usize),
+    // 同じ幕・章の中の前後のシーン（プロットの番号）と結合する
+    MergeWith(usize),
 }
 
+// ドラッグ中のプロットのID
+struct PlotDrag(usize);
+
 fn load_icon() -> Option<egui::IconData> {
     let icon_bytes = include_bytes!("ico/128.ico");
     let img = image::load_from_memory(icon_bytes).ok()?.into_rgba8();
@@ -1220,7 +3515,15 @@ fn get_screen_size() -> (f32, f32) {
 }
 
 fn main() -> eframe::Result<()> {
+    // サブコマンド付きで起動された場合はウィンドウを開かずに処理する
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if cli::is_command(&args) {
+        std::process::exit(cli::run(&args));
+    }
+
     let settings = AppSettings::load();
+    let recovery_snapshot = Arc::new(Mutex::new(None));
+    install_recovery_hook(recovery_snapshot.clone());
 
     let (screen_w, screen_h) = get_screen_size();
     let window_w = screen_w * 0.7;
@@ -1232,7 +3535,7 @@ fn main() -> eframe::Result<()> {
         .with_title("StoryComposer2");
 
     if let Some(icon) = load_icon() {
-        viewport = viewport.with_icon(std::sync::Arc::new(icon));
+        viewport = viewport.with_icon(Arc::new(icon));
     }
 
     let options = eframe::NativeOptions {
@@ -1245,7 +3548,7 @@ fn main() -> eframe::Result<()> {
         options,
         Box::new(move |cc| {
             setup_font(&cc.egui_ctx, &settings.font_choice);
-            Ok(Box::new(StoryComposerApp::new(settings)))
+            Ok(Box::new(StoryComposerApp::new(settings, recovery_snapshot)))
         }),
     )
 }
@@ -0,0 +1,179 @@
+// 本文中のルビ（｜漢字《かんじ》）と傍点（［＃「…」に傍点］）の記法の解析
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Inline {
+    Text(String),
+    Ruby { base: String, ruby: String },
+    // 傍点を付ける文字列
+    Emphasis(String),
+}
+
+// ｜が無い場合にルビの親文字とみなす文字（漢字の連続）
+fn is_kanji(c: char) -> bool {
+    matches!(c, '\u{4E00}'..='\u{9FFF}' | '\u{3400}'..='\u{4DBF}' | '\u{F900}'..='\u{FAFF}' | '々' | '〆' | 'ヶ' | '〇')
+}
+
+fn push_text(inlines: &mut Vec<Inline>, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+    if let Some(Inline::Text(last)) = inlines.last_mut() {
+        last.push_str(text);
+    } else {
+        inlines.push(Inline::Text(text.to_owned()));
+    }
+}
+
+// 青空文庫の注記 ［＃「対象」に傍点］ から対象の文字列を取り出す（白ゴマ傍点・丸傍点なども傍点として扱う）
+fn emphasis_target(annotation: &str) -> Option<&str> {
+    let body = annotation.strip_prefix("＃「")?;
+    let (target, kind) = body.rsplit_once("」に")?;
+    (kind.ends_with("傍点") && !target.is_empty()).then_some(target)
+}
+
+// 注記の直前の文字列が対象と一致すれば、その部分を傍点にする
+fn apply_emphasis(inlines: &mut Vec<Inline>, target: &str) -> bool {
+    let Some(Inline::Text(last)) = inlines.last_mut() else {
+        return false;
+    };
+    let Some(prefix) = last.strip_suffix(target) else {
+        return false;
+    };
+    let prefix = prefix.to_owned();
+    inlines.pop();
+    push_text(inlines, &prefix);
+    inlines.push(Inline::Emphasis(target.to_owned()));
+    true
+}
+
+// ｜親文字《ルビ》・漢字《ルビ》・傍点の注記を認識する。対応する記号が無いものはそのまま文字として残す。
+pub fn parse_inline(line: &str) -> Vec<Inline> {
+    let mut inlines = Vec::new();
+    // 未確定の文字列と、その中で最後に｜が現れた位置
+    let mut pending = String::new();
+    let mut bar: Option<usize> = None;
+    let mut rest = line;
+
+    while let Some(c) = rest.chars().next() {
+        rest = &rest[c.len_utf8()..];
+        if c == '｜' || c == '|' {
+            bar = Some(pending.len());
+            pending.push(c);
+            continue;
+        }
+        if c == '［' {
+            let target = rest.find('］').and_then(|end| Some((end, emphasis_target(&rest[..end])?)));
+            if let Some((end, target)) = target {
+                push_text(&mut inlines, &pending);
+                pending.clear();
+                bar = None;
+                if apply_emphasis(&mut inlines, target) {
+                    rest = &rest[end + '］'.len_utf8()..];
+                    continue;
+                }
+            }
+        }
+        let close = if c == '《' { rest.find('》') } else { None };
+        let Some(close) = close else {
+            pending.push(c);
+            continue;
+        };
+
+        let (text_end, base_start) = match bar {
+            Some(start) => (start, start + pending[start..].chars().next().map_or(0, char::len_utf8)),
+            None => {
+                let base_len: usize = pending.chars().rev().take_while(|&c| is_kanji(c)).map(char::len_utf8).sum();
+                (pending.len() - base_len, pending.len() - base_len)
+            }
+        };
+        let ruby = &rest[..close];
+        if base_start >= pending.len() || ruby.is_empty() {
+            pending.push(c);
+            continue;
+        }
+        push_text(&mut inlines, &pending[..text_end]);
+        inlines.push(Inline::Ruby {
+            base: pending[base_start..].to_owned(),
+            ruby: ruby.to_owned(),
+        });
+        pending.clear();
+        bar = None;
+        rest = &rest[close + '》'.len_utf8()..];
+    }
+    push_text(&mut inlines, &pending);
+    inlines
+}
+
+// ルビと傍点の記法を取り除き、親文字だけを残す
+pub fn plain_text(line: &str) -> String {
+    parse_inline(line)
+        .into_iter()
+        .map(|inline| match inline {
+            Inline::Text(text) | Inline::Emphasis(text) => text,
+            Inline::Ruby { base, .. } => base,
+        })
+        .collect()
+}
+
+pub fn has_markup(text: &str) -> bool {
+    text.lines()
+        .any(|line| parse_inline(line).iter().any(|inline| !matches!(inline, Inline::Text(_))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> Inline {
+        Inline::Text(s.to_owned())
+    }
+
+    fn ruby(base: &str, ruby: &str) -> Inline {
+        Inline::Ruby {
+            base: base.to_owned(),
+            ruby: ruby.to_owned(),
+        }
+    }
+
+    #[test]
+    fn ruby_with_bar_and_kanji_run() {
+        assert_eq!(parse_inline("｜薔薇色《ばらいろ》の"), [ruby("薔薇色", "ばらいろ"), text("の")]);
+        assert_eq!(parse_inline("a|b《c》"), [text("a"), ruby("b", "c")]);
+        // ｜が無い場合は直前の漢字の連続だけを親文字にする
+        assert_eq!(parse_inline("今日は東京《とうきょう》"), [text("今日は"), ruby("東京", "とうきょう")]);
+        assert_eq!(parse_inline("一々《いちいち》"), [ruby("一々", "いちいち")]);
+    }
+
+    #[test]
+    fn unmatched_ruby_is_left_as_text() {
+        assert_eq!(parse_inline("《》"), [text("《》")]);
+        assert_eq!(parse_inline("｜《a》"), [text("｜《a》")]);
+        assert_eq!(parse_inline("漢字《》"), [text("漢字《》")]);
+        assert_eq!(parse_inline("かな《かな》"), [text("かな《かな》")]);
+        assert_eq!(parse_inline("漢字《閉じない"), [text("漢字《閉じない")]);
+    }
+
+    #[test]
+    fn emphasis_annotation() {
+        assert_eq!(
+            parse_inline("これは重要［＃「重要」に傍点］です"),
+            [text("これは"), Inline::Emphasis("重要".to_owned()), text("です")]
+        );
+        assert_eq!(parse_inline("白［＃「白」に白ゴマ傍点］"), [Inline::Emphasis("白".to_owned())]);
+    }
+
+    #[test]
+    fn invalid_emphasis_annotation_is_left_as_text() {
+        assert_eq!(parse_inline("［＃「」に傍点］"), [text("［＃「」に傍点］")]);
+        assert_eq!(parse_inline("重要［＃「大事」に傍点］"), [text("重要［＃「大事」に傍点］")]);
+        assert_eq!(parse_inline("重要［＃「重要」に傍線］"), [text("重要［＃「重要」に傍線］")]);
+        assert_eq!(parse_inline("［＃「閉じない"), [text("［＃「閉じない")]);
+    }
+
+    #[test]
+    fn plain_text_and_has_markup() {
+        assert_eq!(plain_text("｜漢字《かんじ》と重要［＃「重要」に傍点］"), "漢字と重要");
+        assert!(has_markup("一行目\n東京《とうきょう》"));
+        assert!(!has_markup("《》と［＃「」に傍点］"));
+    }
+}
@@ -0,0 +1,183 @@
+// 出力テキストの手直しを残したまま、変更のあったプロットの区間だけを作り直す
+
+use crate::decompose::{self, same_text};
+use crate::document::{ComposeRecord, Document};
+use std::collections::HashMap;
+
+// プロットと出力テキストの両方が前回の文書生成から変更された区間
+pub struct Conflict {
+    pub id: usize,
+    // 出力テキストで手直しされた内容
+    pub edited: String,
+    // 今のプロットから作った内容
+    pub generated: String,
+}
+
+pub struct Recompose {
+    // 今のプロットから作った記録と、それに対応する出力テキストの区間
+    record: ComposeRecord,
+    sections: Vec<String>,
+    pub conflicts: Vec<Conflict>,
+    // 手直しがあったが、プロットが削除された（または文書生成から除外された）ため出力から除かれる区間
+    pub removed_edits: Vec<String>,
+}
+
+impl Recompose {
+    // 衝突した区間をプロットの内容で置き換える（既定では手直しを残す）
+    pub fn use_generated(&mut self, id: usize) {
+        let Some(conflict) = self.conflicts.iter().find(|c| c.id == id) else {
+            return;
+        };
+        if let Some(i) = self.record.sections.iter().position(|s| s.id == id) {
+            self.sections[i] = conflict.generated.clone();
+        }
+    }
+
+    pub fn text(&self) -> String {
+        self.record.join(self.sections.iter().map(String::as_str))
+    }
+
+    pub fn record(&self) -> &ComposeRecord {
+        &self.record
+    }
+}
+
+// 前回の記録と比べて、プロット（または書式）だけが変わった区間は作り直し、出力だけが変わった区間は手直しを残す。
+// 出力テキストの区間を対応付けられない場合（区切り線や見出しを書き換えた場合など）はエラーを返す。
+pub fn recompose(doc: &Document) -> Result<Recompose, String> {
+    let located: HashMap<usize, String> = decompose::locate_sections(&doc.composed, &doc.composed_text)?
+        .into_iter()
+        .collect();
+    let previous: HashMap<usize, _> = doc.composed.sections.iter().map(|s| (s.id, s)).collect();
+    let record = doc.compose_record();
+
+    let mut sections = Vec::with_capacity(record.sections.len());
+    let mut conflicts = Vec::new();
+    for section in &record.sections {
+        let generated = section.generated.clone();
+        let text = match (previous.get(&section.id), located.get(&section.id)) {
+            (Some(prev), Some(current)) => {
+                let plot_changed = prev.generated != section.generated || prev.kind != section.kind;
+                let output_edited = !same_text(&prev.generated, current);
+                if !plot_changed {
+                    current.clone()
+                } else if !output_edited {
+                    generated
+                } else {
+                    conflicts.push(Conflict {
+                        id: section.id,
+                        edited: current.clone(),
+                        generated,
+                    });
+                    current.clone()
+                }
+            }
+            // 前回の文書生成の後に追加されたプロット
+            _ => generated,
+        };
+        sections.push(text);
+    }
+
+    let removed_edits = doc
+        .composed
+        .sections
+        .iter()
+        .filter(|s| !record.sections.iter().any(|section| section.id == s.id))
+        .filter_map(|s| {
+            let current = located.get(&s.id)?;
+            (!same_text(&s.generated, current)).then(|| current.clone())
+        })
+        .collect();
+
+    Ok(Recompose {
+        record,
+        sections,
+        conflicts,
+        removed_edits,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::{PlotFragment, PlotKind};
+
+    fn composed_doc() -> Document {
+        let plots = ["一", "二", "三"]
+            .iter()
+            .enumerate()
+            .map(|(id, text)| PlotFragment {
+                text: text.to_string(),
+                ..PlotFragment::new(id, PlotKind::Scene)
+            })
+            .collect();
+        let mut doc = Document::from_parts(plots, String::new());
+        doc.compose();
+        doc
+    }
+
+    #[test]
+    fn keeps_edits_and_regenerates_changed_plots() {
+        let mut doc = composed_doc();
+        doc.composed_text = doc.composed_text.replace("一", "一（手直し）");
+        doc.plots[1].text = "二（変更）".to_owned();
+        let result = recompose(&doc).unwrap();
+        assert!(result.conflicts.is_empty());
+        assert_eq!(result.text(), "一（手直し）\n\n---\n\n二（変更）\n\n---\n\n三");
+    }
+
+    #[test]
+    fn reports_conflicts_and_keeps_edit_by_default() {
+        let mut doc = composed_doc();
+        doc.composed_text = doc.composed_text.replace("二", "二（手直し）");
+        doc.plots[1].text = "二（変更）".to_owned();
+        let mut result = recompose(&doc).unwrap();
+        assert_eq!(result.conflicts.len(), 1);
+        let conflict = &result.conflicts[0];
+        assert_eq!((conflict.id, conflict.edited.as_str(), conflict.generated.as_str()), (1, "二（手直し）", "二（変更）"));
+        assert!(result.text().contains("二（手直し）"));
+        result.use_generated(1);
+        assert_eq!(result.text(), "一\n\n---\n\n二（変更）\n\n---\n\n三");
+    }
+
+    #[test]
+    fn reports_edits_of_removed_plots() {
+        let mut doc = composed_doc();
+        doc.composed_text = doc.composed_text.replace("三", "三（手直し）");
+        doc.plots[2].excluded = true;
+        doc.plots[0].text = "一（変更）".to_owned();
+        let result = recompose(&doc).unwrap();
+        assert_eq!(result.removed_edits, ["三（手直し）"]);
+        assert_eq!(result.text(), "一（変更）\n\n---\n\n二");
+        assert_eq!(result.record().sections.len(), 2);
+    }
+
+    #[test]
+    fn keeps_separator_inside_scene() {
+        let mut doc = composed_doc();
+        doc.plots[0].text = "一\n\n---\n\n一の続き".to_owned();
+        doc.compose();
+        doc.composed_text = doc.composed_text.replace("三", "三（手直し）");
+        doc.plots[1].text = "二（変更）".to_owned();
+        let result = recompose(&doc).unwrap();
+        assert!(result.conflicts.is_empty());
+        assert!(result.removed_edits.is_empty());
+        assert_eq!(result.text(), "一\n\n---\n\n一の続き\n\n---\n\n二（変更）\n\n---\n\n三（手直し）");
+    }
+
+    #[test]
+    fn adds_new_plots() {
+        let mut doc = composed_doc();
+        doc.composed_text = doc.composed_text.replace("一", "一（手直し）");
+        let id = doc.allocate_id();
+        doc.plots.insert(
+            1,
+            PlotFragment {
+                text: "追加".to_owned(),
+                ..PlotFragment::new(id, PlotKind::Scene)
+            },
+        );
+        let result = recompose(&doc).unwrap();
+        assert_eq!(result.text(), "一（手直し）\n\n---\n\n追加\n\n---\n\n二\n\n---\n\n三");
+    }
+}
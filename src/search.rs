@@ -0,0 +1,86 @@
+// 文字列の検索と置換
+
+use crate::document::Document;
+
+#[derive(Clone, PartialEq)]
+pub enum SearchLocation {
+    Plot,
+    ComposedText,
+}
+
+#[derive(Clone)]
+pub struct SearchResult {
+    pub location: SearchLocation,
+    pub plot_index: Option<usize>,
+    pub start: usize,
+    pub end: usize,
+}
+
+// 一致した位置（バイト単位）を重なりも含めてすべて返す
+pub fn find_all(text: &str, query: &str) -> Vec<usize> {
+    let mut positions = Vec::new();
+    if query.is_empty() {
+        return positions;
+    }
+    let mut start = 0;
+    while let Some(pos) = text[start..].find(query) {
+        let actual_start = start + pos;
+        positions.push(actual_start);
+        // 次の文字の先頭から探す（マルチバイト文字の途中で切らない）
+        start = actual_start + text[actual_start..].chars().next().map_or(1, char::len_utf8);
+    }
+    positions
+}
+
+impl Document {
+    pub fn search(&self, query: &str) -> Vec<SearchResult> {
+        let mut results = Vec::new();
+
+        // Search in plots
+        for (i, plot) in self.plots.iter().enumerate() {
+            for start in find_all(&plot.text, query) {
+                results.push(SearchResult {
+                    location: SearchLocation::Plot,
+                    plot_index: Some(i),
+                    start,
+                    end: start + query.len(),
+                });
+            }
+        }
+
+        // Search in composed text
+        for start in find_all(&self.composed_text, query) {
+            results.push(SearchResult {
+                location: SearchLocation::ComposedText,
+                plot_index: None,
+                start,
+                end: start + query.len(),
+            });
+        }
+
+        results
+    }
+
+    pub fn replace_all(&mut self, query: &str, replacement: &str) -> bool {
+        if query.is_empty() {
+            return false;
+        }
+        let mut changed = false;
+
+        // Replace in plots
+        for plot in &mut self.plots {
+            if plot.text.contains(query) {
+                plot.text = plot.text.replace(query, replacement);
+                changed = true;
+            }
+        }
+
+        // Replace in composed text
+        if self.composed_text.contains(query) {
+            self.composed_text = self.composed_text.replace(query, replacement);
+            changed = true;
+        }
+
+        changed
+    }
+}
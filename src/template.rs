@@ -0,0 +1,222 @@
+// 文書生成の書式。プロットごとの区間に {{index}} などの差し込み項目を当てはめて出力テキストを作る
+
+use crate::document::{section_text, ComposeRecord, ComposedSection, DocumentMeta, PlotFragment, PlotKind};
+use serde::{Deserialize, Serialize};
+
+const TEXT_PLACEHOLDER: &str = "{{text}}";
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ComposeTemplate {
+    // 出力テキストの先頭と末尾（{{title}} は文書のタイトル、{{author}} は著者名）
+    #[serde(default)]
+    pub header: String,
+    #[serde(default)]
+    pub footer: String,
+    // 幕・章の見出し
+    pub heading: String,
+    // シーン（{{index}} はシーンの通し番号、{{title}} はプロットのタイトル、{{chapter}} は属する章の見出し）
+    pub scene: String,
+    // シーン同士の間に入れる行（空なら空行だけで区切る）
+    #[serde(default)]
+    pub separator: String,
+}
+
+impl Default for ComposeTemplate {
+    fn default() -> Self {
+        Self {
+            header: String::new(),
+            footer: String::new(),
+            heading: TEXT_PLACEHOLDER.to_owned(),
+            scene: TEXT_PLACEHOLDER.to_owned(),
+            separator: "---".to_owned(),
+        }
+    }
+}
+
+impl ComposeTemplate {
+    pub fn builtins() -> Vec<(&'static str, ComposeTemplate)> {
+        let plain = ComposeTemplate::default();
+        vec![
+            ("標準", plain.clone()),
+            (
+                "シーン番号付き",
+                ComposeTemplate {
+                    scene: "【{{index}}】{{title}}\n\n{{text}}".to_owned(),
+                    ..plain.clone()
+                },
+            ),
+            (
+                "脚本",
+                ComposeTemplate {
+                    header: "{{title}}".to_owned(),
+                    heading: "■{{text}}".to_owned(),
+                    scene: "○{{title}}\n{{text}}".to_owned(),
+                    separator: String::new(),
+                    ..plain.clone()
+                },
+            ),
+            (
+                "区切りなし",
+                ComposeTemplate {
+                    separator: String::new(),
+                    ..plain
+                },
+            ),
+        ]
+    }
+
+    // 既定の書式のいずれかと同じならその名前
+    pub fn builtin_name(&self) -> Option<&'static str> {
+        Self::builtins().into_iter().find(|(_, t)| t == self).map(|(name, _)| name)
+    }
+
+    // 見出し・シーンの書式で {{text}} を使えるのは1か所だけ（本文の前後の部分で区間を対応付けるため）
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, template) in [("見出し", &self.heading), ("シーン", &self.scene)] {
+            if template.matches(TEXT_PLACEHOLDER).count() > 1 {
+                return Err(format!("{}の書式で {} を使えるのは1か所だけです", name, TEXT_PLACEHOLDER));
+            }
+        }
+        Ok(())
+    }
+
+    // シーン同士の間の文字列
+    pub fn scene_joiner(&self) -> String {
+        if self.separator.trim().is_empty() {
+            "\n\n".to_owned()
+        } else {
+            format!("\n\n{}\n\n", self.separator.trim())
+        }
+    }
+}
+
+// {{name}} を値に置き換える。知らない項目はそのまま残す
+fn fill(template: &str, values: &[(&str, &str)]) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let value = after
+            .find("}}")
+            .and_then(|end| values.iter().find(|(name, _)| *name == &after[..end]).map(|v| (end, v.1)));
+        match value {
+            Some((end, value)) => {
+                result.push_str(value);
+                rest = &after[end + 2..];
+            }
+            None => {
+                result.push_str("{{");
+                rest = after;
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+// 本文の前後の部分と、本文を当てはめた区間を作る
+fn render_section(template: &str, values: &[(&str, &str)], text: &str) -> (String, Option<(String, String)>) {
+    match template.split_once(TEXT_PLACEHOLDER) {
+        Some((lead, trail)) => {
+            let (lead, trail) = (fill(lead, values), fill(trail, values));
+            (format!("{}{}{}", lead, text, trail), Some((lead, trail)))
+        }
+        None => (fill(template, values), None),
+    }
+}
+
+// 書式をプロットの並びに当てはめ、区間ごとの内容を記録として返す
+pub fn compose_record<'a>(
+    plots: impl IntoIterator<Item = &'a PlotFragment>,
+    template: &ComposeTemplate,
+    meta: &DocumentMeta,
+) -> ComposeRecord {
+    let mut sections = Vec::new();
+    let mut index = 0;
+    let mut chapter = "";
+    for plot in plots {
+        let text = section_text(plot.kind, &plot.text);
+        // 幕が変われば、次の章が来るまでは属する章は無い
+        match plot.kind {
+            PlotKind::Act => chapter = "",
+            PlotKind::Chapter => chapter = text,
+            PlotKind::Scene => {}
+        }
+        let section_template = if plot.kind.is_heading() {
+            &template.heading
+        } else {
+            index += 1;
+            &template.scene
+        };
+        let index_text = index.to_string();
+        let values = [("index", index_text.as_str()), ("title", plot.title.trim()), ("chapter", chapter)];
+        let (generated, frame) = render_section(section_template, &values, text);
+        sections.push(ComposedSection {
+            id: plot.id,
+            kind: plot.kind,
+            generated,
+            frame,
+        });
+    }
+
+    let values = [("title", meta.title.trim()), ("author", meta.author.trim())];
+    ComposeRecord {
+        header: fill(&template.header, &values),
+        footer: fill(&template.footer, &values),
+        separator: template.scene_joiner(),
+        sections,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plots(plots: &[(PlotKind, &str)]) -> Vec<PlotFragment> {
+        plots
+            .iter()
+            .enumerate()
+            .map(|(id, &(kind, text))| PlotFragment {
+                text: text.to_owned(),
+                ..PlotFragment::new(id, kind)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn text_placeholder_is_allowed_once() {
+        for (_, template) in ComposeTemplate::builtins() {
+            assert!(template.validate().is_ok());
+        }
+        let template = ComposeTemplate {
+            scene: "{{text}}\n{{text}}".to_owned(),
+            ..Default::default()
+        };
+        assert!(template.validate().is_err());
+        // ヘッダー・フッターでは {{text}} は差し込み項目ではない
+        let template = ComposeTemplate {
+            header: "{{text}}{{text}}".to_owned(),
+            ..Default::default()
+        };
+        assert!(template.validate().is_ok());
+    }
+
+    #[test]
+    fn chapter_is_reset_by_act() {
+        let plots = plots(&[
+            (PlotKind::Act, "第一幕"),
+            (PlotKind::Chapter, "第一章"),
+            (PlotKind::Scene, "本文A"),
+            (PlotKind::Act, "第二幕"),
+            (PlotKind::Scene, "本文B"),
+        ]);
+        let template = ComposeTemplate {
+            scene: "[{{chapter}}]{{text}}".to_owned(),
+            ..Default::default()
+        };
+        let record = compose_record(&plots, &template, &DocumentMeta::default());
+        let generated: Vec<&str> = record.sections.iter().map(|s| s.generated.as_str()).collect();
+        assert_eq!(generated, ["第一幕", "第一章", "[第一章]本文A", "第二幕", "[]本文B"]);
+    }
+}